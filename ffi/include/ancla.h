@@ -0,0 +1,132 @@
+#ifndef ANCLA_H
+#define ANCLA_H
+
+/* Generated by cbindgen, do not edit it manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call is succeeded.
+ */
+#define ANCLA_OK 0
+
+/**
+ * The bucket or key doesn't exist.
+ */
+#define ANCLA_NOT_FOUND 1
+
+/**
+ * The arguments are invalid or the database is corrupted.
+ */
+#define ANCLA_ERROR -1
+
+/**
+ * The opaque handle of an opened database, it's not thread safe.
+ */
+typedef struct AnclaDb AnclaDb;
+
+/**
+ * Called for each bucket by `ancla_iter_buckets`, the name is only valid during
+ * the call. Return non-zero to stop the iteration.
+ */
+typedef int (*AnclaBucketCallback)(const uint8_t *name,
+                                   size_t name_len,
+                                   bool is_inline,
+                                   uint64_t page_id,
+                                   void *ctx);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the database file at `path`, NULL is returned if it can't be opened or
+ * it's not a valid bolt database. The handle must be closed by `ancla_close`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string.
+ */
+struct AnclaDb *ancla_open(const char *path);
+
+/**
+ * Closes the database, it's a no-op if `db` is NULL.
+ *
+ * # Safety
+ *
+ * `db` must be returned by `ancla_open` and not closed yet.
+ */
+void ancla_close(struct AnclaDb *db);
+
+/**
+ * Returns the meta information of the database as a JSON object, the string must
+ * be freed by `ancla_string_free`. NULL is returned on error.
+ *
+ * # Safety
+ *
+ * `db` must be an opened database handle.
+ */
+char *ancla_info_json(const struct AnclaDb *db);
+
+/**
+ * Frees the string returned by this library, it's a no-op if `s` is NULL.
+ *
+ * # Safety
+ *
+ * `s` must be returned by this library and not freed yet.
+ */
+void ancla_string_free(char *s);
+
+/**
+ * Calls `cb` for each child bucket of the bucket at `path`, the names in the path
+ * are joined by `/`. The top level buckets are iterated if `path` is NULL or empty.
+ *
+ * Returns `ANCLA_NOT_FOUND` if the bucket doesn't exist.
+ *
+ * # Safety
+ *
+ * `db` must be an opened database handle, `path` must be NULL or a NUL-terminated
+ * string.
+ */
+int ancla_iter_buckets(const struct AnclaDb *db,
+                       const char *path,
+                       AnclaBucketCallback cb,
+                       void *ctx);
+
+/**
+ * Looks up `key` in the bucket at `path`, the names in the path are joined by `/`.
+ * The value is returned by `value` and `value_len`, and must be freed by
+ * `ancla_bytes_free`.
+ *
+ * Returns `ANCLA_NOT_FOUND` if the bucket or key doesn't exist.
+ *
+ * # Safety
+ *
+ * `db` must be an opened database handle, `path` must be a NUL-terminated string,
+ * `key` must point to `key_len` bytes, `value` and `value_len` must be writable.
+ */
+int ancla_get(const struct AnclaDb *db,
+              const char *path,
+              const uint8_t *key,
+              size_t key_len,
+              uint8_t **value,
+              size_t *value_len);
+
+/**
+ * Frees the value returned by `ancla_get`, it's a no-op if `value` is NULL.
+ *
+ * # Safety
+ *
+ * `value` and `value_len` must be returned by `ancla_get` and not freed yet.
+ */
+void ancla_bytes_free(uint8_t *value, size_t value_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ANCLA_H */
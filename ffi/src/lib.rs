@@ -0,0 +1,232 @@
+//! The C ABI of the ancla bolt database reader, the header is generated by
+//! cbindgen into `include/ancla.h`.
+//!
+//! The doc comments are copied into the header, so they are written for C users.
+
+use ancla::{AnclaOptions, Bucket, DB};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    rc::Rc,
+    slice,
+};
+
+/// The call is succeeded.
+pub const ANCLA_OK: c_int = 0;
+/// The bucket or key doesn't exist.
+pub const ANCLA_NOT_FOUND: c_int = 1;
+/// The arguments are invalid or the database is corrupted.
+pub const ANCLA_ERROR: c_int = -1;
+
+/// The opaque handle of an opened database, it's not thread safe.
+pub struct AnclaDb {
+    db: Rc<RefCell<DB>>,
+}
+
+/// Called for each bucket by `ancla_iter_buckets`, the name is only valid during
+/// the call. Return non-zero to stop the iteration.
+pub type AnclaBucketCallback = Option<
+    extern "C" fn(
+        name: *const u8,
+        name_len: usize,
+        is_inline: bool,
+        page_id: u64,
+        ctx: *mut c_void,
+    ) -> c_int,
+>;
+
+// The reader panics on corrupted databases, which must not unwind across the
+// C ABI, so every call catches it and reports it as an error.
+fn guard<T>(default: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+// bucket_path splits the `/` joined bucket path, NULL is the same as the empty path.
+unsafe fn bucket_path<'a>(path: *const c_char) -> Vec<&'a [u8]> {
+    if path.is_null() {
+        return Vec::new();
+    }
+
+    let path = CStr::from_ptr(path).to_bytes();
+    if path.is_empty() {
+        return Vec::new();
+    }
+    path.split(|&c| c == b'/').collect()
+}
+
+/// Opens the database file at `path`, NULL is returned if it can't be opened or
+/// it's not a valid bolt database. The handle must be closed by `ancla_close`.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_open(path: *const c_char) -> *mut AnclaDb {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        return ptr::null_mut();
+    };
+
+    let options = AnclaOptions::builder().db_path(path.to_string()).build();
+    guard(ptr::null_mut(), || {
+        let db = DB::build(options);
+        // Read the meta pages now, so an invalid database is reported by open.
+        DB::info(db.clone());
+        Box::into_raw(Box::new(AnclaDb { db }))
+    })
+}
+
+/// Closes the database, it's a no-op if `db` is NULL.
+///
+/// # Safety
+///
+/// `db` must be returned by `ancla_open` and not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_close(db: *mut AnclaDb) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Returns the meta information of the database as a JSON object, the string must
+/// be freed by `ancla_string_free`. NULL is returned on error.
+///
+/// # Safety
+///
+/// `db` must be an opened database handle.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_info_json(db: *const AnclaDb) -> *mut c_char {
+    let Some(db) = db.as_ref() else {
+        return ptr::null_mut();
+    };
+
+    guard(ptr::null_mut(), || {
+        serde_json::to_string(&DB::info(db.db.clone()))
+            .ok()
+            .and_then(|info| CString::new(info).ok())
+            .map_or(ptr::null_mut(), CString::into_raw)
+    })
+}
+
+/// Frees the string returned by this library, it's a no-op if `s` is NULL.
+///
+/// # Safety
+///
+/// `s` must be returned by this library and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Calls `cb` for each child bucket of the bucket at `path`, the names in the path
+/// are joined by `/`. The top level buckets are iterated if `path` is NULL or empty.
+///
+/// Returns `ANCLA_NOT_FOUND` if the bucket doesn't exist.
+///
+/// # Safety
+///
+/// `db` must be an opened database handle, `path` must be NULL or a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_iter_buckets(
+    db: *const AnclaDb,
+    path: *const c_char,
+    cb: AnclaBucketCallback,
+    ctx: *mut c_void,
+) -> c_int {
+    let (Some(db), Some(cb)) = (db.as_ref(), cb) else {
+        return ANCLA_ERROR;
+    };
+    let path = bucket_path(path);
+
+    guard(ANCLA_ERROR, || {
+        let buckets: Box<dyn Iterator<Item = Bucket>> = if path.is_empty() {
+            Box::new(DB::iter_buckets(db.db.clone()))
+        } else {
+            match DB::get_bucket(db.db.clone(), &path) {
+                Some(bucket) => Box::new(bucket.iter_buckets()),
+                None => return ANCLA_NOT_FOUND,
+            }
+        };
+
+        for bucket in buckets {
+            let name = &bucket.name;
+            if cb(
+                name.as_ptr(),
+                name.len(),
+                bucket.is_inline,
+                bucket.page_id,
+                ctx,
+            ) != 0
+            {
+                break;
+            }
+        }
+        ANCLA_OK
+    })
+}
+
+/// Looks up `key` in the bucket at `path`, the names in the path are joined by `/`.
+/// The value is returned by `value` and `value_len`, and must be freed by
+/// `ancla_bytes_free`.
+///
+/// Returns `ANCLA_NOT_FOUND` if the bucket or key doesn't exist.
+///
+/// # Safety
+///
+/// `db` must be an opened database handle, `path` must be a NUL-terminated string,
+/// `key` must point to `key_len` bytes, `value` and `value_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_get(
+    db: *const AnclaDb,
+    path: *const c_char,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+) -> c_int {
+    let Some(db) = db.as_ref() else {
+        return ANCLA_ERROR;
+    };
+    if value.is_null() || value_len.is_null() || (key.is_null() && key_len != 0) {
+        return ANCLA_ERROR;
+    }
+    let path = bucket_path(path);
+    let key = if key_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(key, key_len)
+    };
+
+    guard(ANCLA_ERROR, || {
+        let Some(data) = DB::get_bucket(db.db.clone(), &path).and_then(|bucket| bucket.get(key))
+        else {
+            return ANCLA_NOT_FOUND;
+        };
+
+        let data = data.into_vec().into_boxed_slice();
+        *value_len = data.len();
+        *value = Box::into_raw(data) as *mut u8;
+        ANCLA_OK
+    })
+}
+
+/// Frees the value returned by `ancla_get`, it's a no-op if `value` is NULL.
+///
+/// # Safety
+///
+/// `value` and `value_len` must be returned by `ancla_get` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ancla_bytes_free(value: *mut u8, value_len: usize) {
+    if !value.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            value, value_len,
+        )));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    AnclaOptions, Bytes, DatabaseError, DbItem, Info, MemorySource, PageInfo, ReadOptions,
+};
+use std::{cell::RefCell, io, path::Path, rc::Rc, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+// The number of items buffered by the streams before the producer waits.
+const STREAM_BUFFER: usize = 64;
+
+// DB is the async version of crate::DB for the async servers. The file is read by
+// tokio::fs when it's opened, then the pages are decoded from memory, so the calls
+// never block the runtime on file IO. It's cheap to clone and can be shared between
+// tasks, unlike crate::DB which is shared by Rc.
+#[derive(Clone)]
+pub struct DB {
+    data: Arc<Vec<u8>>,
+}
+
+// Item is the owned version of crate::DbItem, which can be sent between tasks.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Item {
+    // the path of the bucket, from the top level bucket.
+    Bucket(Vec<Vec<u8>>),
+    KeyValue {
+        bucket: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        value: Bytes,
+    },
+}
+
+impl From<DbItem> for Item {
+    fn from(item: DbItem) -> Self {
+        match item {
+            DbItem::Bucket(bucket) => Item::Bucket(bucket.path().to_vec()),
+            DbItem::KeyValue { bucket, key, value } => Item::KeyValue { bucket, key, value },
+        }
+    }
+}
+
+impl DB {
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<DB> {
+        let data = tokio::fs::read(path).await?;
+        Ok(DB::from_bytes(data))
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> DB {
+        DB {
+            data: Arc::new(data),
+        }
+    }
+
+    // db opens the sync database on the shared data, the data isn't copied.
+    fn db(&self) -> Rc<RefCell<crate::DB>> {
+        crate::DB::open_from_source(
+            AnclaOptions::builder().build(),
+            MemorySource::shared(Arc::clone(&self.data)),
+        )
+    }
+
+    pub async fn info(&self) -> Result<Info, DatabaseError> {
+        crate::DB::try_info(self.db())
+    }
+
+    // get returns the value of the key in the bucket, the bucket is specified by
+    // it's path from the top level bucket.
+    pub async fn get(&self, path: &[&[u8]], key: &[u8]) -> Option<Bytes> {
+        crate::DB::get_bucket(self.db(), path)?.get(key)
+    }
+
+    // iter_items streams the items selected by the options, see
+    // crate::DB::iter_items_with. It must be called in the tokio runtime.
+    pub fn iter_items(&self, options: ReadOptions) -> ReceiverStream<Item> {
+        self.stream(move |db| crate::DB::iter_items_with(db, options).map(Item::from))
+    }
+
+    // iter_pages streams the pages selected by the options, see
+    // crate::DB::iter_pages_with. It must be called in the tokio runtime.
+    pub fn iter_pages(&self, options: ReadOptions) -> ReceiverStream<PageInfo> {
+        self.stream(move |db| crate::DB::iter_pages_with(db, options))
+    }
+
+    // stream runs the sync iterator in a blocking task, since crate::DB can't be
+    // sent between the workers. The task stops once the stream is dropped.
+    fn stream<T, I>(
+        &self,
+        iter: impl FnOnce(Rc<RefCell<crate::DB>>) -> I + Send + 'static,
+    ) -> ReceiverStream<T>
+    where
+        T: Send + 'static,
+        I: Iterator<Item = T>,
+    {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || {
+            for item in iter(db.db()) {
+                if sender.blocking_send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        ReceiverStream::new(receiver)
+    }
+}
@@ -1,9 +1,19 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
 use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
 use std::rc::Rc;
-use std::result::Result;
+use std::time::Instant;
+use tracing::{debug, info_span, warn};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -11,15 +21,104 @@ struct Command {
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
 
+    /// The page size which the pages are read with, it's detected from the meta if
+    /// not set.
     #[arg(short, long)]
     page_size: Option<u32>,
 
+    /// Read the pages with `--page-size` even if it mismatches the meta's page size.
+    #[arg(long, default_value_t = false, requires = "page_size")]
+    force_page_size: bool,
+
+    /// The byte order which the database is written in, it's the native byte order
+    /// of this machine if not set.
     #[arg(short, long)]
     endian: Option<Endian>,
 
+    /// The format of the logs which are written to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// When to color the severities of the findings, the errors and the logs. The
+    /// auto colors the terminals unless NO_COLOR is set.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// The same as `--color never`.
+    #[arg(long, default_value_t = false, conflicts_with = "color")]
+    no_color: bool,
+
+    /// Dump the IO metrics in the prometheus text format to stderr when the
+    /// command finishes.
+    #[arg(long, default_value_t = false)]
+    metrics_dump: bool,
+
+    /// Print the pages and bytes read, the page cache hits and misses and the wall
+    /// time of the command to stderr when it finishes.
+    #[arg(long, default_value_t = false)]
+    stats: bool,
+
+    /// The format of the output, csv is escaped properly so it can be processed by
+    /// other tools. The errors are written to stderr as `{"error": {...}}` if it's
+    /// json, the commands which don't support json print the text output. The
+    /// `doctor` and `pages unreachable` print the findings with their severity and
+    /// code as a check report if it's json or yaml.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Read the database whose version is 1 or 3 with the layout of version 2, a
+    /// warning is logged for it.
+    #[arg(long, default_value_t = false)]
+    compat: bool,
+
+    /// Don't limit the element counts and overflow pages read from the page
+    /// headers. By default a page with more than 2^18 overflow pages or a freelist
+    /// with more than 2^24 ids is reported as corrupted instead of being read.
+    #[arg(long, default_value_t = false)]
+    trusted_input: bool,
+
+    /// Read a snapshot of the database if it's locked by a running writer, the
+    /// file is copied to the temporary directory before reading, so the pages are
+    /// not changed by the writer during the command.
+    #[arg(long, default_value_t = false)]
+    read_dirty: bool,
+
+    /// Read the database from the member of the tar or zip archive, e.g. a snapshot
+    /// tarball. The member must not be compressed.
+    #[arg(long)]
+    member: Option<String>,
+
+    /// Keep the pages read in a sidecar file in the directory, so the next commands
+    /// on the unchanged database don't read the pages from it again.
+    #[arg(long)]
+    page_cache_dir: Option<PathBuf>,
+
+    /// Don't print the header line of the csv output.
+    #[arg(long, default_value_t = false)]
+    no_header: bool,
+
+    /// How the keys are decoded when they are printed: text, hex, u64, revision
+    /// (etcd), uuid or timestamp. The keys which can't be decoded are printed as text.
+    #[arg(long, default_value_t = ancla::decode::KeyFormat::Text)]
+    key_format: ancla::decode::KeyFormat,
+
+    /// How the bucket names which are not valid UTF-8 are printed: lossy (the
+    /// invalid bytes are replaced by U+FFFD), escape (the invalid bytes are printed
+    /// as `\xNN`) or error (the command fails).
+    #[arg(long, default_value_t = ancla::decode::NameEncoding::Lossy)]
+    name_encoding: ancla::decode::NameEncoding,
+
+    /// Record the command, the sha256 of the database and the output of it in the
+    /// session file, the command is appended if the file exists. The session can be
+    /// re-run by `replay` to reproduce a bug report.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: SubCommand,
 
+    /// The database file path, use `-` to read the database from stdin. An
+    /// http(s) url is also accepted when the `http` feature is enabled.
     db: String,
 }
 
@@ -29,14 +128,616 @@ enum Endian {
     Big,
 }
 
+impl From<Endian> for ancla::Endian {
+    fn from(endian: Endian) -> Self {
+        match endian {
+            Endian::Little => ancla::Endian::Little,
+            Endian::Big => ancla::Endian::Big,
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // enabled reports whether the stream is colored, NO_COLOR is ignored if it's
+    // empty.
+    fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").map_or(true, |value| value.is_empty())
+                    && stream.is_terminal()
+            }
+        }
+    }
+}
+
+const RED: u8 = 31;
+const YELLOW: u8 = 33;
+
+// paint wraps the text with the ANSI color if it's enabled.
+fn paint(text: &str, color: u8, enabled: bool) -> Cow<'_, str> {
+    if enabled {
+        Cow::Owned(format!("\x1b[{}m{}\x1b[0m", color, text))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+    Yaml,
+}
+
+// CliError is the error of the commands, each kind of them has a stable exit code
+// so the scripts can tell them apart.
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    NotFound(String),
+    Corruption(String),
+    Io(io::Error),
+}
+
+impl CliError {
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::Usage(_) => "usage",
+            CliError::NotFound(_) => "not_found",
+            CliError::Corruption(_) => "corruption",
+            CliError::Io(_) => "io",
+        }
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 1,
+            CliError::NotFound(_) => 2,
+            CliError::Corruption(_) => 3,
+            CliError::Io(_) => 4,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "kind": self.kind(),
+                "code": self.exit_code(),
+                "message": self.to_string(),
+            }
+        })
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message)
+            | CliError::NotFound(message)
+            | CliError::Corruption(message) => f.write_str(message),
+            CliError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Io(e)
+    }
+}
+
+impl From<ancla::DatabaseError> for CliError {
+    fn from(e: ancla::DatabaseError) -> Self {
+        match e {
+            ancla::DatabaseError::Io(e) => CliError::Io(e),
+            ancla::DatabaseError::Locked => CliError::Io(io::Error::other(e.to_string())),
+            ancla::DatabaseError::BucketNotFound { .. } | ancla::DatabaseError::KeyNotFound => {
+                CliError::NotFound(e.to_string())
+            }
+            ancla::DatabaseError::InvalidBucketIdentifier { .. }
+            | ancla::DatabaseError::InvalidBucketPattern { .. }
+            | ancla::DatabaseError::InvalidUtf8 { .. }
+            | ancla::DatabaseError::BucketExists { .. }
+            | ancla::DatabaseError::IncompatibleValue { .. }
+            | ancla::DatabaseError::PageSizeMismatch { .. }
+            | ancla::DatabaseError::MergeConflict { .. }
+            | ancla::DatabaseError::UnsupportedVersion { .. }
+            | ancla::DatabaseError::Unsupported { .. } => CliError::Usage(e.to_string()),
+            ancla::DatabaseError::TooSmallData { .. }
+            | ancla::DatabaseError::InvalidMeta
+            | ancla::DatabaseError::Corrupted { .. } => CliError::Corruption(e.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum SubCommand {
     Buckets(BucketsArgs),
-    Pages {},
+    Pages(PagesArgs),
+    Analyze(AnalyzeArgs),
+    /// Hexdump the bytes of the database file, each line is annotated with the page
+    /// and the region of the page it falls into.
+    Dump(DumpArgs),
+    /// Print the information of the current meta: the page size, version, txid,
+    /// root page id, freelist page id and max page id.
+    Info {
+        /// Read all reachable pages to count the pages of each type, the progress
+        /// is printed to stderr if it's a terminal.
+        #[arg(long, default_value_t = false)]
+        page_types: bool,
+    },
+    /// Print the page, region, element and bucket which the byte offset of the
+    /// database file belongs to, e.g. the bad offset reported by fsck.
+    Locate {
+        /// The byte offset of the database file.
+        offset: u64,
+    },
+    Kv(KvArgs),
+    /// Export the buckets and key-value pairs as NDJSON in depth-first order, the
+    /// records are streamed so the memory doesn't grow with the database.
+    Export {
+        /// Only export the buckets with their sub buckets, the paths are separated
+        /// by `,`. The paths can be patterns, e.g. `app/*/config`.
+        #[arg(long, value_delimiter = ',')]
+        buckets: Vec<String>,
+
+        /// Write to the file instead of stdout, it's compressed with gzip or zstd if
+        /// the name ends with `.gz` or `.zst`.
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Replace the keys or values, so the structure of a sensitive database can
+        /// be shared, e.g. `values` or `keys=hash,values`. The placeholder (the
+        /// default) keeps the size by replacing each byte by `*`, the hash is the
+        /// hex sha256 which keeps the equal keys or values equal.
+        #[arg(long, value_delimiter = ',')]
+        scrub: Vec<ScrubRule>,
+    },
+    Etcd(EtcdArgs),
+    /// Print a random sample of the key-value pairs of the bucket, e.g. to share the
+    /// representative data in an issue. The same seed prints the same sample of an
+    /// unchanged database.
+    Sample {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+
+        /// The number of the pairs in the sample.
+        #[arg(long, default_value_t = 100)]
+        n: usize,
+
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Replace each byte of the values by `*`, so only their sizes are shared.
+        #[arg(long, default_value_t = false)]
+        anonymize: bool,
+    },
+    /// Rewrite the pages of the bucket into fully packed pages, other pages are not
+    /// changed. The database file is modified in place, so it must not be opened by
+    /// other processes.
+    Compact {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+
+        /// Confirm the modification of the database.
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+
+        /// Print the pages freed and written and the size of the file after the
+        /// compaction, the file is not modified.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Merge the database with another one into a new database file, the buckets
+    /// with the same path are merged recursively.
+    Merge {
+        /// The path of the other database file.
+        #[arg(long)]
+        with: String,
+
+        /// The path of the new database file, it must not exist.
+        #[arg(long)]
+        to: String,
+
+        /// How the key which has different values in the databases is resolved: ours
+        /// keeps the value of this database, theirs keeps the other's, and fail
+        /// aborts the merge.
+        #[arg(long, default_value_t = ancla::ConflictPolicy::Fail)]
+        conflict: ancla::ConflictPolicy,
+    },
+    /// Compare the database with an older copy page by page, and print the changed
+    /// pages with the buckets they belong to in this database.
+    Diff {
+        /// The path of the older database file.
+        #[arg(long)]
+        with: String,
+
+        /// Compare the pages, it's the only supported mode.
+        #[arg(long, required = true)]
+        pages: bool,
+    },
+    /// Check the meta pages, the freelist, the pages, the key order and the
+    /// sequence of all buckets, and print the findings ordered by severity. It
+    /// fails if there are critical findings.
+    Doctor {
+        /// Stop at the first critical finding instead of collecting all findings,
+        /// the statistics only cover the pages checked before it.
+        #[arg(long, default_value_t = false)]
+        fail_fast: bool,
+
+        /// Print nothing, not even the errors, the exit code tells whether the
+        /// database is healthy (0), corrupted (3) or can't be read (4).
+        #[arg(long, default_value_t = false, conflicts_with = "summary")]
+        quiet: bool,
+
+        /// Print one line with the number of findings of each severity instead of
+        /// the findings.
+        #[arg(long, default_value_t = false)]
+        summary: bool,
+
+        /// Resume the check from the checkpoint file if it exists, and save the
+        /// progress to it if the check is paused by `--max-pages` or
+        /// `--max-seconds`. The file is removed once the check is finished, it
+        /// exits with 0 if it's paused.
+        #[arg(long, conflicts_with = "fail_fast")]
+        resume: Option<PathBuf>,
+
+        /// Pause after checking N pages of the buckets in this run.
+        #[arg(long, requires = "resume")]
+        max_pages: Option<u64>,
+
+        /// Pause after checking for N seconds in this run.
+        #[arg(long, requires = "resume")]
+        max_seconds: Option<u64>,
+    },
+    /// Re-run the commands recorded by `--record` in order against a copy of the
+    /// database, and compare their outputs and exit codes with the recorded ones.
+    Replay {
+        /// The session file which is recorded by `--record`.
+        session: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+struct BucketsArgs {
+    /// Only list the buckets of the top N levels.
+    #[arg(long)]
+    depth: Option<usize>,
+
+    #[clap(subcommand)]
+    command: Option<BucketsCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+enum BucketsCommand {
+    /// Print the detail of a single bucket.
+    #[command(group(ArgGroup::new("bucket").required(true).args(["path", "id"])))]
+    Show {
+        /// The bucket path, the names in the path are joined by `/`.
+        path: Option<String>,
+
+        /// The bucket identifier, which is `<page_id>` or `<parent_page_id>:<name>`
+        /// for inline buckets.
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Create an empty bucket, the parent buckets must exist. The database file is
+    /// modified in place, so it must not be opened by other processes.
+    Create {
+        /// The bucket path, the names in the path are joined by `/`.
+        path: String,
+
+        /// Confirm the modification of the database.
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Delete the bucket with it's keys and sub buckets, the pages are freed. The
+    /// database file is modified in place, so it must not be opened by other
+    /// processes.
+    Delete {
+        /// The bucket path, the names in the path are joined by `/`.
+        path: String,
+
+        /// Confirm the modification of the database.
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Write the bucket with it's sub buckets into a new database file, the bucket
+    /// is the only top level bucket of it.
+    Export {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+
+        /// The path of the new database file, it must not exist.
+        #[arg(long)]
+        to: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct PagesArgs {
+    /// Only list the pages whose id is not less than it.
+    #[arg(long, default_value_t = 0)]
+    start_id: u64,
+
+    /// List at most N pages (ordered by id), only N pages are kept in memory.
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Only list the pages of the type, it can be specified multiple times.
+    #[arg(long = "type", value_name = "TYPE")]
+    types: Vec<ancla::PageType>,
+
+    /// Sort at most N pages in memory, the sorted runs of N pages are spilled to
+    /// temporary files and merged if there are more pages.
+    #[arg(long, default_value_t = 1_000_000, value_name = "N")]
+    sort_buffer: usize,
+
+    #[clap(subcommand)]
+    command: Option<PagesCommand>,
+}
+
+#[derive(Debug, Args)]
+struct KvArgs {
+    #[clap(subcommand)]
+    command: KvCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum KvCommand {
+    /// List the key-value pairs of the bucket, the keys of the sub buckets are
+    /// excluded.
+    List {
+        /// The bucket path, the names in the path are joined by `/`. It's a pattern
+        /// if it contains `*`, `?` or `**` (e.g. `app/*/config`), the pairs of all
+        /// matched buckets are listed with the bucket path.
+        bucket: String,
+
+        #[command(flatten)]
+        list: KvListArgs,
+    },
+    /// Print the value of the key in the bucket, the non-printable bytes are escaped
+    /// unless `--raw` or `--out` is given. It exits with 2 if the key doesn't exist.
+    Get {
+        /// The bucket path, the names in the path are joined by `/`.
+        bucket: String,
+        key: String,
+
+        /// Write the exact bytes of the value to stdout, without a trailing newline.
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+
+        /// Write the exact bytes of the value to the file.
+        #[arg(long, conflicts_with = "raw")]
+        out: Option<String>,
+    },
+    /// Search all buckets for the key, and print the bucket path and the value of
+    /// each match. It exits with 2 if the key isn't found in any bucket.
+    Find {
+        #[arg(long)]
+        key: String,
+
+        /// Stop at the first match, the buckets after it are not read.
+        #[arg(long, default_value_t = false)]
+        first: bool,
+    },
+    /// Print where the key is stored in the file: the leaf page id, element index
+    /// and the byte offsets and sizes of the key and value. It exits with 2 if the
+    /// key doesn't exist.
+    Locate {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+        #[arg(long)]
+        key: String,
+    },
+    /// Print the first key-value pair of the bucket, e.g. the oldest entry of the
+    /// revision-ordered keys. It exits with 2 if the bucket doesn't have any key.
+    First {
+        /// The bucket path, the names in the path are joined by `/`.
+        bucket: String,
+    },
+    /// Print the last key-value pair of the bucket, e.g. the newest entry of the
+    /// revision-ordered keys. It exits with 2 if the bucket doesn't have any key.
+    Last {
+        /// The bucket path, the names in the path are joined by `/`.
+        bucket: String,
+    },
+    /// Count the keys of the buckets, the keys of the sub buckets are excluded. It
+    /// prints the count of each bucket and the total.
+    Count {
+        /// The bucket path or pattern (e.g. `app/*/config`), the names in the path
+        /// are joined by `/`, all top level buckets are counted if it's not given.
+        #[arg(long)]
+        bucket: Option<String>,
+
+        /// Count the sub buckets at all depths.
+        #[arg(long, default_value_t = false, conflicts_with = "estimate")]
+        recursive: bool,
+
+        /// Estimate the count of the bucket by sampling the random paths from the
+        /// root page instead of reading all pages, it requires `--bucket`.
+        #[arg(long, default_value_t = false, requires = "bucket")]
+        estimate: bool,
+
+        /// The relative standard error which the estimation stops at.
+        #[arg(long, default_value_t = 0.01, requires = "estimate")]
+        max_error: f64,
+    },
+    /// List the latest keys of the etcd database with the prefix, the Kubernetes
+    /// objects are decoded to print their namespace, name, apiVersion and kind.
+    EtcdList {
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+    /// Set the value of the key in the bucket, the database file is modified in
+    /// place, so it must not be opened by other processes.
+    Put {
+        /// The bucket path, the names in the path are joined by `/`.
+        bucket: String,
+        key: String,
+        value: String,
+
+        /// Confirm the modification of the database.
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+    /// Delete the key from the bucket, the database file is modified in place, so
+    /// it must not be opened by other processes.
+    Delete {
+        /// The bucket path, the names in the path are joined by `/`.
+        bucket: String,
+        key: String,
+
+        /// Confirm the modification of the database.
+        #[arg(long, default_value_t = false)]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct KvListArgs {
+    /// Print at most N key-value pairs.
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Skip the first N key-value pairs.
+    #[arg(long, default_value_t = 0, conflicts_with = "after_key")]
+    offset: usize,
+
+    /// Only print the keys after it in the listing order, e.g. the last key of the
+    /// previous page.
+    #[arg(long)]
+    after_key: Option<String>,
+
+    /// List the keys from the last one, e.g. the latest N keys of the time-ordered
+    /// keys. Only the pages from the end are read.
+    #[arg(long, default_value_t = false)]
+    reverse: bool,
+
+    /// Print at most N bytes of each value, the truncated values are followed by
+    /// `...` and their full size.
+    #[arg(long)]
+    max_value_bytes: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct DumpArgs {
+    /// The offset of the first byte, it's rounded down to 16 bytes.
+    #[arg(long, default_value_t = 0)]
+    offset: u64,
+
+    /// The number of bytes to dump.
+    #[arg(long, default_value_t = 4096)]
+    length: u64,
+}
+
+#[derive(Debug, Args)]
+struct EtcdArgs {
+    #[clap(subcommand)]
+    command: EtcdCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum EtcdCommand {
+    /// Check the sha256 appended to the snapshot by `etcdctl snapshot save` and the
+    /// meta pages, and print the consistent index, term and revisions of etcd. It
+    /// fails if the hash doesn't match.
+    VerifySnapshot,
 }
 
 #[derive(Debug, Args)]
-struct BucketsArgs {}
+struct AnalyzeArgs {
+    #[clap(subcommand)]
+    command: AnalyzeCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum AnalyzeCommand {
+    /// Compare the txid of the two meta pages, and report the pages written by the
+    /// last transaction and the buckets they belong to.
+    Tx,
+    /// Print the buckets which are stored in their own pages but fit inline, and the
+    /// inline buckets which don't fit inline, with the bytes saved after compaction.
+    Inline,
+    /// Print the key count and value bytes of each key prefix of the bucket, the
+    /// prefix is the first N segments of the key split by the delimiter.
+    Prefixes {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+
+        #[arg(long, default_value = "/")]
+        delimiter: String,
+
+        /// The number of segments of the prefix.
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+    },
+    /// Print the min, max and average leaf depth of each bucket's B-tree, the
+    /// distribution of the branching factor, and the unbalanced subtrees.
+    Tree,
+    /// Detect the formats of the values of the bucket (json, protobuf, gob, msgpack,
+    /// text or binary), and print the count and percentage of each format.
+    Values {
+        /// The bucket path, the names in the path are joined by `/`.
+        #[arg(long)]
+        bucket: String,
+
+        /// The number of the values sampled from the start of the bucket.
+        #[arg(long, default_value_t = 1000)]
+        samples: usize,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PagesCommand {
+    /// Print the content hash of each page and the Merkle-style hash of each
+    /// bucket's subtree, to compare two database copies quickly.
+    Hash,
+    /// Print one line per element of all branch and leaf pages: the page id, index,
+    /// kind, key, key size, value size and child page id.
+    Elements,
+    /// Print each element of the branch or leaf page: the index, flags, key, value
+    /// size, child page id and whether it's a bucket.
+    Items {
+        #[arg(long)]
+        page_id: u64,
+    },
+    /// Print the path from the root page to the page: the element of each page
+    /// which points to the next one, or why the page is not in the tree.
+    Path {
+        #[arg(long)]
+        page_id: u64,
+    },
+    /// Print the pages in the freelist, the pending pages are freed by the last
+    /// transaction but still reachable from the previous meta.
+    Freelist,
+    /// Print the pages which are neither reachable nor free, the overflow pages of
+    /// them, and the pages which are used more than once.
+    Unreachable,
+}
 
 const fn is_target_little_endian() -> bool {
     // cfg!(target_endian = "little")
@@ -50,88 +751,2523 @@ struct Bucket {
     child_buckets: Vec<Bucket>,
 }
 
-fn iter_buckets_inner(bucket: &ancla::Bucket) -> Vec<Bucket> {
-    let mut buckets: Vec<Bucket> = Vec::new();
-
-    let child_buckets: Vec<ancla::Bucket> = bucket.iter_buckets().collect();
-    for child_bucket in child_buckets {
-        buckets.push(Bucket {
+// build_buckets builds the bucket tree from the buckets which are ordered in
+// depth-first order, the buckets at the depth are the children of the same parent.
+fn build_buckets(
+    buckets: &mut Peekable<impl Iterator<Item = ancla::Bucket>>,
+    depth: usize,
+) -> Vec<Bucket> {
+    let mut children: Vec<Bucket> = Vec::new();
+    while let Some(bucket) = buckets.next_if(|bucket| bucket.path().len() == depth) {
+        children.push(Bucket {
             name: bucket.name.clone(),
             page_id: bucket.page_id,
             is_inline: bucket.is_inline,
-            child_buckets: iter_buckets_inner(&child_bucket),
-        })
+            child_buckets: build_buckets(buckets, depth + 1),
+        });
     }
 
-    buckets
+    children
 }
 
-fn iter_buckets(db: Rc<RefCell<ancla::DB>>) -> Vec<Bucket> {
-    let buckets: Vec<ancla::Bucket> = ancla::DB::iter_buckets(db).collect();
-    buckets
-        .iter()
-        .map(|bucket| Bucket {
-            name: bucket.name.clone(),
-            page_id: bucket.page_id,
-            is_inline: bucket.is_inline,
-            child_buckets: iter_buckets_inner(bucket),
-        })
-        .collect()
+// collect_buckets collects the buckets in depth-first order, the buckets read
+// before the database fails are returned with the error, so a partially corrupted
+// database can still be listed.
+fn collect_buckets(
+    db: Rc<RefCell<ancla::DB>>,
+    max_depth: Option<usize>,
+) -> (Vec<ancla::Bucket>, Result<(), CliError>) {
+    let mut items = ancla::DB::iter_items(db, max_depth);
+    let mut buckets = Vec::new();
+    loop {
+        match items.try_next() {
+            Ok(Some(ancla::DbItem::Bucket(bucket))) => buckets.push(bucket),
+            Ok(Some(ancla::DbItem::KeyValue { .. })) => {}
+            Ok(None) => return (buckets, Ok(())),
+            Err(e) => return (buckets, Err(e.into())),
+        }
+    }
 }
 
-fn print_buckets(buckets: &Vec<Bucket>, level: usize) {
+fn iter_buckets(
+    db: Rc<RefCell<ancla::DB>>,
+    max_depth: Option<usize>,
+) -> (Vec<Bucket>, Result<(), CliError>) {
+    let (buckets, result) = collect_buckets(db, max_depth);
+    let buckets = build_buckets(&mut buckets.into_iter().peekable(), 1);
+    (buckets, result)
+}
+
+fn print_buckets(buckets: &Vec<Bucket>, level: usize, output: &Output) -> Result<(), CliError> {
     for bucket in buckets {
         println!(
             "{}{}, {}, {}",
             '-'.to_string().repeat(level),
-            String::from_utf8(bucket.name.clone()).unwrap(),
+            output.name(&bucket.name)?,
             bucket.is_inline,
             bucket.page_id
         );
-        print_buckets(&bucket.child_buckets, level + 2);
+        print_buckets(&bucket.child_buckets, level + 2, output)?;
     }
+    Ok(())
+}
+
+// Output writes the listings in the output format, the text format is the
+// human-readable output of each command.
+struct Output {
+    format: OutputFormat,
+    header: bool,
+    key_format: ancla::decode::KeyFormat,
+    name_encoding: ancla::decode::NameEncoding,
+    // print the read statistics of the command to stderr.
+    stats: bool,
+    // print nothing, not even the errors.
+    quiet: bool,
+    // color the severities of the findings.
+    color: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut cli = Command::parse();
+impl Output {
+    // severity returns the severity which is colored by it's level, the critical
+    // is red and the warning is yellow.
+    fn severity(&self, severity: ancla::Severity) -> Cow<'static, str> {
+        match severity {
+            ancla::Severity::Critical => paint(severity.as_str(), RED, self.color),
+            ancla::Severity::Warning => paint(severity.as_str(), YELLOW, self.color),
+            ancla::Severity::Info => Cow::Borrowed(severity.as_str()),
+        }
+    }
 
-    if cli.endian.is_none() {
-        if is_target_little_endian() {
-            cli.endian = Some(Endian::Little);
+    fn name(&self, name: &[u8]) -> Result<String, CliError> {
+        Ok(self.name_encoding.render(name)?)
+    }
+
+    fn bucket_path(&self, path: &[Vec<u8>]) -> Result<String, CliError> {
+        if path.is_empty() {
+            return Ok("(root)".to_string());
+        }
+
+        let names = path
+            .iter()
+            .map(|name| self.name(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(names.join("/"))
+    }
+
+    fn is_csv(&self) -> bool {
+        self.format == OutputFormat::Csv
+    }
+
+    fn csv_header(&self, fields: &[&str]) {
+        if self.header {
+            self.csv_record(fields);
+        }
+    }
+
+    fn csv_record<S: AsRef<str>>(&self, fields: &[S]) {
+        let record: Vec<Cow<'_, str>> = fields.iter().map(|f| csv_escape(f.as_ref())).collect();
+        println!("{}", record.join(","));
+    }
+}
+
+// csv_escape quotes the field if it contains the delimiter, quote or line breaks,
+// the quotes in it are doubled as RFC 4180.
+fn csv_escape(field: &str) -> Cow<'_, str> {
+    if !field.contains([',', '"', '\r', '\n']) {
+        return Cow::Borrowed(field);
+    }
+    Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+}
+
+fn print_buckets_csv(
+    db: Rc<RefCell<ancla::DB>>,
+    max_depth: Option<usize>,
+    output: &Output,
+) -> Result<(), CliError> {
+    let (buckets, result) = collect_buckets(db, max_depth);
+    output.csv_header(&["path", "inline", "page_id"]);
+    for bucket in buckets {
+        output.csv_record(&[
+            output.bucket_path(bucket.path())?,
+            bucket.is_inline.to_string(),
+            bucket.page_id.to_string(),
+        ]);
+    }
+    result
+}
+
+fn print_pages(
+    pages: impl Iterator<Item = io::Result<ancla::PageInfo>>,
+    output: &Output,
+) -> Result<(), CliError> {
+    if !output.is_csv() {
+        for page in pages {
+            println!("{:?}", page?);
+        }
+        return Ok(());
+    }
+
+    output.csv_header(&[
+        "id",
+        "type",
+        "overflow",
+        "capacity",
+        "used",
+        "slack",
+        "parent_page_id",
+    ]);
+    for page in pages {
+        let page = page?;
+        output.csv_record(&[
+            page.id.to_string(),
+            page.typ.to_string(),
+            page.overflow.to_string(),
+            page.capacity.to_string(),
+            page.used.to_string(),
+            page.slack.to_string(),
+            page.parent_page_id
+                .map_or_else(String::new, |id| id.to_string()),
+        ]);
+    }
+    Ok(())
+}
+
+fn print_freelist(db: Rc<RefCell<ancla::DB>>, output: &Output) {
+    if output.is_csv() {
+        output.csv_header(&["page_id", "state"]);
+    }
+    for page in ancla::DB::freelist(db) {
+        let state = if page.pending { "pending" } else { "free" };
+        if output.is_csv() {
+            output.csv_record(&[page.id.to_string(), state.to_string()]);
         } else {
-            cli.endian = Some(Endian::Big);
+            println!("{}, {}", page.id, state);
+        }
+    }
+}
+
+// to_json renders the report of the library as a single line JSON document.
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    // The reports are plain structs, serializing them never fails.
+    serde_json::to_string(value).unwrap()
+}
+
+// to_yaml renders the report of the library as a YAML document, the strings are
+// double-quoted with the JSON escapes which are valid in YAML too, unless they're
+// plain words such as the severity.
+fn to_yaml<T: serde::Serialize>(value: &T) -> String {
+    let mut output = String::new();
+    yaml_value(&serde_json::to_value(value).unwrap(), 0, &mut output);
+    output
+}
+
+fn yaml_value(value: &serde_json::Value, indent: usize, output: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields {
+                output.push_str(&format!("{}{}:", pad, key));
+                yaml_child(field, indent + 2, output);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                // The first line of the item is nested under the dash.
+                let mut nested = String::new();
+                yaml_value(item, indent + 2, &mut nested);
+                output.push_str(&format!("{}- {}", pad, &nested[indent + 2..]));
+            }
+        }
+        _ => output.push_str(&format!("{}{}\n", pad, yaml_scalar(value))),
+    }
+}
+
+// yaml_child renders the value of a mapping key, the collections start on the next
+// line and the scalars follow the key.
+fn yaml_child(value: &serde_json::Value, indent: usize, output: &mut String) {
+    match value {
+        serde_json::Value::Object(fields) if fields.is_empty() => output.push_str(" {}\n"),
+        serde_json::Value::Array(items) if items.is_empty() => output.push_str(" []\n"),
+        serde_json::Value::Object(_) | serde_json::Value::Array(_) => {
+            output.push('\n');
+            yaml_value(value, indent, output);
         }
+        _ => output.push_str(&format!(" {}\n", yaml_scalar(value))),
     }
+}
 
-    println!("{:?}", cli);
-    println!("{:?}", page_size::get());
+fn yaml_scalar(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s)
+            if s.starts_with(|c: char| c.is_ascii_lowercase())
+                && s.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                && !["null", "true", "false", "yes", "no", "on", "off", "y", "n"]
+                    .contains(&s.as_str()) =>
+        {
+            s.clone()
+        }
+        _ => value.to_string(),
+    }
+}
 
-    let options = ancla::AnclaOptions::builder()
-        .db_path(
-            // Path::new(env!("CARGO_MANIFEST_DIR"))
-            //     .join("testdata")
-            //     .join("test1.db")
-            //     .to_str()
-            //     .unwrap()
-            //     .to_string(),
-            cli.db,
+fn print_unreachable_pages(db: Rc<RefCell<ancla::DB>>, output: &Output) {
+    let report = ancla::DB::unreachable_pages(db);
+    if output.format == OutputFormat::Json {
+        println!("{}", to_json(&report.check_report()));
+        return;
+    }
+    if output.format == OutputFormat::Yaml {
+        print!("{}", to_yaml(&report.check_report()));
+        return;
+    }
+    if output.is_csv() {
+        output.csv_header(&["page_id", "kind"]);
+    }
+    let pages = report
+        .unreachable
+        .iter()
+        .map(|id| (id, "unreachable"))
+        .chain(
+            report
+                .orphaned_overflow
+                .iter()
+                .map(|id| (id, "orphaned-overflow")),
         )
-        .build();
-    let db = ancla::DB::build(options);
+        .chain(
+            report
+                .double_referenced
+                .iter()
+                .map(|id| (id, "double-referenced")),
+        );
+    for (id, kind) in pages {
+        if output.is_csv() {
+            output.csv_record(&[id.to_string(), kind.to_string()]);
+        } else {
+            println!("{}, {}", id, kind);
+        }
+    }
+}
+
+fn print_page_elements(db: Rc<RefCell<ancla::DB>>, output: &Output) {
+    if output.is_csv() {
+        output.csv_header(&[
+            "page_id",
+            "index",
+            "kind",
+            "key",
+            "ksize",
+            "vsize",
+            "child_page_id",
+        ]);
+    }
+    for element in ancla::DB::iter_page_elements(db) {
+        let record = [
+            element.page_id.to_string(),
+            element.index.to_string(),
+            element.kind.to_string(),
+            output.key_format.render(&element.key),
+            element.ksize.to_string(),
+            element.vsize.to_string(),
+            element
+                .child_page_id
+                .map_or_else(String::new, |id| id.to_string()),
+        ];
+        if output.is_csv() {
+            output.csv_record(&record);
+        } else {
+            println!("{}", record.join(", "));
+        }
+    }
+}
+
+fn print_page_items(
+    db: Rc<RefCell<ancla::DB>>,
+    page_id: u64,
+    output: &Output,
+) -> Result<(), CliError> {
+    let max_page_id = ancla::DB::info(db.clone()).max_page_id;
+    if page_id >= max_page_id {
+        return Err(CliError::NotFound(format!(
+            "page {} is out of the database, the max page id is {}",
+            page_id, max_page_id
+        )));
+    }
+    let typ = ancla::DB::page_layout(db.clone(), page_id).typ;
+    if !matches!(typ, ancla::PageType::DataBranch | ancla::PageType::DataLeaf) {
+        return Err(CliError::Usage(format!(
+            "page {} is a {} page, only the branch and leaf pages have elements",
+            page_id, typ
+        )));
+    }
 
-    match cli.command {
-        SubCommand::Buckets(_) => {
-            let buckets = iter_buckets(db);
-            print_buckets(&buckets, 0);
+    if output.is_csv() {
+        output.csv_header(&["index", "flags", "key", "vsize", "child_page_id", "bucket"]);
+    }
+    for element in ancla::DB::page_elements(db, page_id) {
+        let record = [
+            element.index.to_string(),
+            format!("{:#x}", element.flags),
+            output.key_format.render(&element.key),
+            element.vsize.to_string(),
+            element
+                .child_page_id
+                .map_or_else(String::new, |id| id.to_string()),
+            (element.kind == ancla::ElementKind::Bucket).to_string(),
+        ];
+        if output.is_csv() {
+            output.csv_record(&record);
+        } else {
+            println!("{}", record.join(", "));
         }
-        SubCommand::Pages {} => {
-            let mut pages: Vec<ancla::PageInfo> = ancla::DB::iter_pages(db).collect();
-            pages.sort();
-            pages.iter().for_each(|p| {
-                println!("{:?}", p);
-            });
+    }
+    Ok(())
+}
+
+fn print_sample(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    n: usize,
+    seed: u64,
+    anonymize: bool,
+    output: &Output,
+) -> Result<(), CliError> {
+    let Some(pairs) = ancla::DB::sample_keys(db, &parse_bucket_path(bucket), n, seed) else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+
+    if output.is_csv() {
+        output.csv_header(&["key", "value"]);
+    }
+    for (key, value) in pairs {
+        let key = output.key_format.render(&key);
+        let value = if anonymize {
+            ScrubMode::Placeholder.apply(&value)
+        } else {
+            Cow::Borrowed(&*value)
+        };
+        let value = value.escape_ascii().to_string();
+        if output.is_csv() {
+            output.csv_record(&[key, value]);
+        } else {
+            println!("{}, {}", key, value);
         }
     }
+    Ok(())
+}
+
+fn print_kvs(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    list: &KvListArgs,
+    output: &Output,
+) -> Result<(), CliError> {
+    // The pairs of all matched buckets are listed with their bucket path if the
+    // bucket is a pattern.
+    let pattern: ancla::BucketPattern = bucket.parse()?;
+    let with_bucket = pattern.has_wildcard();
+    let items: DbItems = match (list.reverse, with_bucket) {
+        (false, _) => Box::new(matched_items(db, &pattern, false)?),
+        (true, false) => Box::new(
+            ancla::DB::iter_bucket_items_rev(db, &parse_bucket_path(bucket))
+                .ok_or_else(|| CliError::NotFound("bucket not found".to_string()))?
+                .try_iter(),
+        ),
+        (true, true) => {
+            return Err(CliError::Usage(
+                "--reverse doesn't support the bucket patterns".to_string(),
+            ))
+        }
+    };
 
+    if output.is_csv() {
+        if with_bucket {
+            output.csv_header(&["bucket", "key", "value"]);
+        } else {
+            output.csv_header(&["key", "value"]);
+        }
+    }
+    let after_key = list.after_key.as_ref().map(|key| key.as_bytes());
+    // The error ends the iteration, so it's never skipped.
+    let mut offset = list.offset;
+    let items = items
+        .filter_map(|item| match item {
+            Ok(ancla::DbItem::KeyValue { bucket, key, value }) => Some(Ok((bucket, key, value))),
+            Ok(ancla::DbItem::Bucket(_)) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .skip_while(|item| {
+            item.as_ref().is_ok_and(|(_, key, _)| {
+                after_key.is_some_and(|after_key| match list.reverse {
+                    false => key.as_slice() <= after_key,
+                    true => key.as_slice() >= after_key,
+                })
+            })
+        })
+        .filter(|item| match item {
+            Ok(_) if offset > 0 => {
+                offset -= 1;
+                false
+            }
+            _ => true,
+        })
+        .take(list.limit.unwrap_or(usize::MAX));
+    for item in items {
+        let (bucket, key, value) = item?;
+        let key = output.key_format.render(&key);
+        let value = match list.max_value_bytes {
+            Some(max) if value.len() > max => {
+                format!("{}... ({} bytes)", value[..max].escape_ascii(), value.len())
+            }
+            _ => value.escape_ascii().to_string(),
+        };
+        match (output.is_csv(), with_bucket) {
+            (true, true) => output.csv_record(&[output.bucket_path(&bucket)?, key, value]),
+            (true, false) => output.csv_record(&[key, value]),
+            (false, true) => println!("{}, {}, {}", output.bucket_path(&bucket)?, key, value),
+            (false, false) => println!("{}, {}", key, value),
+        }
+    }
     Ok(())
 }
+
+// DbItems iterates the items of the database, the iteration ends at the page which
+// can't be read.
+type DbItems = Box<dyn Iterator<Item = Result<ancla::DbItem, ancla::DatabaseError>>>;
+
+// matched_items iterates the items of the buckets matched by the pattern, the items
+// of their sub buckets are included only if recursive. It fails if no bucket is
+// matched.
+fn matched_items(
+    db: Rc<RefCell<ancla::DB>>,
+    pattern: &ancla::BucketPattern,
+    recursive: bool,
+) -> Result<Peekable<impl Iterator<Item = Result<ancla::DbItem, ancla::DatabaseError>>>, CliError> {
+    let max_depth = if recursive { None } else { pattern.max_depth() };
+    let options = ancla::ReadOptions::builder()
+        .max_depth(max_depth)
+        .buckets_filter(vec![pattern.clone()])
+        .build();
+    let pattern = pattern.clone();
+    let mut items = ancla::DB::iter_items_with(db, options)
+        .try_iter()
+        .filter(move |item| {
+            recursive
+                || match item {
+                    Ok(ancla::DbItem::Bucket(bucket)) => pattern.matches(bucket.path()),
+                    Ok(ancla::DbItem::KeyValue { bucket, .. }) => pattern.matches(bucket),
+                    Err(_) => true,
+                }
+        })
+        .peekable();
+    // The matched bucket is returned before it's items.
+    if items.peek().is_none() {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    }
+    Ok(items)
+}
+
+fn print_key_counts(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: Option<String>,
+    recursive: bool,
+    output: &Output,
+) -> Result<(), CliError> {
+    let mut items: DbItems = match bucket {
+        Some(bucket) => Box::new(matched_items(db, &bucket.parse()?, recursive)?),
+        // The top level buckets are 1 level below the root.
+        None => {
+            Box::new(ancla::DB::iter_items(db, if recursive { None } else { Some(1) }).try_iter())
+        }
+    };
+    let counts = items.try_fold(BTreeMap::new(), |mut counts, item| {
+        match item? {
+            ancla::DbItem::Bucket(bucket) => {
+                counts.entry(bucket.path().to_vec()).or_insert(0);
+            }
+            ancla::DbItem::KeyValue { bucket, .. } => {
+                *counts.entry(bucket).or_insert(0) += 1;
+            }
+        }
+        Ok::<_, ancla::DatabaseError>(counts)
+    })?;
+
+    if output.is_csv() {
+        output.csv_header(&["bucket", "keys"]);
+    }
+    for (path, count) in &counts {
+        let path = output.bucket_path(path)?;
+        if output.is_csv() {
+            output.csv_record(&[path, count.to_string()]);
+        } else {
+            println!("{}, {}", path, count);
+        }
+    }
+    if !output.is_csv() {
+        println!("total, {}", counts.values().sum::<u64>());
+    }
+    Ok(())
+}
+
+// print_key_location prints the page, element and file offsets which the key and
+// value of the bucket are stored at.
+fn print_key_location(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    key: &str,
+    output: &Output,
+) -> Result<(), CliError> {
+    let location = ancla::DB::get_bucket(db, &parse_bucket_path(bucket))
+        .ok_or_else(|| CliError::NotFound("bucket not found".to_string()))?
+        .locate(key.as_bytes())
+        .ok_or_else(|| CliError::NotFound("key not found".to_string()))?;
+
+    if output.is_csv() {
+        output.csv_header(&[
+            "page_id",
+            "inline",
+            "index",
+            "kind",
+            "key_offset",
+            "ksize",
+            "value_offset",
+            "vsize",
+        ]);
+        output.csv_record(&[
+            location.page_id.to_string(),
+            location.is_inline.to_string(),
+            location.index.to_string(),
+            location.kind.to_string(),
+            location.key_offset.to_string(),
+            location.ksize.to_string(),
+            location.value_offset.to_string(),
+            location.vsize.to_string(),
+        ]);
+        return Ok(());
+    }
+    println!("page id: {}", location.page_id);
+    if location.is_inline {
+        println!("inline: true");
+    }
+    println!("element: {}", location.index);
+    println!("kind: {}", location.kind);
+    println!(
+        "key: {}..{} ({} bytes)",
+        location.key_offset,
+        location.key_offset + location.ksize,
+        location.ksize
+    );
+    println!(
+        "value: {}..{} ({} bytes)",
+        location.value_offset,
+        location.value_offset + location.vsize,
+        location.vsize
+    );
+    Ok(())
+}
+
+// print_edge_key prints the first or last key-value pair of the bucket.
+fn print_edge_key(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    last: bool,
+    output: &Output,
+) -> Result<(), CliError> {
+    let Some(bucket) = ancla::DB::get_bucket(db, &parse_bucket_path(bucket)) else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+    let pair = if last { bucket.last() } else { bucket.first() };
+    let Some((key, value)) = pair else {
+        return Err(CliError::NotFound(
+            "the bucket doesn't have any key".to_string(),
+        ));
+    };
+
+    let key = output.key_format.render(&key);
+    let value = value.escape_ascii().to_string();
+    if output.is_csv() {
+        output.csv_header(&["key", "value"]);
+        output.csv_record(&[key, value]);
+    } else {
+        println!("{}, {}", key, value);
+    }
+    Ok(())
+}
+
+fn print_key_count_estimate(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    max_error: f64,
+    output: &Output,
+) -> Result<(), CliError> {
+    let Some(estimate) = ancla::DB::estimate_key_count(db, &parse_bucket_path(bucket), max_error)?
+    else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+
+    let error = format!("{:.2}", estimate.relative_error * 100.0);
+    if output.is_csv() {
+        output.csv_header(&["bucket", "keys", "exact", "samples", "error_percent"]);
+        output.csv_record(&[
+            bucket.to_string(),
+            estimate.keys.to_string(),
+            estimate.is_exact().to_string(),
+            estimate.samples.to_string(),
+            error,
+        ]);
+    } else if estimate.is_exact() {
+        println!("{}, {} (exact)", bucket, estimate.keys);
+    } else {
+        println!(
+            "{}, ~{} (error {}%, {} samples)",
+            bucket, estimate.keys, error, estimate.samples
+        );
+    }
+    Ok(())
+}
+
+fn print_value(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    key: &str,
+    raw: bool,
+    out: Option<String>,
+) -> Result<(), CliError> {
+    let value = ancla::DB::get_bucket(db, &parse_bucket_path(bucket))
+        .ok_or_else(|| CliError::NotFound("bucket not found".to_string()))?
+        .get(key.as_bytes())
+        .ok_or_else(|| CliError::NotFound("key not found".to_string()))?;
+    if let Some(out) = out {
+        fs::write(&out, &value)
+            .map_err(|e| io::Error::new(e.kind(), format!("write {}: {}", out, e)))?;
+    } else if raw {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&value)?;
+        stdout.flush()?;
+    } else {
+        println!("{}", value.escape_ascii());
+    }
+    Ok(())
+}
+
+fn print_key_matches(
+    db: Rc<RefCell<ancla::DB>>,
+    key: &str,
+    first: bool,
+    output: &Output,
+) -> Result<(), CliError> {
+    let matches = ancla::DB::iter_items(db, None)
+        .try_iter()
+        .filter_map(|item| match item {
+            Ok(ancla::DbItem::KeyValue {
+                bucket,
+                key: item_key,
+                value,
+            }) if item_key == key.as_bytes() => Some(Ok((bucket, value))),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        });
+    let matches = matches.take(if first { 1 } else { usize::MAX });
+
+    if output.is_csv() {
+        output.csv_header(&["bucket", "value"]);
+    }
+    let mut found = false;
+    for item in matches {
+        let (bucket, value) = item?;
+        found = true;
+        let record = [
+            output.bucket_path(&bucket)?,
+            value.escape_ascii().to_string(),
+        ];
+        if output.is_csv() {
+            output.csv_record(&record);
+        } else {
+            println!("{}", record.join(", "));
+        }
+    }
+    if !found {
+        return Err(CliError::NotFound("key not found".to_string()));
+    }
+    Ok(())
+}
+
+// write_confirmation returns whether the modification is confirmed if the command
+// modifies the database file, it's None for other commands.
+fn write_confirmation(command: &SubCommand) -> Option<bool> {
+    match command {
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Put { yes, .. } | KvCommand::Delete { yes, .. },
+        })
+        | SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Create { yes, .. } | BucketsCommand::Delete { yes, .. }),
+            ..
+        })
+        | SubCommand::Compact { yes, .. } => Some(*yes),
+        _ => None,
+    }
+}
+
+// run_write runs the commands which modify the database file, they don't read the
+// database by DB.
+fn run_write(path: &str, command: SubCommand, yes: bool) -> Result<(), CliError> {
+    if !yes {
+        return Err(CliError::Usage(
+            "the database will be modified, pass --yes to confirm".to_string(),
+        ));
+    }
+    if path == "-" || path.starts_with("http://") || path.starts_with("https://") {
+        return Err(CliError::Usage(
+            "only the local database file can be modified".to_string(),
+        ));
+    }
+
+    let mut tx = ancla::WriteTx::begin(path)?;
+    match command {
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Put {
+                bucket, key, value, ..
+            },
+        }) => tx.put(
+            &parse_bucket_path(&bucket),
+            key.as_bytes(),
+            value.as_bytes(),
+        )?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Delete { bucket, key, .. },
+        }) => tx.delete(&parse_bucket_path(&bucket), key.as_bytes())?,
+        SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Create { path, .. }),
+            ..
+        }) => tx.create_bucket(&parse_bucket_path(&path))?,
+        SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Delete { path, .. }),
+            ..
+        }) => tx.delete_bucket(&parse_bucket_path(&path))?,
+        SubCommand::Compact { bucket, .. } => tx.compact_bucket(&parse_bucket_path(&bucket))?,
+        _ => unreachable!("only the write commands are run by run_write"),
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// record_session runs the command in a child process without `--record`, passes
+// its output through and appends it to the session file, the exit code of the
+// child is returned.
+fn record_session(session: &Path, db: &str) -> Result<ExitCode, CliError> {
+    if db == "-" || db.contains("://") {
+        return Err(CliError::Usage(
+            "only the command on the local database file can be recorded".to_string(),
+        ));
+    }
+
+    let mut args = Vec::new();
+    let mut iter = std::env::args_os().skip(1);
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy().into_owned();
+        if arg == "--record" {
+            iter.next();
+        } else if !arg.starts_with("--record=") {
+            args.push(arg);
+        }
+    }
+    let db_index = db_arg_index(&args, db)
+        .ok_or_else(|| CliError::Usage(format!("the database path {} can't be recorded", db)))?;
+    let db_sha256 = file_sha256(Path::new(db))?;
+
+    let child = process::Command::new(std::env::current_exe()?)
+        .args(&args)
+        .stdin(process::Stdio::inherit())
+        .output()?;
+    io::stdout().write_all(&child.stdout)?;
+    io::stderr().write_all(&child.stderr)?;
+    // The child is killed by a signal if it doesn't have the exit code.
+    let exit_code = child.status.code().unwrap_or(-1);
+
+    let mut value = if session.exists() {
+        read_session(session)?
+    } else {
+        serde_json::json!({ "version": 1, "commands": [] })
+    };
+    let Some(commands) = value["commands"].as_array_mut() else {
+        return Err(CliError::Usage(format!(
+            "{} is not a session file",
+            session.display()
+        )));
+    };
+    commands.push(serde_json::json!({
+        "args": args,
+        "db_index": db_index,
+        "db_sha256": db_sha256,
+        "stdout": String::from_utf8_lossy(&child.stdout),
+        "stderr": String::from_utf8_lossy(&child.stderr),
+        "exit_code": exit_code,
+    }));
+    fs::write(session, serde_json::to_string_pretty(&value).unwrap())?;
+    Ok(ExitCode::from(exit_code as u8))
+}
+
+// db_arg_index returns the index of the database path in the arguments, which is
+// replaced by the copy of the database when the session is replayed. The path may
+// also be the value of an option, so clap tells which one is the positional by
+// parsing the arguments with each of them replaced.
+fn db_arg_index(args: &[String], db: &str) -> Option<usize> {
+    const PLACEHOLDER: &str = "\0db";
+    (0..args.len()).filter(|&i| args[i] == db).find(|&i| {
+        let mut probe = args.to_vec();
+        probe[i] = PLACEHOLDER.to_string();
+        Command::try_parse_from(std::iter::once("anclalet".to_string()).chain(probe))
+            .is_ok_and(|cli| cli.db == PLACEHOLDER)
+    })
+}
+
+fn read_session(session: &Path) -> Result<serde_json::Value, CliError> {
+    let data = fs::read(session)
+        .map_err(|e| io::Error::new(e.kind(), format!("open {}: {}", session.display(), e)))?;
+    serde_json::from_slice(&data).map_err(|e| {
+        CliError::Usage(format!(
+            "{} is not a session file: {}",
+            session.display(),
+            e
+        ))
+    })
+}
+
+fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("open {}: {}", path.display(), e)))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// replay_session re-runs the recorded commands against a copy of the database, the
+// copy is shared by the commands, so the commands which modify the database are
+// replayed in the same order as they're recorded.
+fn replay_session(db: &str, session: &Path, output: &Output) -> Result<(), CliError> {
+    let value = read_session(session)?;
+    let Some(commands) = value["commands"].as_array() else {
+        return Err(CliError::Usage(format!(
+            "{} is not a session file",
+            session.display()
+        )));
+    };
+
+    let copy = std::env::temp_dir().join(format!("anclalet-{}.replay.db", process::id()));
+    fs::copy(db, &copy).map_err(|e| io::Error::new(e.kind(), format!("copy {}: {}", db, e)))?;
+    let result = replay_commands(commands, &copy, output);
+    fs::remove_file(&copy)?;
+    result
+}
+
+fn replay_commands(
+    commands: &[serde_json::Value],
+    copy: &Path,
+    output: &Output,
+) -> Result<(), CliError> {
+    let same = |same: bool| if same { "same" } else { "differs" };
+    if output.is_csv() {
+        output.csv_header(&["index", "command", "db", "stdout", "stderr", "exit_code"]);
+    }
+
+    let mut differs = 0;
+    for (index, command) in commands.iter().enumerate() {
+        let invalid =
+            || CliError::Usage(format!("the command {} of the session is invalid", index));
+        let mut args: Vec<String> = command["args"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|arg| arg.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect::<Result<_, _>>()?;
+        let db_index = command["db_index"].as_u64().ok_or_else(invalid)? as usize;
+        let recorded = args.get_mut(db_index).ok_or_else(invalid)?;
+        *recorded = copy.to_string_lossy().into_owned();
+
+        let db_same = command["db_sha256"].as_str() == Some(file_sha256(copy)?.as_str());
+        let child = process::Command::new(std::env::current_exe()?)
+            .args(&args)
+            .stdin(process::Stdio::null())
+            .output()?;
+        let stdout_same =
+            command["stdout"].as_str() == Some(&String::from_utf8_lossy(&child.stdout));
+        let stderr_same =
+            command["stderr"].as_str() == Some(&String::from_utf8_lossy(&child.stderr));
+        let exit_code = child.status.code().unwrap_or(-1);
+        let exit_code_same = command["exit_code"].as_i64() == Some(exit_code as i64);
+        if !(stdout_same && stderr_same && exit_code_same) {
+            differs += 1;
+        }
+
+        args[db_index] = "<DB>".to_string();
+        let record = [
+            index.to_string(),
+            args.join(" "),
+            same(db_same).to_string(),
+            same(stdout_same).to_string(),
+            same(stderr_same).to_string(),
+            format!("{} ({})", exit_code, same(exit_code_same)),
+        ];
+        if output.is_csv() {
+            output.csv_record(&record);
+        } else {
+            println!(
+                "{}: {}, db {}, stdout {}, stderr {}, exit code {}",
+                record[0], record[1], record[2], record[3], record[4], record[5]
+            );
+        }
+    }
+    if !output.is_csv() {
+        println!("replayed {} commands, {} differ", commands.len(), differs);
+    }
+    Ok(())
+}
+
+// print_compact_forecast compacts the bucket in a transaction which isn't committed,
+// and prints what the compaction would change.
+fn print_compact_forecast(path: &str, bucket: &str) -> Result<(), CliError> {
+    let mut tx = ancla::WriteTx::begin(path)?;
+    tx.compact_bucket(&parse_bucket_path(bucket))?;
+    let forecast = tx.forecast()?;
+    println!("pages freed: {}", forecast.pages_freed);
+    println!("pages written: {}", forecast.pages_written);
+    println!("reclaimed bytes: {}", forecast.reclaimed_bytes());
+    println!("file size: {}", forecast.file_size);
+    println!("new file size: {}", forecast.new_file_size);
+    Ok(())
+}
+
+fn parse_bucket_path(path: &str) -> Vec<&[u8]> {
+    path.split('/').map(str::as_bytes).collect()
+}
+
+fn print_bucket_info(
+    db: Rc<RefCell<ancla::DB>>,
+    path: Option<String>,
+    id: Option<String>,
+    output: &Output,
+) -> Result<(), CliError> {
+    let bucket = match (path, id) {
+        (Some(path), _) => ancla::DB::get_bucket(db, &parse_bucket_path(&path)),
+        (None, Some(id)) => ancla::DB::get_bucket_by_id(db, &ancla::Bucket::parse_id(&id)?),
+        (None, None) => None,
+    };
+    let Some(bucket) = bucket else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+
+    let info = bucket.info();
+    println!("name: {}", output.name(&bucket.name)?);
+    println!("id: {}", info.id);
+    println!("root page id: {}", info.root_page_id);
+    println!("inline: {}", info.is_inline);
+    println!("sequence: {}", info.sequence);
+    println!("child buckets: {}", info.child_buckets);
+    println!("keys: {}", info.keys);
+    println!("depth: {}", info.depth);
+    println!("pages: {}", info.pages);
+    println!("capacity: {}", info.capacity);
+    println!("used: {}", info.used);
+    Ok(())
+}
+
+fn print_tx_analysis(db: Rc<RefCell<ancla::DB>>, output: &Output) -> Result<(), CliError> {
+    let analysis = ancla::DB::analyze_tx(db);
+    for (name, meta) in [("meta0", &analysis.meta0), ("meta1", &analysis.meta1)] {
+        println!(
+            "{}: txid {}, root {}, freelist {}, max page id {}, flags {:#x}",
+            name, meta.txid, meta.root_page_id, meta.freelist_page_id, meta.max_page_id, meta.flags
+        );
+        if !meta.reserved.is_empty() {
+            println!("{}: reserved {}", name, meta.reserved);
+        }
+    }
+    print_changed_pages(&analysis.changed_pages, "(freelist)", output)
+}
+
+// print_changed_pages prints the changed pages with their buckets, and the number
+// of changed pages of each bucket. The pages not in a bucket are labeled as other.
+fn print_changed_pages(
+    pages: &[ancla::ChangedPage],
+    other: &str,
+    output: &Output,
+) -> Result<(), CliError> {
+    println!("changed pages: {}", pages.len());
+
+    let mut buckets: BTreeMap<String, usize> = BTreeMap::new();
+    for page in pages {
+        let bucket = match &page.bucket {
+            Some(path) => output.bucket_path(path)?,
+            None => other.to_string(),
+        };
+        println!("{}, {}", page.id, bucket);
+        *buckets.entry(bucket).or_default() += 1;
+    }
+
+    println!("changed buckets: {}", buckets.len());
+    for (bucket, count) in buckets {
+        println!("{}, {}", bucket, count);
+    }
+    Ok(())
+}
+
+// sorted_pages sorts the pages by id, only the first limit pages are kept when the
+// limit is specified. Otherwise at most sort_buffer pages are kept in memory, see
+// MergedPages.
+fn sorted_pages(
+    pages: impl Iterator<Item = ancla::PageInfo>,
+    limit: Option<usize>,
+    sort_buffer: usize,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<ancla::PageInfo>>>> {
+    let Some(limit) = limit else {
+        return MergedPages::sort(pages, sort_buffer.max(1));
+    };
+
+    // The heap keeps the smallest pages, the biggest one is popped when it's full.
+    let mut heap: BinaryHeap<ancla::PageInfo> = BinaryHeap::with_capacity(limit + 1);
+    for page in pages {
+        heap.push(page);
+        if heap.len() > limit {
+            heap.pop();
+        }
+    }
+    Ok(Box::new(heap.into_sorted_vec().into_iter().map(Ok)))
+}
+
+const PAGE_TYPES: [ancla::PageType; 5] = [
+    ancla::PageType::Meta,
+    ancla::PageType::DataLeaf,
+    ancla::PageType::DataBranch,
+    ancla::PageType::Freelist,
+    ancla::PageType::Free,
+];
+
+// SpilledRun is a sorted run of pages in a temporary file, each page is stored as
+// 7 little-endian u64, the file is removed when it's dropped.
+struct SpilledRun {
+    path: PathBuf,
+    reader: io::BufReader<fs::File>,
+}
+
+impl SpilledRun {
+    fn write(pages: &[ancla::PageInfo], index: usize) -> io::Result<SpilledRun> {
+        let path =
+            std::env::temp_dir().join(format!("anclalet-{}-{}.pages", std::process::id(), index));
+        let mut writer = io::BufWriter::new(fs::File::create(&path)?);
+        for page in pages {
+            let typ = PAGE_TYPES.iter().position(|&typ| typ == page.typ).unwrap();
+            let fields = [
+                page.id,
+                typ as u64,
+                page.overflow,
+                page.capacity,
+                page.used,
+                page.slack,
+                page.parent_page_id.unwrap_or(u64::MAX),
+            ];
+            for field in fields {
+                writer.write_all(&field.to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+        let reader = io::BufReader::new(fs::File::open(&path)?);
+        Ok(SpilledRun { path, reader })
+    }
+}
+
+impl Iterator for SpilledRun {
+    type Item = io::Result<ancla::PageInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut data = [0u8; 56];
+        match self.reader.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        let field = |i: usize| u64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(Ok(ancla::PageInfo {
+            id: field(0),
+            typ: PAGE_TYPES[field(1) as usize],
+            overflow: field(2),
+            capacity: field(3),
+            used: field(4),
+            slack: field(5),
+            parent_page_id: Some(field(6)).filter(|&id| id != u64::MAX),
+        }))
+    }
+}
+
+impl Drop for SpilledRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// MergedPages merges the sorted runs of pages, it's the external sort of the pages
+// which may not fit in memory, only the head of each run is kept in the heap.
+struct MergedPages {
+    runs: Vec<Box<dyn Iterator<Item = io::Result<ancla::PageInfo>>>>,
+    heap: BinaryHeap<Reverse<(ancla::PageInfo, usize)>>,
+}
+
+impl MergedPages {
+    // sort sorts the pages by id, the runs of buffer pages are spilled to the
+    // temporary files, the last run is kept in memory.
+    fn sort(
+        pages: impl Iterator<Item = ancla::PageInfo>,
+        buffer: usize,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<ancla::PageInfo>>>> {
+        let mut runs: Vec<Box<dyn Iterator<Item = io::Result<ancla::PageInfo>>>> = Vec::new();
+        let mut chunk = Vec::new();
+        for page in pages {
+            chunk.push(page);
+            if chunk.len() >= buffer {
+                chunk.sort();
+                runs.push(Box::new(SpilledRun::write(&chunk, runs.len())?));
+                chunk.clear();
+            }
+        }
+        chunk.sort();
+        if runs.is_empty() {
+            return Ok(Box::new(chunk.into_iter().map(Ok)));
+        }
+        debug!(runs = runs.len(), "spilled the pages to sort");
+        runs.push(Box::new(chunk.into_iter().map(Ok)));
+
+        let mut merged = MergedPages {
+            heap: BinaryHeap::with_capacity(runs.len()),
+            runs,
+        };
+        for i in 0..merged.runs.len() {
+            if let Some(page) = merged.runs[i].next() {
+                merged.heap.push(Reverse((page?, i)));
+            }
+        }
+        Ok(Box::new(merged))
+    }
+}
+
+impl Iterator for MergedPages {
+    type Item = io::Result<ancla::PageInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((page, i)) = self.heap.pop()?;
+        match self.runs[i].next() {
+            Some(Ok(next)) => self.heap.push(Reverse((next, i))),
+            Some(Err(e)) => return Some(Err(e)),
+            None => {}
+        }
+        Some(Ok(page))
+    }
+}
+
+// annotate_offset returns the page and the region of the page which the offset
+// falls into, the pages are all the reachable pages keyed by their id.
+fn annotate_offset(
+    db: Rc<RefCell<ancla::DB>>,
+    pages: &BTreeMap<u64, ancla::PageInfo>,
+    layouts: &mut HashMap<u64, ancla::PageLayout>,
+    page_size: u64,
+    offset: u64,
+) -> String {
+    let page_id = offset / page_size;
+    let Some(page) = pages
+        .range(..=page_id)
+        .next_back()
+        .map(|(_, page)| page)
+        .filter(|page| page_id <= page.id + page.overflow)
+    else {
+        return format!("page {}, unreachable", page_id);
+    };
+
+    let location = if page.id == page_id {
+        format!("page {}", page_id)
+    } else {
+        format!("page {} (overflow of {})", page_id, page.id)
+    };
+    if page.typ == ancla::PageType::Free {
+        return format!("{}, free", location);
+    }
+
+    let layout = layouts
+        .entry(page.id)
+        .or_insert_with(|| ancla::DB::page_layout(db, page.id));
+    format!(
+        "{}, {} {}",
+        location,
+        layout.typ,
+        layout.region(offset - page.id * page_size)
+    )
+}
+
+// print_value_formats prints the formats sniffed from the first values of the
+// bucket, the most common format first.
+fn print_value_formats(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    samples: usize,
+    output: &Output,
+) -> Result<(), CliError> {
+    let Some(bucket) = ancla::DB::get_bucket(db, &parse_bucket_path(bucket)) else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+
+    let mut formats: HashMap<ancla::decode::ValueFormat, usize> = HashMap::new();
+    let values = bucket
+        .iter_items(Some(0))
+        .try_iter()
+        .filter_map(|item| match item {
+            Ok(ancla::DbItem::KeyValue { value, .. }) => Some(Ok(value)),
+            Ok(ancla::DbItem::Bucket(_)) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .take(samples);
+    for value in values {
+        let value = value?;
+        *formats
+            .entry(ancla::decode::sniff_value(&value))
+            .or_default() += 1;
+    }
+    let total: usize = formats.values().sum();
+    let mut formats: Vec<_> = formats.into_iter().collect();
+    formats.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    if output.is_csv() {
+        output.csv_header(&["format", "values", "percent"]);
+    }
+    for (format, count) in formats {
+        let percent = format!("{:.2}", count as f64 * 100.0 / total as f64);
+        if output.is_csv() {
+            output.csv_record(&[format.to_string(), count.to_string(), percent]);
+        } else {
+            println!("{}, {} values, {}%", format, count, percent);
+        }
+    }
+    if !output.is_csv() {
+        println!("sampled: {} values", total);
+    }
+    Ok(())
+}
+
+fn print_tree_stats(db: Rc<RefCell<ancla::DB>>, output: &Output) -> Result<(), CliError> {
+    if output.is_csv() {
+        output.csv_header(&[
+            "bucket",
+            "leaves",
+            "min_leaf_depth",
+            "max_leaf_depth",
+            "avg_leaf_depth",
+            "branch_pages",
+            "unbalanced",
+        ]);
+    }
+
+    let mut branching: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut unbalanced = Vec::new();
+    for stats in ancla::DB::tree_stats(db) {
+        let path = output.bucket_path(&stats.bucket)?;
+        let branch_pages: u64 = stats.branching.values().sum();
+        if output.is_csv() {
+            output.csv_record(&[
+                path.clone(),
+                stats.leaves.to_string(),
+                stats.min_leaf_depth.to_string(),
+                stats.max_leaf_depth.to_string(),
+                format!("{:.2}", stats.avg_leaf_depth()),
+                branch_pages.to_string(),
+                stats.unbalanced.len().to_string(),
+            ]);
+        } else {
+            println!(
+                "{}, leaves {}, leaf depth min {} max {} avg {:.2}, branch pages {}",
+                path,
+                stats.leaves,
+                stats.min_leaf_depth,
+                stats.max_leaf_depth,
+                stats.avg_leaf_depth(),
+                branch_pages
+            );
+        }
+
+        for (fanout, pages) in stats.branching {
+            *branching.entry(fanout).or_default() += pages;
+        }
+        unbalanced.extend(stats.unbalanced.into_iter().map(|id| (id, path.clone())));
+    }
+    if output.is_csv() {
+        return Ok(());
+    }
+
+    println!("branching factor:");
+    for (fanout, pages) in branching {
+        println!("  {} children, {} pages", fanout, pages);
+    }
+    for (id, path) in unbalanced {
+        println!("unbalanced: page {} of {}", id, path);
+    }
+    Ok(())
+}
+
+// print_inline_advice prints the buckets whose storage changes after compaction. A
+// bucket stored inline takes the bytes of it's inline page in the parent's leaf
+// page, otherwise it takes it's own pages.
+fn print_inline_advice(db: Rc<RefCell<ancla::DB>>, output: &Output) -> Result<(), CliError> {
+    let page_size = ancla::DB::info(db.clone()).page_size as u64;
+    if output.is_csv() {
+        output.csv_header(&[
+            "bucket",
+            "inline",
+            "fits_inline",
+            "inline_size",
+            "saved_bytes",
+        ]);
+    }
+
+    let mut total: i64 = 0;
+    for item in ancla::DB::iter_items(db, None).try_iter() {
+        let ancla::DbItem::Bucket(bucket) = item? else {
+            continue;
+        };
+        let info = bucket.info();
+        let fits_inline = info.fits_inline(page_size);
+        let saved = match (info.is_inline, fits_inline) {
+            (false, true) => info.capacity as i64 - info.inline_size as i64,
+            (true, false) => info.inline_size as i64 - page_size as i64,
+            _ => continue,
+        };
+        total += saved;
+
+        let path = output.bucket_path(bucket.path())?;
+        if output.is_csv() {
+            output.csv_record(&[
+                path,
+                info.is_inline.to_string(),
+                fits_inline.to_string(),
+                info.inline_size.to_string(),
+                saved.to_string(),
+            ]);
+        } else {
+            println!(
+                "{}, inline {}, fits inline {}, inline size {}, saved {} bytes",
+                path, info.is_inline, fits_inline, info.inline_size, saved
+            );
+        }
+    }
+    if !output.is_csv() {
+        println!("total saved: {} bytes", total);
+    }
+    Ok(())
+}
+
+// key_prefix returns the first depth segments of the key with the delimiter after
+// them, or the whole key if it has fewer segments.
+fn key_prefix<'a>(key: &'a [u8], delimiter: &[u8], depth: usize) -> &'a [u8] {
+    if delimiter.is_empty() {
+        return key;
+    }
+
+    let mut segments = 0;
+    let mut i = 0;
+    while i + delimiter.len() <= key.len() {
+        if key[i..].starts_with(delimiter) {
+            segments += 1;
+            i += delimiter.len();
+            if segments == depth {
+                return &key[..i];
+            }
+        } else {
+            i += 1;
+        }
+    }
+    key
+}
+
+fn print_prefixes(
+    db: Rc<RefCell<ancla::DB>>,
+    bucket: &str,
+    delimiter: &[u8],
+    depth: usize,
+    output: &Output,
+) -> Result<(), CliError> {
+    let Some(bucket) = ancla::DB::get_bucket(db, &parse_bucket_path(bucket)) else {
+        return Err(CliError::NotFound("bucket not found".to_string()));
+    };
+
+    if output.is_csv() {
+        output.csv_header(&["prefix", "keys", "value_bytes"]);
+    }
+    let print = |prefix: &[u8], keys: u64, value_bytes: u64| {
+        let prefix = prefix.escape_ascii().to_string();
+        if output.is_csv() {
+            output.csv_record(&[prefix, keys.to_string(), value_bytes.to_string()]);
+        } else {
+            println!("{}, {}, {}", prefix, keys, value_bytes);
+        }
+    };
+    // The keys with the same prefix are adjacent because the keys are sorted, so
+    // only the current prefix is kept.
+    let mut current: Option<(Vec<u8>, u64, u64)> = None;
+    for item in bucket.iter_items(Some(0)).try_iter() {
+        let ancla::DbItem::KeyValue { key, value, .. } = item? else {
+            continue;
+        };
+        let prefix = key_prefix(&key, delimiter, depth);
+        match &mut current {
+            Some((current, keys, value_bytes)) if current.as_slice() == prefix => {
+                *keys += 1;
+                *value_bytes += value.len() as u64;
+            }
+            _ => {
+                if let Some((prefix, keys, value_bytes)) = current.take() {
+                    print(&prefix, keys, value_bytes);
+                }
+                current = Some((prefix.to_vec(), 1, value.len() as u64));
+            }
+        }
+    }
+    if let Some((prefix, keys, value_bytes)) = current {
+        print(&prefix, keys, value_bytes);
+    }
+    Ok(())
+}
+
+// verify_snapshot_hash prints the result of the snapshot hash, the meta of etcd is
+// printed after the database is opened, which validates the meta pages.
+fn verify_snapshot_hash(path: &str, is_member: bool) -> Result<(), CliError> {
+    if path == "-" || path.contains("://") || is_member {
+        return Err(CliError::Usage(
+            "the snapshot must be a local file".to_string(),
+        ));
+    }
+
+    match ancla::etcd::verify_snapshot_hash(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("open {}: {}", path, e)))?
+    {
+        ancla::etcd::SnapshotHash::Valid(hash) => println!("hash: {} (valid)", hash),
+        ancla::etcd::SnapshotHash::Missing => println!("hash: missing"),
+        ancla::etcd::SnapshotHash::Mismatch { expected, actual } => {
+            return Err(CliError::Corruption(format!(
+                "snapshot hash mismatch, expect {}, got {}",
+                expected, actual
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn print_etcd_objects(db: Rc<RefCell<ancla::DB>>, prefix: &str, output: &Output) {
+    let decoders = ancla::etcd::Decoders::default();
+    if output.is_csv() {
+        output.csv_header(&["key", "object", "api_version", "kind", "mod_revision"]);
+    }
+    for kv in ancla::etcd::latest_key_values(db, prefix.as_bytes()) {
+        let object = decoders.decode(&kv.key, &kv.value).unwrap_or_default();
+        let name = match object.namespace {
+            Some(namespace) => format!("{}/{}", namespace, object.name),
+            None => object.name,
+        };
+        let record = [
+            kv.key.escape_ascii().to_string(),
+            name,
+            object.api_version,
+            object.kind,
+            kv.mod_revision.to_string(),
+        ];
+        if output.is_csv() {
+            output.csv_record(&record);
+        } else {
+            println!("{}", record.join(", "));
+        }
+    }
+}
+
+fn print_etcd_meta(db: Rc<RefCell<ancla::DB>>) {
+    let info = ancla::DB::info(db.clone());
+    println!("meta: valid, txid {}", info.txid);
+    let meta = ancla::etcd::EtcdMeta::read(db);
+    let print = |name: &str, value: Option<String>| {
+        println!("{}: {}", name, value.as_deref().unwrap_or("unknown"));
+    };
+    print(
+        "consistent index",
+        meta.consistent_index.map(|index| index.to_string()),
+    );
+    print("term", meta.term.map(|term| term.to_string()));
+    print(
+        "revision",
+        meta.revision.map(|revision| revision.main.to_string()),
+    );
+    print(
+        "compact revision",
+        meta.compact_revision
+            .map(|revision| revision.main.to_string()),
+    );
+}
+
+// export_writer returns the writer of the export, the file is compressed by it's
+// extension.
+fn export_writer(to: Option<String>) -> io::Result<Box<dyn Write>> {
+    let Some(to) = to else {
+        return Ok(Box::new(io::BufWriter::new(io::stdout().lock())));
+    };
+
+    let file = fs::File::create(&to)
+        .map_err(|e| io::Error::new(e.kind(), format!("create {}: {}", to, e)))?;
+    let file = io::BufWriter::new(file);
+    if to.ends_with(".gz") {
+        return Ok(Box::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        )));
+    }
+    if to.ends_with(".zst") {
+        return Ok(Box::new(zstd::Encoder::new(file, 0)?.auto_finish()));
+    }
+    Ok(Box::new(file))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScrubTarget {
+    Keys,
+    Values,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScrubMode {
+    Placeholder,
+    Hash,
+}
+
+impl ScrubMode {
+    fn apply<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        match self {
+            ScrubMode::Placeholder => Cow::Owned(vec![b'*'; data.len()]),
+            ScrubMode::Hash => Cow::Owned(format!("{:x}", Sha256::digest(data)).into_bytes()),
+        }
+    }
+}
+
+// ScrubRule is a rule of `export --scrub`, it's formatted as `<target>[=<mode>]`,
+// e.g. `keys=hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ScrubRule {
+    target: ScrubTarget,
+    mode: ScrubMode,
+}
+
+impl std::str::FromStr for ScrubRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, mode) = s.split_once('=').unwrap_or((s, "placeholder"));
+        let target = match target {
+            "keys" => ScrubTarget::Keys,
+            "values" => ScrubTarget::Values,
+            _ => {
+                return Err(format!(
+                    "unknown scrub target {}, expect keys or values",
+                    target
+                ))
+            }
+        };
+        let mode = match mode {
+            "placeholder" => ScrubMode::Placeholder,
+            "hash" => ScrubMode::Hash,
+            _ => {
+                return Err(format!(
+                    "unknown scrub mode {}, expect placeholder or hash",
+                    mode
+                ))
+            }
+        };
+        Ok(ScrubRule { target, mode })
+    }
+}
+
+// scrub applies the last rule of the target to the data, it's unchanged if there
+// isn't a rule of the target.
+fn scrub<'a>(rules: &[ScrubRule], target: ScrubTarget, data: &'a [u8]) -> Cow<'a, [u8]> {
+    match rules.iter().rev().find(|rule| rule.target == target) {
+        Some(rule) => rule.mode.apply(data),
+        None => Cow::Borrowed(data),
+    }
+}
+
+fn export_ndjson(
+    db: Rc<RefCell<ancla::DB>>,
+    buckets: &[String],
+    to: Option<String>,
+    scrub_rules: &[ScrubRule],
+    output: &Output,
+) -> Result<(), CliError> {
+    let mut patterns = Vec::with_capacity(buckets.len());
+    for bucket in buckets {
+        let pattern: ancla::BucketPattern = bucket.parse()?;
+        if !pattern.has_wildcard()
+            && ancla::DB::get_bucket(db.clone(), &parse_bucket_path(bucket)).is_none()
+        {
+            return Err(CliError::NotFound(format!("bucket {} not found", bucket)));
+        }
+        patterns.push(pattern);
+    }
+    // The matched bucket is exported before it's items, the ancestors of it are
+    // not exported.
+    let options = ancla::ReadOptions::builder()
+        .buckets_filter(patterns)
+        .build();
+    let items = ancla::DB::iter_items_with(db, options);
+
+    let mut writer = export_writer(to)?;
+    for item in items.try_iter() {
+        match item? {
+            ancla::DbItem::Bucket(bucket) => writeln!(
+                writer,
+                "{}",
+                serde_json::json!({
+                    "type": "bucket",
+                    "bucket": output.bucket_path(bucket.path())?,
+                })
+            )?,
+            ancla::DbItem::KeyValue { bucket, key, value } => writeln!(
+                writer,
+                "{}",
+                serde_json::json!({
+                    "type": "kv",
+                    "bucket": output.bucket_path(&bucket)?,
+                    "key": scrub(scrub_rules, ScrubTarget::Keys, &key)
+                        .escape_ascii()
+                        .to_string(),
+                    "value": scrub(scrub_rules, ScrubTarget::Values, &value)
+                        .escape_ascii()
+                        .to_string(),
+                })
+            )?,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_page_path(
+    db: Rc<RefCell<ancla::DB>>,
+    page_id: u64,
+    output: &Output,
+) -> Result<(), CliError> {
+    let max_page_id = ancla::DB::info(db.clone()).max_page_id;
+    if page_id >= max_page_id {
+        return Err(CliError::NotFound(format!(
+            "page {} is out of the database, the max page id is {}",
+            page_id, max_page_id
+        )));
+    }
+
+    let (root_page_id, steps, overflow_of) = match ancla::DB::page_path(db, page_id) {
+        ancla::PagePath::Tree {
+            root_page_id,
+            steps,
+            overflow_of,
+        } => (root_page_id, steps, overflow_of),
+        path => {
+            let status = match path {
+                ancla::PagePath::Meta => "a meta page",
+                ancla::PagePath::Freelist => "the freelist page",
+                ancla::PagePath::Free { pending: true } => "pending",
+                ancla::PagePath::Free { pending: false } => "free",
+                _ => "unreachable",
+            };
+            println!("page {} is {}", page_id, status);
+            return Ok(());
+        }
+    };
+
+    println!("root: {}", root_page_id);
+    for step in steps {
+        println!(
+            "page {}, element {}, {} {} -> page {}",
+            step.page_id,
+            step.index,
+            step.kind,
+            output.key_format.render(&step.key),
+            step.child_page_id
+        );
+    }
+    if let Some(owner_page_id) = overflow_of {
+        println!("overflow of: {}", owner_page_id);
+    }
+    Ok(())
+}
+
+fn print_location(
+    db: Rc<RefCell<ancla::DB>>,
+    offset: u64,
+    output: &Output,
+) -> Result<(), CliError> {
+    let location = ancla::DB::locate(db, offset);
+    println!("offset: {}", location.offset);
+    println!("page id: {}", location.page_id);
+    let Some(owner_page_id) = location.owner_page_id else {
+        println!("unreachable: true");
+        return Ok(());
+    };
+    if owner_page_id != location.page_id {
+        println!("overflow of: {}", owner_page_id);
+    }
+    if let Some(typ) = location.typ {
+        println!("type: {}", typ);
+    }
+    if let Some(region) = location.region {
+        println!("region: {}", region);
+    }
+    if let Some(index) = location.element_index {
+        println!("element: {}", index);
+    }
+    if let Some(bucket) = location.bucket {
+        println!("bucket: {}", output.bucket_path(&bucket)?);
+    }
+    Ok(())
+}
+
+fn print_dump(db: Rc<RefCell<ancla::DB>>, offset: u64, length: u64) {
+    let offset = offset - offset % 16;
+    let data = ancla::DB::read_range(db.clone(), offset, length);
+    let page_size = ancla::DB::info(db.clone()).page_size as u64;
+    let pages: BTreeMap<u64, ancla::PageInfo> = ancla::DB::iter_pages(db.clone())
+        .map(|page| (page.id, page))
+        .collect();
+    let mut layouts: HashMap<u64, ancla::PageLayout> = HashMap::new();
+
+    for (i, line) in data.chunks(16).enumerate() {
+        let line_offset = offset + i as u64 * 16;
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!(
+            "{:08x}  {:<47}  |{:<16}|  {}",
+            line_offset,
+            hex.join(" "),
+            ascii,
+            annotate_offset(db.clone(), &pages, &mut layouts, page_size, line_offset)
+        );
+    }
+}
+
+fn print_info(db: Rc<RefCell<ancla::DB>>, page_types: bool, output: &Output) {
+    let info = if page_types {
+        let is_terminal = io::stderr().is_terminal();
+        let info = ancla::DB::info_with_page_types(db, |counted, max_page_id| {
+            if is_terminal && (counted % 1024 == 0 || counted == max_page_id) {
+                eprint!("\rcounted {} of {} pages", counted, max_page_id);
+            }
+        });
+        if is_terminal {
+            eprintln!();
+        }
+        info
+    } else {
+        ancla::DB::info(db)
+    };
+    if output.format == OutputFormat::Json {
+        println!("{}", to_json(&info));
+        return;
+    }
+
+    println!("page size: {}", info.page_size);
+    println!("version: {}", info.version);
+    println!("txid: {}", info.txid);
+    println!("root page id: {}", info.root_page_id);
+    println!("freelist page id: {}", info.freelist_page_id);
+    println!("max page id: {}", info.max_page_id);
+    if let Some(counts) = info.page_types {
+        println!("meta pages: {}", counts.meta);
+        println!("freelist pages: {}", counts.freelist);
+        println!("branch pages: {}", counts.branch);
+        println!("leaf pages: {}", counts.leaf);
+        println!("free pages: {}", counts.free);
+        println!("overflow pages: {}", counts.overflow);
+        println!("unreachable pages: {}", counts.unreachable);
+    }
+}
+
+// resume_diagnosis runs the doctor from the checkpoint file, it returns None if
+// the check is paused, the progress is saved to the file.
+fn resume_diagnosis(
+    db: Rc<RefCell<ancla::DB>>,
+    path: &Path,
+    max_pages: Option<u64>,
+    max_seconds: Option<u64>,
+    output: &Output,
+) -> Result<Option<ancla::Diagnosis>, CliError> {
+    let checkpoint = match fs::read(path) {
+        Ok(data) => Some(ancla::DoctorCheckpoint::decode(&data)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(CliError::Io(io::Error::new(
+                e.kind(),
+                format!("read {}: {}", path.display(), e),
+            )))
+        }
+    };
+
+    let start = Instant::now();
+    let mut pages = 0;
+    let pause = || {
+        pages += 1;
+        max_pages.is_some_and(|max_pages| pages > max_pages)
+            || max_seconds.is_some_and(|max_seconds| start.elapsed().as_secs() >= max_seconds)
+    };
+    let checkpoint = match ancla::DB::doctor_resume(db, checkpoint, pause)? {
+        ancla::DoctorProgress::Done(diagnosis) => {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(CliError::Io(e)),
+                _ => {}
+            }
+            return Ok(Some(diagnosis));
+        }
+        ancla::DoctorProgress::Paused(checkpoint) => checkpoint,
+    };
+
+    // The checkpoint is replaced at once, so it isn't broken if it's interrupted.
+    let temp = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&temp, checkpoint.encode())?;
+    fs::rename(&temp, path)?;
+    if output.quiet {
+        // The exit code is the only output.
+    } else if output.format == OutputFormat::Json {
+        println!(
+            r#"{{"paused":true,"checked_pages":{},"queued_pages":{},"findings":{}}}"#,
+            checkpoint.checked_pages(),
+            checkpoint.queued_pages(),
+            checkpoint.findings().len()
+        );
+    } else {
+        println!(
+            "paused: {} pages checked, {} pages queued, {} findings so far",
+            checkpoint.checked_pages(),
+            checkpoint.queued_pages(),
+            checkpoint.findings().len()
+        );
+    }
+    Ok(None)
+}
+
+fn print_diagnosis(
+    diagnosis: &ancla::Diagnosis,
+    summary: bool,
+    output: &Output,
+) -> Result<(), CliError> {
+    if output.quiet {
+        // The exit code is the only output.
+    } else if summary {
+        let count = |severity| {
+            diagnosis
+                .findings
+                .iter()
+                .filter(|finding| finding.severity == severity)
+                .count()
+        };
+        println!(
+            "{}: {} critical, {} warning, {} info, {} reachable pages, {} leaked pages",
+            if diagnosis.is_healthy() {
+                "healthy"
+            } else {
+                "corrupted"
+            },
+            count(ancla::Severity::Critical),
+            count(ancla::Severity::Warning),
+            count(ancla::Severity::Info),
+            diagnosis.reachable_pages,
+            diagnosis.leaked_pages
+        );
+    } else if output.format == OutputFormat::Json {
+        println!("{}", to_json(&diagnosis));
+    } else if output.format == OutputFormat::Yaml {
+        print!("{}", to_yaml(&diagnosis.check_report()));
+    } else {
+        for finding in &diagnosis.findings {
+            println!("{}: {}", output.severity(finding.severity), finding.message);
+            println!("  {}", finding.suggestion);
+        }
+        if !diagnosis.complete {
+            println!("(stopped at the first critical finding)");
+        }
+        println!(
+            "meta: {}",
+            diagnosis
+                .meta_page_id
+                .map_or_else(|| "none".to_string(), |id| id.to_string())
+        );
+        println!("max page id: {}", diagnosis.max_page_id);
+        println!("reachable pages: {}", diagnosis.reachable_pages);
+        println!("free pages: {}", diagnosis.free_pages);
+        println!("overflow pages: {}", diagnosis.overflow_pages);
+        println!("leaked pages: {}", diagnosis.leaked_pages);
+    }
+
+    if !diagnosis.is_healthy() {
+        return Err(CliError::Corruption(
+            "the database is corrupted".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn print_bucket_hashes(bucket: &ancla::Bucket, output: &Output) -> Result<(), CliError> {
+    println!(
+        "{}, {:016x}",
+        output.bucket_path(bucket.path())?,
+        bucket.hash()
+    );
+    for child_bucket in bucket.iter_buckets() {
+        print_bucket_hashes(&child_bucket, output)?;
+    }
+    Ok(())
+}
+
+fn print_hashes(db: Rc<RefCell<ancla::DB>>, output: &Output) -> Result<(), CliError> {
+    let mut pages: Vec<ancla::PageInfo> = ancla::DB::iter_pages(db.clone()).collect();
+    pages.sort();
+    for page in pages {
+        // The content of free pages is meaningless.
+        if page.typ == ancla::PageType::Free {
+            continue;
+        }
+        let hash = ancla::DB::page_hash(db.clone(), page.id);
+        println!("{}, {}, {:016x}", page.id, page.typ, hash);
+    }
+
+    for bucket in ancla::DB::iter_buckets(db) {
+        print_bucket_hashes(&bucket, output)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    // The output is often piped to head, so the closed stdout ends the process
+    // quietly as the other unix tools do, instead of the panic of println.
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    let mut cli = match Command::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // The help and version are printed by the error too.
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::from(CliError::Usage(String::new()).exit_code())
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let color = if cli.no_color {
+        ColorChoice::Never
+    } else {
+        cli.color
+    };
+    let stderr_color = color.enabled(&io::stderr());
+    if let Some(session) = cli.record.take() {
+        return record_session(&session, &cli.db).unwrap_or_else(|e| {
+            eprintln!("{}: {}", paint("error", RED, stderr_color), e);
+            ExitCode::from(e.exit_code())
+        });
+    }
+
+    if cli.endian.is_none() {
+        if is_target_little_endian() {
+            cli.endian = Some(Endian::Little);
+        } else {
+            cli.endian = Some(Endian::Big);
+        }
+    }
+
+    let quiet = matches!(cli.command, SubCommand::Doctor { quiet: true, .. });
+    // The warnings are not logged either if it's quiet.
+    if !quiet {
+        init_tracing(cli.verbose, &cli.log_format, stderr_color);
+    }
+    debug!(?cli, os_page_size = page_size::get(), "parsed command");
+
+    let metrics_dump = cli.metrics_dump;
+    let output = Output {
+        format: cli.output,
+        header: !cli.no_header,
+        key_format: cli.key_format,
+        name_encoding: cli.name_encoding,
+        stats: cli.stats,
+        quiet,
+        color: color.enabled(&io::stdout()),
+    };
+    let result = open_and_run(cli, &output);
+    if metrics_dump {
+        eprint!("{}", ancla::metrics::metrics().render_prometheus());
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if output.quiet {
+                // The exit code is the only output.
+            } else if output.format == OutputFormat::Json {
+                eprintln!("{}", e.to_json());
+            } else {
+                eprintln!("{}: {}", paint("error", RED, stderr_color), e);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn open_and_run(cli: Command, output: &Output) -> Result<(), CliError> {
+    if let SubCommand::Compact {
+        bucket,
+        dry_run: true,
+        ..
+    } = &cli.command
+    {
+        return print_compact_forecast(&cli.db, bucket);
+    }
+    if let SubCommand::Replay { session } = &cli.command {
+        return replay_session(&cli.db, session, output);
+    }
+    if let Some(yes) = write_confirmation(&cli.command) {
+        if cli.member.is_some() {
+            return Err(CliError::Usage(
+                "the database in an archive can't be modified".to_string(),
+            ));
+        }
+        return run_write(&cli.db, cli.command, yes);
+    }
+
+    let options = || {
+        ancla::AnclaOptions::builder()
+            .page_size(cli.page_size)
+            .force_page_size(cli.force_page_size)
+            .compat(cli.compat)
+            .disk_cache_dir(cli.page_cache_dir.clone())
+            .limits(if cli.trusted_input {
+                ancla::ReadLimits::unlimited()
+            } else {
+                ancla::ReadLimits::default()
+            })
+            .endian(
+                cli.endian
+                    .clone()
+                    .map_or_else(ancla::Endian::native, From::from),
+            )
+    };
+    // open_file opens the database file with the options of the command, the other
+    // database of diff and merge is opened with it too.
+    let open_file = |path: String| -> Result<Rc<RefCell<ancla::DB>>, CliError> {
+        // The file is opened here, so the error of it is reported as an io error.
+        let source = ancla::FileSource::open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("open {}: {}", path, e)))?;
+        Ok(ancla::DB::open_from_source(
+            options().db_path(path).build(),
+            source,
+        ))
+    };
+    if let SubCommand::Etcd(EtcdArgs {
+        command: EtcdCommand::VerifySnapshot,
+    }) = &cli.command
+    {
+        verify_snapshot_hash(&cli.db, cli.member.is_some())?;
+    }
+
+    if cli.db == "-" {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        return run(
+            ancla::DB::open_from_bytes(options().build(), data),
+            cli.command,
+            output,
+            &open_file,
+        );
+    }
+
+    #[cfg(feature = "http")]
+    if cli.db.starts_with("http://") || cli.db.starts_with("https://") {
+        let source = ancla::HttpSource::new(&cli.db);
+        return run(
+            ancla::DB::open_from_source(options().build(), source),
+            cli.command,
+            output,
+            &open_file,
+        );
+    }
+
+    if let Some(member) = &cli.member {
+        let source = ancla::ArchiveSource::open(&cli.db, member)
+            .map_err(|e| io::Error::new(e.kind(), format!("open {}: {}", cli.db, e)))?;
+        return run(
+            ancla::DB::open_from_source(options().build(), source),
+            cli.command,
+            output,
+            &open_file,
+        );
+    }
+
+    if ancla::FileSource::is_write_locked(&cli.db)? {
+        if !cli.read_dirty {
+            return Err(CliError::Usage(format!(
+                "{} is locked by a running writer, use --read-dirty to read a snapshot of it",
+                cli.db
+            )));
+        }
+        warn!(
+            db = cli.db,
+            "the database is locked by a running writer, read a snapshot of it"
+        );
+        let options = options().db_path(cli.db).snapshot_before_read(true).build();
+        return run(ancla::DB::build(options), cli.command, output, &open_file);
+    }
+
+    run(open_file(cli.db)?, cli.command, output, &open_file)
+}
+
+fn init_tracing(verbose: bool, log_format: &LogFormat, color: bool) {
+    let level = if verbose {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::WARN
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(color)
+        .with_writer(io::stderr);
+    match log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+// OpenFile opens the other database files of the command, e.g. the `--with` of
+// diff, with the options of the command.
+type OpenFile<'a> = dyn Fn(String) -> Result<Rc<RefCell<ancla::DB>>, CliError> + 'a;
+
+fn run(
+    db: Rc<RefCell<ancla::DB>>,
+    command: SubCommand,
+    output: &Output,
+    open_file: &OpenFile,
+) -> Result<(), CliError> {
+    let start = Instant::now();
+    let result = run_command(db.clone(), command, output, open_file);
+    if output.stats {
+        let stats = ancla::DB::read_stats(db);
+        eprintln!(
+            "stats: pages read {}, bytes read {}, cache hits {}, cache misses {}, elapsed {:?}",
+            stats.pages_read,
+            stats.bytes_read,
+            stats.cache_hits,
+            stats.cache_misses,
+            start.elapsed()
+        );
+    }
+    result
+}
+
+fn run_command(
+    db: Rc<RefCell<ancla::DB>>,
+    command: SubCommand,
+    output: &Output,
+    open_file: &OpenFile,
+) -> Result<(), CliError> {
+    let _span = info_span!("command", ?command).entered();
+    // The page size is checked before the command, so the mismatch is reported as
+    // an usage error.
+    ancla::DB::try_info(db.clone())?;
+    match command {
+        SubCommand::Buckets(BucketsArgs {
+            depth,
+            command: None,
+        }) if output.is_csv() => print_buckets_csv(db, depth, output)?,
+        SubCommand::Buckets(BucketsArgs {
+            depth,
+            command: None,
+        }) => {
+            let (buckets, result) = iter_buckets(db, depth);
+            print_buckets(&buckets, 0, output)?;
+            if let Err(e) = &result {
+                println!("(partial, the buckets after it can't be read: {})", e);
+            }
+            result?;
+        }
+        SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Show { path, id }),
+            ..
+        }) => print_bucket_info(db, path, id, output)?,
+        SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Export { bucket, to }),
+            ..
+        }) => ancla::WriteTx::export_bucket(db, &parse_bucket_path(&bucket), &to)?,
+        SubCommand::Pages(PagesArgs {
+            start_id,
+            limit,
+            types,
+            sort_buffer,
+            command: None,
+        }) => {
+            let filter = ancla::PageFilter::builder()
+                .start_id(start_id)
+                .types(types)
+                .build();
+            let pages = ancla::DB::iter_pages_filtered(db, filter);
+            print_pages(sorted_pages(pages, limit, sort_buffer)?, output)?;
+        }
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Hash),
+            ..
+        }) => print_hashes(db, output)?,
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Elements),
+            ..
+        }) => print_page_elements(db, output),
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Items { page_id }),
+            ..
+        }) => print_page_items(db, page_id, output)?,
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Path { page_id }),
+            ..
+        }) => print_page_path(db, page_id, output)?,
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Freelist),
+            ..
+        }) => print_freelist(db, output),
+        SubCommand::Pages(PagesArgs {
+            command: Some(PagesCommand::Unreachable),
+            ..
+        }) => print_unreachable_pages(db, output),
+        SubCommand::Analyze(AnalyzeArgs {
+            command: AnalyzeCommand::Tx,
+        }) => print_tx_analysis(db, output)?,
+        SubCommand::Analyze(AnalyzeArgs {
+            command: AnalyzeCommand::Inline,
+        }) => print_inline_advice(db, output)?,
+        SubCommand::Analyze(AnalyzeArgs {
+            command: AnalyzeCommand::Tree,
+        }) => print_tree_stats(db, output)?,
+        SubCommand::Analyze(AnalyzeArgs {
+            command: AnalyzeCommand::Values { bucket, samples },
+        }) => print_value_formats(db, &bucket, samples, output)?,
+        SubCommand::Analyze(AnalyzeArgs {
+            command:
+                AnalyzeCommand::Prefixes {
+                    bucket,
+                    delimiter,
+                    depth,
+                },
+        }) => print_prefixes(db, &bucket, delimiter.as_bytes(), depth, output)?,
+        SubCommand::Etcd(EtcdArgs {
+            command: EtcdCommand::VerifySnapshot,
+        }) => print_etcd_meta(db),
+        SubCommand::Export { buckets, to, scrub } => {
+            export_ndjson(db, &buckets, to, &scrub, output)?
+        }
+        SubCommand::Sample {
+            bucket,
+            n,
+            seed,
+            anonymize,
+        } => print_sample(db, &bucket, n, seed, anonymize, output)?,
+        SubCommand::Dump(DumpArgs { offset, length }) => print_dump(db, offset, length),
+        SubCommand::Info { page_types } => print_info(db, page_types, output),
+        SubCommand::Locate { offset } => print_location(db, offset, output)?,
+        SubCommand::Doctor {
+            fail_fast,
+            summary,
+            resume,
+            max_pages,
+            max_seconds,
+            ..
+        } => {
+            let diagnosis = match resume {
+                Some(path) => match resume_diagnosis(db, &path, max_pages, max_seconds, output)? {
+                    Some(diagnosis) => diagnosis,
+                    None => return Ok(()),
+                },
+                None if fail_fast => ancla::DB::doctor_fail_fast(db),
+                None => ancla::DB::doctor(db),
+            };
+            print_diagnosis(&diagnosis, summary, output)?
+        }
+        SubCommand::Diff { with, .. } => {
+            let old = open_file(with)?;
+            print_changed_pages(
+                &ancla::DB::diff_pages(old, db)?,
+                "(not in a bucket)",
+                output,
+            )?;
+        }
+        SubCommand::Merge { with, to, conflict } => {
+            let theirs = open_file(with)?;
+            ancla::WriteTx::merge(db, theirs, &to, conflict)?;
+        }
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::List { bucket, list },
+        }) => print_kvs(db, &bucket, &list, output)?,
+        SubCommand::Kv(KvArgs {
+            command:
+                KvCommand::Get {
+                    bucket,
+                    key,
+                    raw,
+                    out,
+                },
+        }) => print_value(db, &bucket, &key, raw, out)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Find { key, first },
+        }) => print_key_matches(db, &key, first, output)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Locate { bucket, key },
+        }) => print_key_location(db, &bucket, &key, output)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::First { bucket },
+        }) => print_edge_key(db, &bucket, false, output)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Last { bucket },
+        }) => print_edge_key(db, &bucket, true, output)?,
+        SubCommand::Kv(KvArgs {
+            command:
+                KvCommand::Count {
+                    bucket: Some(bucket),
+                    estimate: true,
+                    max_error,
+                    ..
+                },
+        }) => print_key_count_estimate(db, &bucket, max_error, output)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Count {
+                bucket, recursive, ..
+            },
+        }) => print_key_counts(db, bucket, recursive, output)?,
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::EtcdList { prefix },
+        }) => print_etcd_objects(db, &prefix, output),
+        SubCommand::Kv(KvArgs {
+            command: KvCommand::Put { .. } | KvCommand::Delete { .. },
+        })
+        | SubCommand::Buckets(BucketsArgs {
+            command: Some(BucketsCommand::Create { .. } | BucketsCommand::Delete { .. }),
+            ..
+        })
+        | SubCommand::Compact { .. } => unreachable!("the write commands are run by run_write"),
+        SubCommand::Replay { .. } => unreachable!("the session is replayed by replay_session"),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn db_arg_index_skips_option_values() {
+        let args = args(&["--page-cache-dir", "a.db", "-vp4096", "a.db", "info"]);
+        assert_eq!(db_arg_index(&args, "a.db"), Some(3));
+    }
+
+    #[test]
+    fn db_arg_index_not_found() {
+        assert_eq!(db_arg_index(&args(&["a.db", "info"]), "b.db"), None);
+    }
+
+    // dump_database returns the database of a bucket with a small and a large value,
+    // the large value is stored in the overflow pages.
+    // The database is written to a temporary file named by the test.
+    fn dump_database(name: &str) -> Rc<RefCell<ancla::DB>> {
+        let path = std::env::temp_dir().join(format!("anclalet-{}-{}.db", name, process::id()));
+        let path = path.to_str().unwrap();
+        let mut tx = ancla::WriteTx::create(path, 4096).unwrap();
+        tx.create_bucket(&[b"b"]).unwrap();
+        tx.put(&[b"b"], b"key", b"value").unwrap();
+        tx.put(&[b"b"], b"large", &[b'x'; 10000]).unwrap();
+        tx.commit().unwrap();
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        ancla::DB::open_from_bytes(ancla::AnclaOptions::builder().build(), data)
+    }
+
+    #[test]
+    fn annotate_offset_regions() {
+        let db = dump_database("annotate-offset-regions");
+        let pages: BTreeMap<u64, ancla::PageInfo> = ancla::DB::iter_pages(db.clone())
+            .map(|page| (page.id, page))
+            .collect();
+        let mut layouts = HashMap::new();
+        let mut annotate = |offset| annotate_offset(db.clone(), &pages, &mut layouts, 4096, offset);
+
+        assert_eq!(annotate(0), "page 0, meta header");
+        assert_eq!(annotate(4096 + 16), "page 1, meta meta");
+        assert_eq!(annotate(4096 + 1024), "page 1, meta unused");
+
+        let key = ancla::DB::locate_key(db.clone(), &[b"b"], b"key").unwrap();
+        let leaf = key.page_id;
+        assert_eq!(annotate(leaf * 4096), format!("page {}, leaf header", leaf));
+        assert_eq!(
+            annotate(leaf * 4096 + 16),
+            format!("page {}, leaf elements", leaf)
+        );
+        assert_eq!(annotate(key.key_offset), format!("page {}, leaf kv", leaf));
+
+        let large = ancla::DB::locate_key(db.clone(), &[b"b"], b"large").unwrap();
+        assert_eq!(large.page_id, leaf);
+        assert_eq!(
+            annotate(large.value_offset + 5000),
+            format!("page {} (overflow of {}), leaf kv", leaf + 1, leaf)
+        );
+
+        let max_page_id = ancla::DB::info(db.clone()).max_page_id;
+        assert_eq!(
+            annotate((max_page_id + 1) * 4096),
+            format!("page {}, unreachable", max_page_id + 1)
+        );
+    }
+
+    #[test]
+    fn annotate_offset_follows_locate() {
+        let db = dump_database("annotate-offset-follows-locate");
+        let pages: BTreeMap<u64, ancla::PageInfo> = ancla::DB::iter_pages(db.clone())
+            .map(|page| (page.id, page))
+            .collect();
+        let mut layouts = HashMap::new();
+        let max_page_id = ancla::DB::info(db.clone()).max_page_id;
+        for offset in (0..(max_page_id + 1) * 4096).step_by(16) {
+            // The annotation of each line is the page and region located by the library.
+            let location = ancla::DB::locate(db.clone(), offset);
+            let page = match location.owner_page_id {
+                Some(owner) if owner != location.page_id => {
+                    format!("page {} (overflow of {})", location.page_id, owner)
+                }
+                _ => format!("page {}", location.page_id),
+            };
+            let expected = match (location.typ, location.region) {
+                (None, _) => format!("{}, unreachable", page),
+                (Some(ancla::PageType::Free), _) => format!("{}, free", page),
+                (Some(typ), Some(region)) => format!("{}, {} {}", page, typ, region),
+                (Some(typ), None) => panic!("no region of the {} page at {}", typ, offset),
+            };
+            assert_eq!(
+                annotate_offset(db.clone(), &pages, &mut layouts, 4096, offset),
+                expected,
+                "offset {}",
+                offset
+            );
+        }
+    }
+}
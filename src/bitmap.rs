@@ -0,0 +1,194 @@
+use std::fmt;
+
+// PgidBitmap is a set of page ids which takes 1 bit per page up to the largest id,
+// it's used instead of the hash sets to track the pages of the huge databases. The
+// bitmap grows to the largest inserted id, so the ids should be bounded by the max
+// page id of the meta.
+#[derive(Clone, Default)]
+pub struct PgidBitmap {
+    words: Vec<u64>,
+    len: u64,
+}
+
+impl PgidBitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // with_max_pgid allocates the bitmap for the ids less than max_pgid at once.
+    pub fn with_max_pgid(max_pgid: u64) -> Self {
+        PgidBitmap {
+            words: Vec::with_capacity(max_pgid.div_ceil(64) as usize),
+            len: 0,
+        }
+    }
+
+    // insert returns false if the id is already in the set.
+    pub fn insert(&mut self, pgid: u64) -> bool {
+        let (index, mask) = Self::position(pgid);
+        let index = usize::try_from(index).expect("the page id exceeds the address space");
+        if index >= self.words.len() {
+            self.words.resize(index + 1, 0);
+        }
+        if self.words[index] & mask != 0 {
+            return false;
+        }
+        self.words[index] |= mask;
+        self.len += 1;
+        true
+    }
+
+    // remove returns false if the id isn't in the set.
+    pub fn remove(&mut self, pgid: u64) -> bool {
+        if !self.contains(pgid) {
+            return false;
+        }
+        let (index, mask) = Self::position(pgid);
+        self.words[index as usize] &= !mask;
+        self.len -= 1;
+        true
+    }
+
+    pub fn contains(&self, pgid: u64) -> bool {
+        let (index, mask) = Self::position(pgid);
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.words.get(index))
+            .is_some_and(|word| word & mask != 0)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // iter returns the ids in the ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index as u64 * 64 + bit)
+        })
+    }
+
+    pub fn union(&self, other: &PgidBitmap) -> PgidBitmap {
+        let (longer, shorter) = if self.words.len() >= other.words.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut words = longer.words.clone();
+        for (word, other) in words.iter_mut().zip(&shorter.words) {
+            *word |= other;
+        }
+        Self::from_words(words)
+    }
+
+    pub fn intersection(&self, other: &PgidBitmap) -> PgidBitmap {
+        let words = self
+            .words
+            .iter()
+            .zip(&other.words)
+            .map(|(word, other)| word & other)
+            .collect();
+        Self::from_words(words)
+    }
+
+    // difference returns the ids which are in self but not in other.
+    pub fn difference(&self, other: &PgidBitmap) -> PgidBitmap {
+        let mut words = self.words.clone();
+        for (word, other) in words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+        Self::from_words(words)
+    }
+
+    // words returns the 64 ids of each word from the lowest bit, it's used to save
+    // the bitmap.
+    pub(crate) fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub(crate) fn from_words(mut words: Vec<u64>) -> Self {
+        let used = words
+            .iter()
+            .rposition(|&word| word != 0)
+            .map_or(0, |i| i + 1);
+        words.truncate(used);
+        let len = words.iter().map(|word| word.count_ones() as u64).sum();
+        PgidBitmap { words, len }
+    }
+
+    fn position(pgid: u64) -> (u64, u64) {
+        (pgid / 64, 1 << (pgid % 64))
+    }
+}
+
+// The bitmaps of the same ids may have different numbers of the trailing zero words,
+// e.g. after remove, so they are not compared by the words directly.
+impl PartialEq for PgidBitmap {
+    fn eq(&self, other: &Self) -> bool {
+        let (longer, shorter) = if self.words.len() >= other.words.len() {
+            (&self.words, &other.words)
+        } else {
+            (&other.words, &self.words)
+        };
+        self.len == other.len
+            && longer[..shorter.len()] == shorter[..]
+            && longer[shorter.len()..].iter().all(|&word| word == 0)
+    }
+}
+
+impl Eq for PgidBitmap {}
+
+impl FromIterator<u64> for PgidBitmap {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        let mut bitmap = PgidBitmap::new();
+        for pgid in iter {
+            bitmap.insert(pgid);
+        }
+        bitmap
+    }
+}
+
+impl fmt::Debug for PgidBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eq_ignores_trailing_zero_words() {
+        let mut bitmap: PgidBitmap = [3, 200].into_iter().collect();
+        bitmap.remove(200);
+        assert_eq!(bitmap, [3].into_iter().collect());
+        assert_eq!(PgidBitmap::from_words(vec![0, 0]), PgidBitmap::new());
+        assert_ne!(bitmap, [4].into_iter().collect());
+        assert_ne!(bitmap, [3, 64].into_iter().collect());
+    }
+
+    #[test]
+    fn set_operations() {
+        let a: PgidBitmap = [1, 2, 130].into_iter().collect();
+        let b: PgidBitmap = [2, 3].into_iter().collect();
+        assert_eq!(a.union(&b), [1, 2, 3, 130].into_iter().collect());
+        assert_eq!(b.union(&a), a.union(&b));
+        assert_eq!(a.intersection(&b), [2].into_iter().collect());
+        assert_eq!(a.difference(&b), [1, 130].into_iter().collect());
+        assert_eq!(b.difference(&a), [3].into_iter().collect());
+
+        // The words of the empty results are trimmed.
+        let c: PgidBitmap = [130].into_iter().collect();
+        assert!(c.difference(&a).words().is_empty());
+        assert_eq!(a.intersection(&c).words().len(), 3);
+        assert_eq!(a.intersection(&c).len(), 1);
+        assert_eq!(a.union(&c).iter().collect::<Vec<_>>(), vec![1, 2, 130]);
+    }
+}
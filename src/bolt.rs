@@ -25,6 +25,25 @@ pub(crate) struct Page {
 
 pub(crate) const PAGE_HEADER_SIZE: usize = 16;
 
+// The max size of the key and value, they're the same as bolt.
+#[cfg(feature = "write")]
+pub(crate) const MAX_KEY_SIZE: usize = 32768;
+#[cfg(feature = "write")]
+pub(crate) const MAX_VALUE_SIZE: usize = (1 << 31) - 2;
+
+// The encoders are only used by the write path and the tests, the bucket header is
+// only encoded by the write path.
+#[cfg(any(feature = "write", test))]
+impl Page {
+    // encode writes the page header to the start of the buffer.
+    pub(crate) fn encode(&self, data: &mut [u8]) {
+        data[0..8].copy_from_slice(&self.id.0.to_le_bytes());
+        data[8..10].copy_from_slice(&self.flags.bits().to_le_bytes());
+        data[10..12].copy_from_slice(&self.count.to_le_bytes());
+        data[12..16].copy_from_slice(&self.overflow.to_le_bytes());
+    }
+}
+
 impl TryFrom<&[u8]> for Page {
     type Error = errors::DatabaseError;
 
@@ -94,7 +113,8 @@ pub(crate) struct Meta {
     pub(crate) version: u32,
     // Size in bytes of each page.
     pub(crate) page_size: u32,
-    _flag: u32, // unused
+    // the flags of the meta, it's always 0 in bolt, but the forks may use it.
+    pub(crate) flags: u32,
     // Rust doesn't have `type embedding` that Go has, see
     // https://github.com/rust-lang/rfcs/issues/2431 for more detail.
     // The root data pageid of the database.
@@ -125,7 +145,7 @@ impl TryFrom<&[u8]> for Meta {
             magic: utils::read_value::<u32>(data, 16),
             version: utils::read_value::<u32>(data, 20),
             page_size: utils::read_value::<u32>(data, 24),
-            _flag: 0,
+            flags: utils::read_value::<u32>(data, 28),
             root_pgid: Pgid(utils::read_value::<u64>(data, 32)),
             root_sequence: utils::read_value::<u64>(data, 40),
             freelist_pgid: Pgid(utils::read_value::<u64>(data, 48)),
@@ -136,6 +156,53 @@ impl TryFrom<&[u8]> for Meta {
     }
 }
 
+#[cfg(any(feature = "write", test))]
+impl Meta {
+    // new returns the meta of the empty database, which has the freelist at page 2
+    // and the root leaf page at page 3, same as bolt's initialization.
+    pub(crate) fn new(page_size: u32, txid: u64) -> Meta {
+        Meta {
+            magic: MAGIC_NUMBER,
+            version: DATAFILE_VERSION,
+            page_size,
+            flags: 0,
+            root_pgid: Pgid(3),
+            root_sequence: 0,
+            freelist_pgid: Pgid(2),
+            max_pgid: Pgid(4),
+            txid,
+            checksum: 0,
+        }
+    }
+
+    // encode returns the meta page with the checksum of the meta, the txid decides
+    // which meta page it is.
+    pub(crate) fn encode(&self, page_size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; page_size];
+        let page_id = self.txid % 2;
+        Page {
+            id: Pgid(page_id),
+            flags: PageFlag::MetaPageFlag,
+            count: 0,
+            overflow: 0,
+        }
+        .encode(&mut data);
+
+        data[16..20].copy_from_slice(&self.magic.to_le_bytes());
+        data[20..24].copy_from_slice(&self.version.to_le_bytes());
+        data[24..28].copy_from_slice(&self.page_size.to_le_bytes());
+        data[28..32].copy_from_slice(&self.flags.to_le_bytes());
+        data[32..40].copy_from_slice(&self.root_pgid.0.to_le_bytes());
+        data[40..48].copy_from_slice(&self.root_sequence.to_le_bytes());
+        data[48..56].copy_from_slice(&self.freelist_pgid.0.to_le_bytes());
+        data[56..64].copy_from_slice(&self.max_pgid.0.to_le_bytes());
+        data[64..72].copy_from_slice(&self.txid.to_le_bytes());
+        let checksum = utils::fnv64(&data[16..72]);
+        data[72..80].copy_from_slice(&checksum.to_le_bytes());
+        data
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub(crate) struct BranchPageElement {
@@ -167,6 +234,15 @@ impl TryFrom<&[u8]> for BranchPageElement {
     }
 }
 
+#[cfg(any(feature = "write", test))]
+impl BranchPageElement {
+    pub(crate) fn encode(&self, data: &mut [u8]) {
+        data[0..4].copy_from_slice(&self.pos.to_le_bytes());
+        data[4..8].copy_from_slice(&self.ksize.to_le_bytes());
+        data[8..16].copy_from_slice(&self.pgid.0.to_le_bytes());
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub(crate) struct LeafPageElement {
@@ -202,6 +278,16 @@ impl TryFrom<&[u8]> for LeafPageElement {
     }
 }
 
+#[cfg(any(feature = "write", test))]
+impl LeafPageElement {
+    pub(crate) fn encode(&self, data: &mut [u8]) {
+        data[0..4].copy_from_slice(&self.flags.to_le_bytes());
+        data[4..8].copy_from_slice(&self.pos.to_le_bytes());
+        data[8..12].copy_from_slice(&self.ksize.to_le_bytes());
+        data[12..16].copy_from_slice(&self.vsize.to_le_bytes());
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 // Bucket represents the on-file representation of a bucket. It is stored as
@@ -210,7 +296,17 @@ impl TryFrom<&[u8]> for LeafPageElement {
 pub(crate) struct Bucket {
     // the bucket's root-level page.
     pub(crate) root: Pgid,
-    sequence: u64,
+    pub(crate) sequence: u64,
+}
+
+#[cfg(feature = "write")]
+impl Bucket {
+    pub(crate) fn encode(&self) -> [u8; 16] {
+        let mut data = [0u8; 16];
+        data[0..8].copy_from_slice(&self.root.0.to_le_bytes());
+        data[8..16].copy_from_slice(&self.sequence.to_le_bytes());
+        data
+    }
 }
 
 impl TryFrom<&[u8]> for Bucket {
@@ -236,3 +332,111 @@ pub(crate) const MAGIC_NUMBER: u32 = 0xED0CDAED;
 
 // The data file format version.
 pub(crate) const DATAFILE_VERSION: u32 = 2;
+
+// The versions which are read with the layout of DATAFILE_VERSION in the compat
+// mode, the layout of bolt hasn't changed since version 2, so the neighbour versions
+// are accepted in case a fork bumps the version without changing the layout:
+//
+//   version | meta | freelist | branch/leaf pages | inline buckets
+//   1       | yes  | yes      | yes               | yes
+//   2       | yes  | yes      | yes               | yes
+//   3       | yes  | yes      | yes               | yes
+pub(crate) const COMPAT_VERSIONS: [u32; 3] = [1, 2, 3];
+
+// swap reverses the byte order of the field at the offset, it's ignored if the field
+// is out of the data.
+fn swap(data: &mut [u8], offset: usize, size: usize) {
+    if let Some(field) = data.get_mut(offset..offset + size) {
+        field.reverse();
+    }
+}
+
+// swap_page reverses the byte order of the page header and the fields of the page
+// in place, so the page written by a machine with the other byte order can be
+// decoded. The keys and values are kept, except the bucket headers and the inline
+// pages in the values.
+//
+// The checksum of the meta is computed on the bytes in the file, so it's recomputed
+// on the swapped bytes if it's valid, the invalid one is still invalid.
+pub(crate) fn swap_page(data: &mut [u8]) {
+    if data.len() < PAGE_HEADER_SIZE {
+        return;
+    }
+    swap(data, 0, 8);
+    swap(data, 8, 2);
+    swap(data, 10, 2);
+    swap(data, 12, 4);
+    let flags = PageFlag::from_bits_retain(utils::read_value::<u16>(data, 8));
+    let count = utils::read_value::<u16>(data, 10) as usize;
+
+    if flags.contains(PageFlag::MetaPageFlag) {
+        if data.len() < 80 {
+            return;
+        }
+        let valid = {
+            let mut checksum = data[72..80].to_vec();
+            checksum.reverse();
+            utils::read_value::<u64>(&checksum, 0) == utils::fnv64(&data[16..72])
+        };
+        for (offset, size) in [(16, 4), (20, 4), (24, 4), (28, 4)] {
+            swap(data, offset, size);
+        }
+        for offset in (32..80).step_by(8) {
+            swap(data, offset, 8);
+        }
+        if valid {
+            let checksum = utils::fnv64(&data[16..72]);
+            data[72..80].copy_from_slice(&checksum.to_ne_bytes());
+        }
+    } else if flags.contains(PageFlag::FreelistPageFlag) {
+        let (count, start) = if count == 0xFFFF {
+            swap(data, PAGE_HEADER_SIZE, 8);
+            let count = data
+                .get(PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 8)
+                .map_or(0, |_| utils::read_value::<u64>(data, PAGE_HEADER_SIZE));
+            (count as usize, 1)
+        } else {
+            (count, 0)
+        };
+        let end = (start + count).min((data.len() - PAGE_HEADER_SIZE) / 8);
+        for i in start..end {
+            swap(data, PAGE_HEADER_SIZE + i * 8, 8);
+        }
+    } else if flags.contains(PageFlag::BranchPageFlag) {
+        for i in 0..count {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            swap(data, start, 4);
+            swap(data, start + 4, 4);
+            swap(data, start + 8, 8);
+        }
+    } else if flags.contains(PageFlag::LeafPageFlag) {
+        for i in 0..count {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            if start + 16 > data.len() {
+                break;
+            }
+            for offset in [0, 4, 8, 12] {
+                swap(data, start + offset, 4);
+            }
+            let element = LeafPageElement::try_from(&data[start..]).unwrap();
+            if element.flags & 0x01 == 0 {
+                continue;
+            }
+            // The value of the bucket is the bucket header, which may be followed by
+            // the inline page.
+            let value_start = start + element.pos as usize + element.ksize as usize;
+            let value_end = value_start + element.vsize as usize;
+            let Some(value) = data.get_mut(value_start..value_end) else {
+                continue;
+            };
+            if value.len() < 16 {
+                continue;
+            }
+            swap(value, 0, 8);
+            swap(value, 8, 8);
+            if utils::read_value::<u64>(value, 0) == 0 {
+                swap_page(&mut value[16..]);
+            }
+        }
+    }
+}
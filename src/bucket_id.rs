@@ -0,0 +1,74 @@
+use crate::errors::DatabaseError;
+use std::{fmt, str::FromStr};
+
+// BucketIdentifier identifies a bucket uniquely in the database, even if there are
+// buckets with the same name at different depths.
+//
+// It's formatted as:
+//   - `<page_id>`: the bucket which have it's own root page, the page id is unique.
+//   - `<parent_page_id>:<name>`: the inline bucket, which doesn't have a root page,
+//     it's identified by the root page of it's parent bucket and it's name. The name
+//     is percent-encoded except for alphanumerics and `-`, `_`, `.`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum BucketIdentifier {
+    Page(u64),
+    Inline { parent_page_id: u64, name: Vec<u8> },
+}
+
+impl fmt::Display for BucketIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BucketIdentifier::Page(page_id) => write!(f, "{}", page_id),
+            BucketIdentifier::Inline {
+                parent_page_id,
+                name,
+            } => {
+                write!(f, "{}:", parent_page_id)?;
+                for &c in name {
+                    if c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.' {
+                        write!(f, "{}", c as char)?;
+                    } else {
+                        write!(f, "%{:02X}", c)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for BucketIdentifier {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DatabaseError::InvalidBucketIdentifier { id: s.to_string() };
+
+        let Some((parent_page_id, encoded_name)) = s.split_once(':') else {
+            return s.parse().map(BucketIdentifier::Page).map_err(|_| invalid());
+        };
+
+        let parent_page_id = parent_page_id.parse().map_err(|_| invalid())?;
+        let mut name = Vec::with_capacity(encoded_name.len());
+        let mut bytes = encoded_name.bytes();
+        while let Some(c) = bytes.next() {
+            if c != b'%' {
+                name.push(c);
+                continue;
+            }
+
+            let hex = [bytes.next(), bytes.next()];
+            let [Some(high), Some(low)] = hex else {
+                return Err(invalid());
+            };
+            let hex = std::str::from_utf8(&[high, low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            name.push(hex.ok_or_else(invalid)?);
+        }
+
+        Ok(BucketIdentifier::Inline {
+            parent_page_id,
+            name,
+        })
+    }
+}
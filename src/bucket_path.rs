@@ -0,0 +1,159 @@
+use crate::errors::DatabaseError;
+use std::{fmt, str::FromStr};
+
+// BucketPattern matches the paths of the buckets, it's the names joined by `/`, e.g.
+// `app/*/config`. In each name, `*` matches any bytes and `?` matches one byte, and
+// the name `**` matches any number of names.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BucketPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Segment {
+    Name(Vec<u8>),
+    Glob(Vec<u8>),
+    AnyDepth,
+}
+
+impl Segment {
+    fn matches(&self, name: &[u8]) -> bool {
+        match self {
+            Segment::Name(expected) => expected == name,
+            Segment::Glob(glob) => glob_match(glob, name),
+            Segment::AnyDepth => true,
+        }
+    }
+}
+
+impl BucketPattern {
+    // exact returns the pattern which only matches the path, the wildcards in the
+    // names are not interpreted.
+    pub fn exact(path: &[Vec<u8>]) -> Self {
+        BucketPattern {
+            segments: path.iter().cloned().map(Segment::Name).collect(),
+        }
+    }
+
+    pub fn has_wildcard(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| !matches!(segment, Segment::Name(_)))
+    }
+
+    // max_depth returns the depth of the matched buckets, it's None if the pattern
+    // contains `**`.
+    pub fn max_depth(&self) -> Option<usize> {
+        if self.segments.contains(&Segment::AnyDepth) {
+            return None;
+        }
+        Some(self.segments.len())
+    }
+
+    // matches returns whether the pattern matches the whole path.
+    pub fn matches(&self, path: &[Vec<u8>]) -> bool {
+        matches_path(&self.segments, path)
+    }
+
+    // matches_ancestor returns whether the pattern matches the path or one of it's
+    // ancestors, i.e. the bucket is in a matched subtree.
+    pub fn matches_ancestor(&self, path: &[Vec<u8>]) -> bool {
+        (0..=path.len()).any(|depth| self.matches(&path[..depth]))
+    }
+
+    // may_match_below returns whether the pattern may match a sub bucket of the path,
+    // the subtrees which can't match are pruned by the iterators.
+    pub fn may_match_below(&self, path: &[Vec<u8>]) -> bool {
+        may_match_below(&self.segments, path)
+    }
+}
+
+fn matches_path(segments: &[Segment], path: &[Vec<u8>]) -> bool {
+    match segments.split_first() {
+        None => path.is_empty(),
+        Some((Segment::AnyDepth, rest)) => {
+            (0..=path.len()).any(|depth| matches_path(rest, &path[depth..]))
+        }
+        Some((segment, rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| segment.matches(name) && matches_path(rest, path)),
+    }
+}
+
+fn may_match_below(segments: &[Segment], path: &[Vec<u8>]) -> bool {
+    let Some((name, path)) = path.split_first() else {
+        return !segments.is_empty();
+    };
+    match segments.split_first() {
+        None => false,
+        Some((Segment::AnyDepth, _)) => true,
+        Some((segment, rest)) => segment.matches(name) && may_match_below(rest, path),
+    }
+}
+
+// glob_match matches the name with `*` and `?`, it backtracks to the last `*` on the
+// mismatch, so it's linear for the patterns with a single `*`.
+fn glob_match(glob: &[u8], name: &[u8]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => {
+                let Some((star_g, star_n)) = star else {
+                    return false;
+                };
+                // The `*` matches one more byte.
+                star = Some((star_g, star_n + 1));
+                g = star_g + 1;
+                n = star_n + 1;
+            }
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+impl FromStr for BucketPattern {
+    type Err = DatabaseError;
+
+    // from_str parses the pattern, the names must not be empty since bolt doesn't
+    // allow the buckets with empty names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segments = s
+            .split('/')
+            .map(|name| match name {
+                "" => Err(DatabaseError::InvalidBucketPattern {
+                    pattern: s.to_string(),
+                }),
+                "**" => Ok(Segment::AnyDepth),
+                _ if name.contains(['*', '?']) => Ok(Segment::Glob(name.as_bytes().to_vec())),
+                _ => Ok(Segment::Name(name.as_bytes().to_vec())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BucketPattern { segments })
+    }
+}
+
+impl fmt::Display for BucketPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            match segment {
+                Segment::Name(name) | Segment::Glob(name) => {
+                    f.write_str(&String::from_utf8_lossy(name))?
+                }
+                Segment::AnyDepth => f.write_str("**")?,
+            }
+        }
+        Ok(())
+    }
+}
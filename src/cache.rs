@@ -0,0 +1,69 @@
+use crate::bolt::Pgid;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+pub(crate) type Pages = BTreeMap<Pgid, Arc<Vec<u8>>>;
+
+// FileIdentity identifies the content of a database file, the file is considered
+// unchanged if it's size and modification time are the same.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct FileIdentity {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileIdentity {
+    pub(crate) fn of(path: &str) -> io::Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let metadata = fs::metadata(&path)?;
+        Ok(FileIdentity {
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+// The process-wide page cache, which is shared by the databases opened from the
+// same file, so re-opening an unchanged file doesn't read the pages again.
+static PAGE_CACHE: Mutex<BTreeMap<PathBuf, (FileIdentity, Pages)>> = Mutex::new(BTreeMap::new());
+
+// load returns the cached pages of the file, the pages of the stale file are
+// dropped.
+pub(crate) fn load(identity: &FileIdentity) -> Pages {
+    let mut cache = PAGE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    match cache.get(&identity.path) {
+        Some((cached, pages)) if cached == identity => pages.clone(),
+        Some(_) => {
+            cache.remove(&identity.path);
+            Pages::new()
+        }
+        None => Pages::new(),
+    }
+}
+
+// store adds the pages read by a database to the cache, they replace the pages of
+// the stale file.
+pub(crate) fn store(identity: &FileIdentity, pages: &Pages) {
+    let mut cache = PAGE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = cache
+        .entry(identity.path.clone())
+        .or_insert_with(|| (identity.clone(), Pages::new()));
+    if entry.0 != *identity {
+        *entry = (identity.clone(), Pages::new());
+    }
+    for (page_id, data) in pages {
+        entry.1.entry(*page_id).or_insert_with(|| Arc::clone(data));
+    }
+}
+
+// clear_page_cache drops all pages of the process-wide page cache.
+pub fn clear_page_cache() {
+    PAGE_CACHE.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
@@ -0,0 +1,26 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// CancellationToken aborts the iterators which are reading with it, the clones of
+// the token share the same state, so it can be cancelled from another thread, e.g.
+// the UI thread, while the iteration is running. The cancelled iterators return
+// None on the next call, so the caller should check is_cancelled to tell whether
+// the iteration is completed.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
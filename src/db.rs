@@ -1,34 +1,51 @@
+use crate::bitmap::PgidBitmap;
 use crate::bolt::{self, PAGE_HEADER_SIZE};
-use bitflags::iter::Iter;
-use bitflags::Flags;
-use fnv_rs::{Fnv64, FnvHasher};
-use prettytable::Table;
+use crate::bucket_id::BucketIdentifier;
+use crate::bucket_path::BucketPattern;
+use crate::bytes::Bytes;
+use crate::cache::{self, FileIdentity};
+use crate::cancel::CancellationToken;
+#[cfg(feature = "disk-cache")]
+use crate::disk_cache::DiskCache;
+use crate::errors::DatabaseError;
+use crate::metrics::{metrics, MetricsSnapshot};
+use crate::source::{FileSource, MemorySource, PageSource, ReaderSource};
+use crate::utils;
 use std::cell::RefCell;
-use std::ops::{Deref, IndexMut};
+use std::ops::IndexMut;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::{
-    collections::BTreeMap,
-    fs::File,
+    collections::{BTreeMap, HashMap, VecDeque},
+    env, fmt, fs,
     io::{self, Read, Seek},
-    ops::Index,
-};
-
-use tui::{
-    backend::CrosstermBackend,
-    widgets::{Block, Borders},
-    Terminal,
+    ops::Range,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tracing::{debug, debug_span, trace, warn};
 use typed_builder::TypedBuilder;
 
 pub struct DB {
     pub(crate) options: AnclaOptions,
-    file: File,
+    source: Box<dyn PageSource>,
 
-    pages: BTreeMap<bolt::Pgid, PageInfo>,
     page_datas: BTreeMap<bolt::Pgid, Arc<Vec<u8>>>,
     meta0: Option<bolt::Meta>,
     meta1: Option<bolt::Meta>,
+    // the page size which the pages are read with, it's detected from the meta.
+    page_size: u64,
+    // the identity of the file if the pages are shared by the process-wide cache.
+    cache_identity: Option<FileIdentity>,
+    // the temporary copy of the file which is read instead of it, it's removed
+    // when the database is dropped.
+    snapshot_path: Option<PathBuf>,
+    // the IO counters of this database, the process-wide ones are in metrics.
+    read_stats: MetricsSnapshot,
+    #[cfg(feature = "disk-cache")]
+    disk_cache: Option<DiskCache>,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -38,6 +55,9 @@ pub struct PageInfo {
     pub overflow: u64,
     pub capacity: u64,
     pub used: u64,
+    // the bytes between the elements' keys and values which are not used by any
+    // element, e.g. the data of the deleted elements.
+    pub slack: u64,
     pub parent_page_id: Option<u64>,
 }
 
@@ -59,15 +79,346 @@ pub struct Bucket {
     pub page_id: u64,
     pub is_inline: bool,
     pub name: Vec<u8>,
+    // the names of all ancestor buckets and this bucket, from the top level bucket.
+    path: Vec<Vec<u8>>,
+    // the sequence which is used by bolt's NextSequence.
+    pub sequence: u64,
+    // the root page id of the parent bucket, it's the root page id of the database
+    // for the top level buckets.
+    parent_page_id: u64,
+    // the key-value pairs of the inline bucket, it's always empty if the
+    // bucket is not inline.
+    inline_items: Vec<KeyValue>,
     db: Rc<RefCell<DB>>,
 }
 
 impl Bucket {
+    // from_element builds the bucket from the element of it's parent bucket's leaf
+    // page, None is returned if the element is not a bucket.
+    fn from_element(
+        db: Rc<RefCell<DB>>,
+        parent_path: &[Vec<u8>],
+        parent_page_id: u64,
+        elem: LeafElement,
+    ) -> Option<Bucket> {
+        let (name, page_id, sequence, inline_items) = match elem {
+            LeafElement::Bucket {
+                name,
+                pgid,
+                sequence,
+            } => (name, pgid, sequence, Vec::new()),
+            LeafElement::InlineBucket {
+                name,
+                sequence,
+                items,
+            } => (name, 0, sequence, items),
+            LeafElement::KeyValue(_) => return None,
+        };
+
+        let mut path = parent_path.to_vec();
+        path.push(name.clone());
+        Some(Bucket {
+            parent_bucket: parent_path.last().cloned().unwrap_or_default(),
+            page_id,
+            is_inline: page_id == 0,
+            name,
+            path,
+            sequence,
+            parent_page_id,
+            inline_items,
+            db,
+        })
+    }
+
+    // path returns the names of all ancestor buckets and this bucket, from the top
+    // level bucket.
+    pub fn path(&self) -> &[Vec<u8>] {
+        &self.path
+    }
+
+    // iter_items iterates the key-value pairs and sub buckets of this bucket in
+    // depth-first order, the sub buckets deeper than max_depth levels below this
+    // bucket are not visited. A max_depth of 0 iterates only the key-value pairs
+    // of this bucket.
+    pub fn iter_items(&self, max_depth: Option<usize>) -> DbItemIterator {
+        self.iter_items_with(ReadOptions::builder().max_depth(max_depth).build())
+    }
+
+    // iter_items_with iterates the items of this bucket as iter_items, the max depth
+    // is relative to this bucket and the paths of the buckets filter are absolute.
+    pub fn iter_items_with(&self, options: ReadOptions) -> DbItemIterator {
+        let mut iter = DbItemIterator {
+            db: self.db.clone(),
+            options,
+            frames: Vec::new(),
+            error: None,
+        };
+        let (visited, matched) = iter.options.filter_bucket(&self.path);
+        if visited {
+            iter.error = iter.push_bucket(self.clone(), 0, matched).err();
+        }
+        iter
+    }
+
+    // id returns the identifier which is unique in the database.
+    pub fn id(&self) -> BucketIdentifier {
+        if self.is_inline {
+            return BucketIdentifier::Inline {
+                parent_page_id: self.parent_page_id,
+                name: self.name.clone(),
+            };
+        }
+
+        BucketIdentifier::Page(self.page_id)
+    }
+
+    // parse_id parses the identifier which is formatted by BucketIdentifier's Display.
+    pub fn parse_id(id: &str) -> Result<BucketIdentifier, DatabaseError> {
+        id.parse()
+    }
+
+    // get returns the value of the key in this bucket, None is returned if the key
+    // doesn't exist or it's a sub bucket.
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        if self.is_inline {
+            return self
+                .inline_items
+                .iter()
+                .find(|kv| kv.key == key)
+                .map(|kv| kv.value.clone());
+        }
+
+        let mut db = self.db.borrow_mut();
+        let page_id = db.find_leaf(self.page_id, key)?;
+        let data = db.read_page(page_id);
+        let leaf_elements = db.read_page_leaf_elements(&data);
+        leaf_elements.into_iter().find_map(|elem| match elem {
+            LeafElement::KeyValue(kv) if kv.key == key => Some(kv.value),
+            _ => None,
+        })
+    }
+
+    // locate returns where the key of this bucket is stored in the file, the key can
+    // be a sub bucket. For the inline bucket, the page is the leaf page of the parent
+    // bucket which stores the inline page in the value of the bucket element.
+    pub fn locate(&self, key: &[u8]) -> Option<KeyLocation> {
+        let mut db = self.db.borrow_mut();
+        let (page_id, data, start) = if self.is_inline {
+            let page_id = db.find_leaf(self.parent_page_id, &self.name)?;
+            let data = db.read_page(page_id);
+            let (index, elem) = find_leaf_element(&data, &self.name)?;
+            // The inline page is stored just after the bucket header.
+            let value_start =
+                PAGE_HEADER_SIZE + index as usize * 16 + elem.pos as usize + elem.ksize as usize;
+            (page_id, data, value_start + 16)
+        } else {
+            let page_id = db.find_leaf(self.page_id, key)?;
+            (page_id, db.read_page(page_id), 0)
+        };
+
+        let (index, elem) = find_leaf_element(&data[start..], key)?;
+        let key_offset = page_id * db.page_size
+            + (start + PAGE_HEADER_SIZE + index as usize * 16 + elem.pos as usize) as u64;
+        Some(KeyLocation {
+            page_id,
+            is_inline: self.is_inline,
+            index,
+            kind: if elem.flags == 0x01 {
+                ElementKind::Bucket
+            } else {
+                ElementKind::KeyValue
+            },
+            key_offset,
+            ksize: elem.ksize as u64,
+            value_offset: key_offset + elem.ksize as u64,
+            vsize: elem.vsize as u64,
+        })
+    }
+
+    // iter_items_rev iterates the key-value pairs and the sub buckets of this bucket
+    // in the reverse order, the items of the sub buckets are not visited.
+    pub fn iter_items_rev(&self) -> BucketItemRevIterator {
+        BucketItemRevIterator::new(self.clone())
+    }
+
+    // estimate_key_count estimates the number of keys of this bucket by the random
+    // descents from the root page, each descent reads one page per level. The
+    // descents stop once the relative standard error is not greater than max_error,
+    // or MAX_ESTIMATE_SAMPLES descents are done. It's exact if the root is a leaf.
+    // It returns Corrupted if a page of the descents is reached twice or its
+    // elements overrun the page.
+    pub fn estimate_key_count(&self, max_error: f64) -> Result<KeyCountEstimate, DatabaseError> {
+        if self.is_inline {
+            return Ok(KeyCountEstimate::exact(self.inline_items.len() as u64));
+        }
+
+        let mut db = self.db.borrow_mut();
+        let data = db.try_read_page(self.page_id)?;
+        let page = checked_page(&data)?;
+        if !page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            return Ok(KeyCountEstimate::exact(leaf_key_count(&data)?));
+        }
+
+        // The fixed seed makes the estimation reproducible.
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        let mut samples = 0;
+        // The pages of the current descent, a page reached twice means the tree has
+        // a cycle, which would make the descent endless.
+        let mut visited = Vec::new();
+        while samples < MAX_ESTIMATE_SAMPLES {
+            // The keys of the leaf, multiplied by the fanout of each branch page on
+            // the path, is an unbiased estimation of the total keys.
+            let mut weight = 1.0;
+            let mut data = Arc::clone(&data);
+            visited.clear();
+            visited.push(self.page_id);
+            loop {
+                let page = checked_page(&data)?;
+                if !page.flags.contains(bolt::PageFlag::BranchPageFlag) || page.count == 0 {
+                    break;
+                }
+                let index = rng.next() % page.count as u64;
+                let elem = bolt::BranchPageElement::try_from(
+                    &data[PAGE_HEADER_SIZE + index as usize * 16..],
+                )?;
+                let child: u64 = elem.pgid.into();
+                if visited.contains(&child) {
+                    return Err(DatabaseError::Corrupted {
+                        reason: format!(
+                            "page {} is reached twice from the root page {}",
+                            child, self.page_id
+                        ),
+                    });
+                }
+                visited.push(child);
+                weight *= page.count as f64;
+                data = db.try_read_page(child)?;
+            }
+            let estimate = weight * leaf_key_count(&data)? as f64;
+            sum += estimate;
+            sum_squares += estimate * estimate;
+            samples += 1;
+
+            if samples >= MIN_ESTIMATE_SAMPLES
+                && relative_error(sum, sum_squares, samples) <= max_error
+            {
+                break;
+            }
+        }
+
+        Ok(KeyCountEstimate {
+            keys: (sum / samples as f64).round() as u64,
+            samples,
+            relative_error: relative_error(sum, sum_squares, samples),
+        })
+    }
+
+    // first returns the first key-value pair of this bucket, the sub buckets are
+    // skipped. Only the pages from the leftmost path are read until a pair is found.
+    pub fn first(&self) -> Option<(Vec<u8>, Bytes)> {
+        self.iter_items(Some(0)).find_map(|item| match item {
+            DbItem::KeyValue { key, value, .. } => Some((key, value)),
+            DbItem::Bucket(_) => None,
+        })
+    }
+
+    // last returns the last key-value pair of this bucket, the sub buckets are
+    // skipped. Only the pages from the rightmost path are read until a pair is found.
+    pub fn last(&self) -> Option<(Vec<u8>, Bytes)> {
+        self.iter_items_rev().find_map(|item| match item {
+            DbItem::KeyValue { key, value, .. } => Some((key, value)),
+            DbItem::Bucket(_) => None,
+        })
+    }
+
+    // sample returns n key-value pairs of this bucket chosen at random by reservoir
+    // sampling over all pairs, the sub buckets are skipped. The same seed chooses
+    // the same pairs of an unchanged bucket, the pairs are ordered by key.
+    pub fn sample(&self, n: usize, seed: u64) -> Vec<(Vec<u8>, Bytes)> {
+        // The state of XorShift must not be zero.
+        let mut rng = XorShift((seed ^ 0x9e37_79b9_7f4a_7c15).max(1));
+        let mut samples = Vec::with_capacity(n.min(1024));
+        let pairs = self.iter_items(Some(0)).filter_map(|item| match item {
+            DbItem::KeyValue { key, value, .. } => Some((key, value)),
+            DbItem::Bucket(_) => None,
+        });
+        for (seen, pair) in pairs.enumerate() {
+            if samples.len() < n {
+                samples.push(pair);
+                continue;
+            }
+            let index = (rng.next() % (seen as u64 + 1)) as usize;
+            if index < n {
+                samples[index] = pair;
+            }
+        }
+        samples.sort();
+        samples
+    }
+
+    // info returns the statistics of the bucket, it walks all pages of the bucket.
+    pub fn info(&self) -> BucketInfo {
+        let mut info = BucketInfo {
+            id: self.id(),
+            root_page_id: self.page_id,
+            is_inline: self.is_inline,
+            sequence: self.sequence,
+            child_buckets: 0,
+            keys: 0,
+            depth: 0,
+            pages: 0,
+            capacity: 0,
+            used: 0,
+            inline_size: PAGE_HEADER_SIZE as u64,
+        };
+
+        if self.is_inline {
+            info.keys = self.inline_items.len() as u64;
+            info.used = PAGE_HEADER_SIZE as u64
+                + self
+                    .inline_items
+                    .iter()
+                    .map(|kv| (16 + kv.key.len() + kv.value.len()) as u64)
+                    .sum::<u64>();
+            info.inline_size = info.used;
+            return info;
+        }
+
+        self.db.borrow_mut().collect_bucket_info(
+            self.page_id,
+            1,
+            &mut info,
+            &mut PgidBitmap::new(),
+        );
+        info
+    }
+
+    // hash returns the Merkle-style hash of the bucket's subtree, two buckets have
+    // the same hash only if all their pages (including sub buckets) are the same.
+    pub fn hash(&self) -> u64 {
+        if self.is_inline {
+            let mut data: Vec<u8> = Vec::new();
+            for kv in &self.inline_items {
+                data.extend_from_slice(&(kv.key.len() as u64).to_be_bytes());
+                data.extend_from_slice(&kv.key);
+                data.extend_from_slice(&(kv.value.len() as u64).to_be_bytes());
+                data.extend_from_slice(&kv.value);
+            }
+            return utils::fnv64(&data);
+        }
+
+        self.db
+            .borrow_mut()
+            .subtree_hash(self.page_id, &mut PgidBitmap::new())
+    }
+
     pub fn iter_buckets(&self) -> impl Iterator<Item = Bucket> {
         if self.is_inline {
             return BucketIterator {
                 db: self.db.clone(),
                 parent_bucket: Some(self.clone()),
+                parent_page_id: self.page_id,
                 stack: Vec::new(),
             };
         }
@@ -75,6 +426,7 @@ impl Bucket {
         BucketIterator {
             db: self.db.clone(),
             parent_bucket: Some(self.clone()),
+            parent_page_id: self.page_id,
             stack: vec![IterItem {
                 page_id: From::from(self.page_id),
                 index: 0,
@@ -83,6 +435,252 @@ impl Bucket {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Info {
+    pub page_size: u32,
+    pub version: u32,
+    // the flags of the meta, it's always 0 in bolt.
+    pub flags: u32,
+    // the hex of the bytes after the meta in the meta page, the trailing zeros are
+    // trimmed, so it's empty in bolt. The forks (e.g. the encrypted variants) may
+    // store their own data here.
+    pub reserved: String,
+    pub txid: u64,
+    pub root_page_id: u64,
+    pub freelist_page_id: u64,
+    pub max_page_id: u64,
+    // the number of pages of each type, it's only computed by DB::info_with_page_types
+    // as all pages are read.
+    pub page_types: Option<PageTypeCounts>,
+}
+
+impl From<bolt::Meta> for Info {
+    fn from(meta: bolt::Meta) -> Self {
+        Info {
+            page_size: meta.page_size,
+            version: meta.version,
+            flags: meta.flags,
+            reserved: String::new(),
+            txid: meta.txid,
+            root_page_id: meta.root_pgid.into(),
+            freelist_page_id: meta.freelist_pgid.into(),
+            max_page_id: meta.max_pgid.into(),
+            page_types: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Info {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Info", 9)?;
+        state.serialize_field("page_size", &self.page_size)?;
+        state.serialize_field("version", &self.version)?;
+        state.serialize_field("flags", &self.flags)?;
+        state.serialize_field("reserved", &self.reserved)?;
+        state.serialize_field("txid", &self.txid)?;
+        state.serialize_field("root_page_id", &self.root_page_id)?;
+        state.serialize_field("freelist_page_id", &self.freelist_page_id)?;
+        state.serialize_field("max_page_id", &self.max_page_id)?;
+        state.serialize_field("page_types", &self.page_types)?;
+        state.end()
+    }
+}
+
+// PageTypeCounts is the number of pages of each type, the overflow pages are not
+// counted in the type of the page they belong to. The unreachable pages are neither
+// reachable from the meta nor free.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PageTypeCounts {
+    pub meta: u64,
+    pub freelist: u64,
+    pub branch: u64,
+    pub leaf: u64,
+    pub free: u64,
+    pub overflow: u64,
+    pub unreachable: u64,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PageTypeCounts {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("PageTypeCounts", 7)?;
+        state.serialize_field("meta", &self.meta)?;
+        state.serialize_field("freelist", &self.freelist)?;
+        state.serialize_field("branch", &self.branch)?;
+        state.serialize_field("leaf", &self.leaf)?;
+        state.serialize_field("free", &self.free)?;
+        state.serialize_field("overflow", &self.overflow)?;
+        state.serialize_field("unreachable", &self.unreachable)?;
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BucketInfo {
+    pub id: BucketIdentifier,
+    pub root_page_id: u64,
+    pub is_inline: bool,
+    pub sequence: u64,
+    // the number of direct child buckets.
+    pub child_buckets: u64,
+    // the number of keys in this bucket, the keys of child buckets are excluded.
+    pub keys: u64,
+    // the levels of pages from the root page to the leaf pages, it's 0 for the
+    // inline bucket.
+    pub depth: u64,
+    // the number of pages including overflow pages, child buckets' are excluded.
+    pub pages: u64,
+    pub capacity: u64,
+    pub used: u64,
+    // the size of the page if the bucket is stored inline, which is the header and
+    // all leaf elements with their keys and values.
+    pub inline_size: u64,
+}
+
+impl BucketInfo {
+    // fits_inline reports whether bolt stores the bucket inline after compaction, it
+    // must not have child buckets and it's inline page must not be larger than a
+    // quarter of the page size.
+    pub fn fits_inline(&self, page_size: u64) -> bool {
+        self.child_buckets == 0 && self.inline_size <= page_size / 4
+    }
+}
+
+// KeyCountEstimate is the estimated number of keys of a bucket, see
+// Bucket::estimate_key_count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyCountEstimate {
+    pub keys: u64,
+    // the number of random descents, it's 0 if the count is exact.
+    pub samples: u64,
+    // the relative standard error of the estimation, it's 0 if the count is exact.
+    pub relative_error: f64,
+}
+
+impl KeyCountEstimate {
+    fn exact(keys: u64) -> Self {
+        KeyCountEstimate {
+            keys,
+            samples: 0,
+            relative_error: 0.0,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.samples == 0
+    }
+}
+
+// The bounds of the random descents of the key count estimation.
+const MIN_ESTIMATE_SAMPLES: u64 = 16;
+const MAX_ESTIMATE_SAMPLES: u64 = 4096;
+
+// relative_error returns the standard error of the mean divided by the mean.
+fn relative_error(sum: f64, sum_squares: f64, samples: u64) -> f64 {
+    let n = samples as f64;
+    let mean = sum / n;
+    if samples < 2 || mean == 0.0 {
+        return 0.0;
+    }
+    let variance = ((sum_squares - sum * mean) / (n - 1.0)).max(0.0);
+    (variance / n).sqrt() / mean
+}
+
+// checked_page returns the header of the page, it returns Corrupted if the elements
+// of the page overrun the page data.
+fn checked_page(data: &[u8]) -> Result<bolt::Page, DatabaseError> {
+    let page: bolt::Page = TryFrom::try_from(data)?;
+    let capacity = data.len().saturating_sub(PAGE_HEADER_SIZE) / 16;
+    if page.count as usize > capacity {
+        return Err(DatabaseError::Corrupted {
+            reason: format!(
+                "page {} has {} elements, only {} elements fit in the page",
+                page.id.0, page.count, capacity
+            ),
+        });
+    }
+    Ok(page)
+}
+
+// leaf_key_count returns the number of the keys in the leaf page, the sub buckets
+// are excluded.
+fn leaf_key_count(data: &[u8]) -> Result<u64, DatabaseError> {
+    let page = checked_page(data)?;
+    Ok((0..page.count as usize)
+        .filter(|i| utils::read_value::<u32>(data, PAGE_HEADER_SIZE + i * 16) != 0x01)
+        .count() as u64)
+}
+
+// XorShift is the pseudo random generator of the sampling, it doesn't need to be
+// cryptographically secure.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// TreeStats is the shape of the B-tree of a bucket, the depth of the root page is 1
+// and the inline bucket has a single leaf of depth 1.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TreeStats {
+    pub bucket: Vec<Vec<u8>>,
+    pub leaves: u64,
+    pub min_leaf_depth: u64,
+    pub max_leaf_depth: u64,
+    // the sum of the depth of all leaf pages, see avg_leaf_depth.
+    pub total_leaf_depth: u64,
+    // the number of branch pages of each branching factor (the number of children).
+    pub branching: BTreeMap<u64, u64>,
+    // the branch pages whose children have leaves at different depths, bolt keeps
+    // all leaves at the same depth, so these subtrees are corrupted.
+    pub unbalanced: Vec<u64>,
+}
+
+impl TreeStats {
+    pub fn avg_leaf_depth(&self) -> f64 {
+        if self.leaves == 0 {
+            return 0.0;
+        }
+        self.total_leaf_depth as f64 / self.leaves as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TxAnalysis {
+    pub meta0: Info,
+    pub meta1: Info,
+    // the pages reachable from the meta which have bigger txid, but not from the
+    // other one, it's ordered by page id.
+    pub changed_pages: Vec<ChangedPage>,
+}
+
+// FreePage is a page in the freelist, see DB::freelist.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FreePage {
+    pub id: u64,
+    // the page is freed by the last transaction, it's still reachable from the
+    // previous meta.
+    pub pending: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangedPage {
+    pub id: u64,
+    // the path of the bucket which the page belongs to, it's empty for the pages
+    // of the root bucket, and None for the other pages, e.g. the freelist page.
+    pub bucket: Option<Vec<Vec<u8>>>,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum PageType {
     Meta,
@@ -92,6 +690,209 @@ pub enum PageType {
     Free,
 }
 
+impl PageType {
+    // as_str returns the stable name of the page type, which is used for both
+    // display and serialization.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageType::Meta => "meta",
+            PageType::DataLeaf => "leaf",
+            PageType::DataBranch => "branch",
+            PageType::Freelist => "freelist",
+            PageType::Free => "free",
+        }
+    }
+}
+
+impl fmt::Display for PageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PageType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "meta" => Ok(PageType::Meta),
+            "leaf" => Ok(PageType::DataLeaf),
+            "branch" => Ok(PageType::DataBranch),
+            "freelist" => Ok(PageType::Freelist),
+            "free" => Ok(PageType::Free),
+            _ => Err(format!("unknown page type: {}", s)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PageType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// PageRegion is the region of a page which the bytes belong to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PageRegion {
+    Header,
+    Meta,
+    Freelist,
+    Elements,
+    KeyValues,
+    Unused,
+}
+
+impl PageRegion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PageRegion::Header => "header",
+            PageRegion::Meta => "meta",
+            PageRegion::Freelist => "freelist",
+            PageRegion::Elements => "elements",
+            PageRegion::KeyValues => "kv",
+            PageRegion::Unused => "unused",
+        }
+    }
+}
+
+impl fmt::Display for PageRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// PageLayout is the regions of a page and it's overflow pages, the offsets of the
+// regions are relative to the start of the page.
+#[derive(Debug, Clone)]
+pub struct PageLayout {
+    pub page_id: u64,
+    pub typ: PageType,
+    pub overflow: u64,
+    pub regions: Vec<(Range<u64>, PageRegion)>,
+}
+
+impl PageLayout {
+    // region returns the region of the offset which is relative to the start of the
+    // page, the bytes not covered by the regions are unused.
+    pub fn region(&self, offset: u64) -> PageRegion {
+        self.regions
+            .iter()
+            .find(|(range, _)| range.contains(&offset))
+            .map_or(PageRegion::Unused, |(_, region)| *region)
+    }
+}
+
+// Location is what a byte offset of the file belongs to, it's used to interpret the
+// bad offsets reported by other tools.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub offset: u64,
+    pub page_id: u64,
+    // the reachable or free page which the offset belongs to, it differs from
+    // page_id if the offset is in an overflow page. It's None if the page is
+    // unreachable.
+    pub owner_page_id: Option<u64>,
+    pub typ: Option<PageType>,
+    // the region of the owner page, it's None for the free pages.
+    pub region: Option<PageRegion>,
+    // the element of the branch or leaf page whose header, key or value contains
+    // the offset.
+    pub element_index: Option<u64>,
+    // the path of the bucket which the branch or leaf page belongs to.
+    pub bucket: Option<Vec<Vec<u8>>>,
+}
+
+// PagePath is how a page is reached from the meta, see DB::page_path.
+#[derive(Debug, Clone)]
+pub enum PagePath {
+    Meta,
+    Freelist,
+    // the page is in the tree from the root page of the meta, the element of each
+    // step points to the next page, the last one points to the page (or the page
+    // which it's an overflow page of).
+    Tree {
+        root_page_id: u64,
+        steps: Vec<PageStep>,
+        // the page whose overflow pages contain the page.
+        overflow_of: Option<u64>,
+    },
+    Free {
+        pending: bool,
+    },
+    Unreachable,
+}
+
+// PageStep is an element on the path to a page, the branch element or the bucket
+// element which points to the root page of the bucket.
+#[derive(Debug, Clone)]
+pub struct PageStep {
+    pub page_id: u64,
+    pub index: u64,
+    pub kind: ElementKind,
+    pub key: Vec<u8>,
+    pub child_page_id: u64,
+}
+
+// KeyLocation is where a key and it's value are stored in the file, see
+// Bucket::locate. The offsets are from the start of the file.
+#[derive(Debug, Clone)]
+pub struct KeyLocation {
+    pub page_id: u64,
+    // the key is in the inline page which is stored in the value of the page's
+    // element, the index is the element of the inline page.
+    pub is_inline: bool,
+    pub index: u64,
+    pub kind: ElementKind,
+    pub key_offset: u64,
+    pub ksize: u64,
+    pub value_offset: u64,
+    pub vsize: u64,
+}
+
+// PageElement is an element of a branch or leaf page as it's stored, it's used to
+// inspect the pages deeply, e.g. to find the keys which are stored in the overflow
+// pages.
+#[derive(Debug, Clone)]
+pub struct PageElement {
+    pub page_id: u64,
+    // the index of the element in the page.
+    pub index: u64,
+    pub kind: ElementKind,
+    // the flags of the leaf element, 0x01 is the bucket. It's 0 for the branch
+    // element, which doesn't have flags.
+    pub flags: u32,
+    pub key: Vec<u8>,
+    pub ksize: u64,
+    pub vsize: u64,
+    // the child page of the branch element, or the root page of the bucket which is
+    // not inline.
+    pub child_page_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ElementKind {
+    Branch,
+    KeyValue,
+    Bucket,
+}
+
+impl ElementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ElementKind::Branch => "branch",
+            ElementKind::KeyValue => "kv",
+            ElementKind::Bucket => "bucket",
+        }
+    }
+}
+
+impl fmt::Display for ElementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone)]
 struct BranchElement {
     key: Vec<u8>,
@@ -100,158 +901,421 @@ struct BranchElement {
 
 #[derive(Debug, Clone)]
 enum LeafElement {
-    Bucket { name: Vec<u8>, pgid: u64 },
-    InlineBucket { name: Vec<u8>, items: Vec<KeyValue> },
+    Bucket {
+        name: Vec<u8>,
+        pgid: u64,
+        sequence: u64,
+    },
+    InlineBucket {
+        name: Vec<u8>,
+        sequence: u64,
+        items: Vec<KeyValue>,
+    },
     KeyValue(KeyValue),
 }
 
 #[derive(Debug, Clone)]
 struct KeyValue {
     key: Vec<u8>,
-    value: Vec<u8>,
+    value: Bytes,
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        if let Some(identity) = &self.cache_identity {
+            cache::store(identity, &self.page_datas);
+        }
+        #[cfg(feature = "disk-cache")]
+        if let Some(disk_cache) = &self.disk_cache {
+            if let Err(e) = disk_cache.store(&self.page_datas) {
+                warn!(%e, "store the disk cache failed");
+            }
+        }
+        if let Some(path) = &self.snapshot_path {
+            if let Err(e) = fs::remove_file(path) {
+                warn!(path = %path.display(), %e, "remove the snapshot failed");
+            }
+        }
+    }
 }
 
 impl DB {
     fn read(&mut self, start: u64, size: usize) -> Vec<u8> {
-        let mut data = vec![0u8; size];
-        self.file.seek(io::SeekFrom::Start(start)).unwrap();
-        let read_size = self.file.read(data.as_mut_slice()).unwrap();
-        if read_size != size {
-            panic!("read {} bytes, expected {}", read_size, size);
+        match self.try_read(start, size) {
+            Ok(data) => data,
+            Err(e) => panic!("read {} bytes at offset {} failed: {}", size, start, e),
         }
-        data
+    }
+
+    // try_read is the same as read, but the error is returned instead of panic, it's
+    // used when the database may be corrupted.
+    pub(crate) fn try_read(&mut self, start: u64, size: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; size];
+        self.source.read_at(start, data.as_mut_slice())?;
+        metrics().record_bytes_read(size);
+        self.read_stats.bytes_read += size as u64;
+        Ok(data)
     }
 
     fn read_page(&mut self, page_id: u64) -> Arc<Vec<u8>> {
+        self.try_read_page(page_id)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // try_read_page is the same as read_page, but the error is returned instead of
+    // panic, e.g. if the page header exceeds the read limits.
+    fn try_read_page(&mut self, page_id: u64) -> Result<Arc<Vec<u8>>, DatabaseError> {
         if let Some(data) = self.page_datas.get(&From::from(page_id)) {
-            return Arc::clone(data);
+            trace!(page_id, bytes = data.len(), cache_hit = true, "read page");
+            metrics().record_cache(true);
+            self.read_stats.cache_hits += 1;
+            return Ok(Arc::clone(data));
         }
+        metrics().record_cache(false);
+        self.read_stats.cache_misses += 1;
 
-        let data = self.read(page_id * 4096, PAGE_HEADER_SIZE);
-        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let mut data = self.try_read(page_id * self.page_size, PAGE_HEADER_SIZE)?;
+        if self.options.endian.is_swapped() {
+            bolt::swap_page(&mut data);
+        }
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        self.options.limits.check_overflow(page_id, page.overflow)?;
 
-        let data_len = 4096 * (page.overflow + 1) as usize;
-        let data = self.read(page_id * 4096, data_len);
+        // The overflow is u32, so the page may not fit in the address space of the
+        // 32-bit targets.
+        let data_len =
+            usize::try_from(self.page_size * (page.overflow as u64 + 1)).map_err(|_| {
+                DatabaseError::Corrupted {
+                    reason: format!("page {} is too large to be read", page_id),
+                }
+            })?;
+        let mut data = self.try_read(page_id * self.page_size, data_len)?;
+        if self.options.endian.is_swapped() {
+            bolt::swap_page(&mut data);
+        }
+        debug!(page_id, bytes = data_len, cache_hit = false, "read page");
+        metrics().record_page_read();
+        self.read_stats.pages_read += 1;
         let data = Arc::new(data);
         self.page_datas
             .insert(From::from(page_id), Arc::clone(&data));
-        Arc::clone(&data)
+        Ok(Arc::clone(&data))
+    }
+
+    // find_leaf returns the leaf page which the key can be stored at in the tree from
+    // the root page, it's None if a branch page is empty.
+    fn find_leaf(&mut self, root_page_id: u64, key: &[u8]) -> Option<u64> {
+        let mut page_id = root_page_id;
+        loop {
+            let data = self.read_page(page_id);
+            let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+            if !page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+                return Some(page_id);
+            }
+            let branch_elements = self.read_page_branch_elements(&data);
+            // The element's key is the smallest key of it's child page, so the key
+            // can only be stored at the last child whose key is not greater than it.
+            let index = branch_elements.partition_point(|elem| elem.key.as_slice() <= key);
+            page_id = branch_elements.get(index.saturating_sub(1))?.pgid;
+        }
+    }
+
+    // overflow returns the number of the overflow pages of the page.
+    fn overflow(&mut self, page_id: u64) -> u64 {
+        let data = self.read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        page.overflow as u64
     }
 
     fn read_page_branch_elements(&mut self, data: &[u8]) -> Vec<BranchElement> {
-        let page: bolt::Page = TryFrom::try_from(data).unwrap();
+        self.try_read_page_branch_elements(data)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // try_read_page_branch_elements is the same as read_page_branch_elements, but the
+    // element which exceeds the page is returned as Corrupted instead of panic.
+    fn try_read_page_branch_elements(
+        &mut self,
+        data: &[u8],
+    ) -> Result<Vec<BranchElement>, DatabaseError> {
+        let page: bolt::Page = TryFrom::try_from(data)?;
         let mut branch_elements: Vec<BranchElement> = Vec::with_capacity(page.count as usize);
-        for i in 0..page.count {
-            let start = (16 + i * 16) as usize;
-            let branch_element: bolt::BranchPageElement =
-                bolt::BranchPageElement::try_from(data.get(start..data.len()).unwrap()).unwrap();
-            let key_start = 16 + i * 16 + branch_element.pos as u16;
-            let key_data = data
-                .get((key_start as usize)..((key_start + branch_element.ksize as u16) as usize))
-                .unwrap();
+        // The offsets are computed in usize, the positions and sizes of the elements
+        // of the pages larger than 64KB (or with overflow pages) don't fit in u16.
+        for i in 0..page.count as usize {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            let branch_element: bolt::BranchPageElement = bolt::BranchPageElement::try_from(
+                element_data(&page, data, i, start..data.len())?,
+            )?;
+            let key_start = start + branch_element.pos as usize;
+            let key_data = element_data(
+                &page,
+                data,
+                i,
+                key_start..key_start + branch_element.ksize as usize,
+            )?;
             branch_elements.push(BranchElement {
                 key: key_data.to_vec(),
                 pgid: branch_element.pgid.into(),
             });
         }
-        branch_elements
+        Ok(branch_elements)
     }
 
     fn read_page_leaf_elements(&mut self, data: &[u8]) -> Vec<LeafElement> {
-        let page: bolt::Page = TryFrom::try_from(data).unwrap();
+        self.try_read_page_leaf_elements(data)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // try_read_page_leaf_elements is the same as read_page_leaf_elements, but the
+    // element which exceeds the page is returned as Corrupted instead of panic.
+    fn try_read_page_leaf_elements(
+        &mut self,
+        data: &[u8],
+    ) -> Result<Vec<LeafElement>, DatabaseError> {
+        let page: bolt::Page = TryFrom::try_from(data)?;
         let mut leaf_elements: Vec<LeafElement> = Vec::with_capacity(page.count as usize);
-        for i in 0..page.count {
-            let start = (16 + i * 16) as usize;
+        for i in 0..page.count as usize {
+            let start = PAGE_HEADER_SIZE + i * 16;
             let leaf_element: bolt::LeafPageElement =
-                bolt::LeafPageElement::try_from(data.get(start..data.len()).unwrap()).unwrap();
-
-            let key_start = 16 + i * 16 + (leaf_element.pos as u16);
-            let key_end = key_start + (leaf_element.ksize as u16);
-            let key = data.get((key_start as usize)..(key_end as usize)).unwrap();
-            let value = data
-                .get((key_end as usize)..((key_end + leaf_element.vsize as u16) as usize))
-                .unwrap();
+                bolt::LeafPageElement::try_from(element_data(&page, data, i, start..data.len())?)?;
+
+            let key_start = start + leaf_element.pos as usize;
+            let key_end = key_start + leaf_element.ksize as usize;
+            let key = element_data(&page, data, i, key_start..key_end)?;
+            let value = element_data(
+                &page,
+                data,
+                i,
+                key_end..key_end + leaf_element.vsize as usize,
+            )?;
             if leaf_element.flags == 0x01 {
+                // The root page id and sequence are read from the bucket header.
+                if value.len() < 16 {
+                    return Err(DatabaseError::Corrupted {
+                        reason: format!(
+                            "bucket {} has a value of {} bytes, the bucket header is 16 bytes",
+                            String::from_utf8_lossy(key),
+                            value.len()
+                        ),
+                    });
+                }
                 let bucket_page_id = self.read_page_u64(value, 0);
+                let sequence = self.read_page_u64(value, 8);
                 if bucket_page_id == 0 {
-                    let page_leaf_elements = self.read_page_leaf_elements(value);
+                    // The inline page is stored just after the bucket header, bolt
+                    // never stores the sub buckets in it.
+                    let items = self
+                        .try_read_page_leaf_elements(&value[16..])?
+                        .into_iter()
+                        .map(|x| match x {
+                            LeafElement::KeyValue(kv) => Ok(kv),
+                            _ => Err(DatabaseError::Corrupted {
+                                reason: format!(
+                                    "inline bucket {} has a sub bucket",
+                                    String::from_utf8_lossy(key)
+                                ),
+                            }),
+                        })
+                        .collect::<Result<_, _>>()?;
                     leaf_elements.push(LeafElement::InlineBucket {
                         name: key.to_vec(),
-                        items: page_leaf_elements
-                            .into_iter()
-                            .map(|x| match x {
-                                LeafElement::KeyValue(kv) => kv,
-                                _ => panic!("unreachable"),
-                            })
-                            .collect(),
+                        sequence,
+                        items,
                     });
                 } else {
                     leaf_elements.push(LeafElement::Bucket {
                         name: key.to_vec(),
                         pgid: bucket_page_id,
+                        sequence,
                     });
                 }
             } else {
                 leaf_elements.push(LeafElement::KeyValue(KeyValue {
                     key: key.to_vec(),
-                    value: value.to_vec(),
+                    value: Bytes::from(value),
                 }));
             }
         }
-        leaf_elements
+        Ok(leaf_elements)
     }
 
-    fn read_meta_page(&mut self, data: &[u8]) -> bolt::Meta {
-        let page: bolt::Page = TryFrom::try_from(data).unwrap();
+    // read_meta_page decodes the meta page, the meta page whose flags, checksum or
+    // magic number is invalid is returned as Corrupted.
+    fn read_meta_page(&mut self, data: &[u8]) -> Result<bolt::Meta, DatabaseError> {
+        let page: bolt::Page = TryFrom::try_from(data)?;
         if !page.flags.contains(bolt::PageFlag::MetaPageFlag) {
-            panic!(
-                "read_page_overflow: page 0 is not a meta page, expect flag {}, got {}",
-                bolt::PageFlag::MetaPageFlag.as_u16(),
-                page.flags.as_u16()
-            );
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "page {} is not a meta page, expect flag {}, got {}",
+                    page.id.0,
+                    bolt::PageFlag::MetaPageFlag.as_u16(),
+                    page.flags.as_u16()
+                ),
+            });
         }
-        let actual_checksum =
-            u64::from_be_bytes(Fnv64::hash(&data[16..72]).as_bytes().try_into().unwrap());
-        let meta: bolt::Meta = TryFrom::try_from(data).unwrap();
+        let meta: bolt::Meta = TryFrom::try_from(data)?;
+        let actual_checksum = utils::fnv64(&data[16..72]);
         if meta.checksum != actual_checksum {
-            panic!(
-                "checksum mismatch, expect {}, got {}",
-                actual_checksum, meta.checksum
-            );
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "checksum mismatch, expect {}, got {}",
+                    actual_checksum, meta.checksum
+                ),
+            });
         }
         if meta.magic != bolt::MAGIC_NUMBER {
-            panic!(
-                "invalid magic number, expect {}, got {}",
-                bolt::MAGIC_NUMBER,
-                meta.magic
-            );
-        }
-        if meta.version != bolt::DATAFILE_VERSION {
-            panic!(
-                "invalid version number, expect {}, got {}",
-                bolt::DATAFILE_VERSION,
-                meta.version
-            );
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "invalid magic number, expect {}, got {}",
+                    bolt::MAGIC_NUMBER,
+                    meta.magic
+                ),
+            });
         }
-        meta
+        Ok(meta)
     }
 
     fn initialize(&mut self) {
-        let data0 = self.read_page(0);
-        let meta0 = self.read_meta_page(&data0);
-        self.meta0 = Some(meta0);
-
-        let data1 = self.read_page(1);
-        let meta1 = self.read_meta_page(&data1);
-        self.meta1 = Some(meta1);
+        self.try_initialize()
+            .unwrap_or_else(|err| panic!("{}", err));
     }
 
-    fn get_meta(&mut self) -> bolt::Meta {
-        if self.meta0.is_none() && self.meta1.is_none() {
-            panic!("meta0 and meta1 are not initialized");
+    // detect_page_size returns the page size which the pages are read with, it's the
+    // page size of the meta page 0 unless it's forced by the options.
+    pub(crate) fn detect_page_size(&mut self) -> Result<u64, DatabaseError> {
+        // The meta page 0 is at the start of the file whatever the page size is, so
+        // the page size is detected from it before any page is read.
+        let mut header = self.try_read(0, 80)?;
+        // The byte order of the file is detected by the magic number.
+        let magic = utils::read_value::<u32>(&header, 16);
+        let file_endian = if magic == bolt::MAGIC_NUMBER {
+            Some(Endian::native())
+        } else if magic == bolt::MAGIC_NUMBER.swap_bytes() {
+            Some(Endian::native().other())
+        } else {
+            None
+        };
+        if let Some(file_endian) = file_endian.filter(|&endian| endian != self.options.endian) {
+            return Err(DatabaseError::Unsupported {
+                reason: format!(
+                    "the database is written in {} endian, but it's read in {} endian",
+                    file_endian, self.options.endian
+                ),
+            });
         }
-
-        if self.meta0.is_none() {
+        if self.options.endian.is_swapped() {
+            bolt::swap_page(&mut header);
+        }
+        let meta: bolt::Meta = TryFrom::try_from(header.as_slice())?;
+        let detected = if meta.page_size.is_power_of_two() {
+            meta.page_size
+        } else {
+            4096
+        };
+        let page_size = match self.options.page_size {
+            Some(requested) if requested != detected => {
+                if !self.options.force_page_size {
+                    return Err(DatabaseError::PageSizeMismatch {
+                        meta: detected,
+                        requested,
+                    });
+                }
+                warn!(
+                    meta = detected,
+                    requested, "read the pages with the forced page size"
+                );
+                requested as u64
+            }
+            _ => detected as u64,
+        };
+        Ok(page_size)
+    }
+
+    fn try_initialize(&mut self) -> Result<(), DatabaseError> {
+        self.page_size = self.detect_page_size()?;
+
+        let data0 = self.try_read_page(0)?;
+        let meta0 = self.read_meta_page(&data0)?;
+        self.check_version(&meta0)?;
+        self.meta0 = Some(meta0);
+
+        let data1 = self.try_read_page(1)?;
+        let meta1 = self.read_meta_page(&data1)?;
+        self.check_version(&meta1)?;
+        self.meta1 = Some(meta1);
+
+        #[cfg(feature = "disk-cache")]
+        self.load_disk_cache();
+        Ok(())
+    }
+
+    // load_disk_cache adds the pages of the disk cache once the txid is known, the
+    // pages which are already read are kept.
+    #[cfg(feature = "disk-cache")]
+    fn load_disk_cache(&mut self) {
+        let txid = self.get_meta().txid;
+        let page_size = self.page_size;
+        let Some(disk_cache) = self.disk_cache.as_mut().filter(|c| !c.is_loaded()) else {
+            return;
+        };
+        for (page_id, data) in disk_cache.load(page_size, txid) {
+            self.page_datas.entry(page_id).or_insert(data);
+        }
+    }
+
+    // check_version returns UnsupportedVersion if the version of the meta isn't
+    // DATAFILE_VERSION, the versions in COMPAT_VERSIONS are accepted with a warning
+    // in the compat mode.
+    fn check_version(&self, meta: &bolt::Meta) -> Result<(), DatabaseError> {
+        if meta.version == bolt::DATAFILE_VERSION {
+            return Ok(());
+        }
+        if self.options.compat && bolt::COMPAT_VERSIONS.contains(&meta.version) {
+            // The metas are read again by each iteration, only the first reading
+            // is warned.
+            if self.meta1.is_some() {
+                return Ok(());
+            }
+            warn!(
+                version = meta.version,
+                "read the database with the layout of version {}",
+                bolt::DATAFILE_VERSION
+            );
+            return Ok(());
+        }
+        Err(DatabaseError::UnsupportedVersion {
+            found: meta.version,
+            supported: bolt::DATAFILE_VERSION,
+        })
+    }
+
+    // meta_page_id returns the page id of the meta which is returned by get_meta.
+    fn meta_page_id(&mut self) -> u64 {
+        match (self.meta0, self.meta1) {
+            (Some(meta0), Some(meta1)) if meta0.txid > meta1.txid => 0,
+            (Some(_), None) => 0,
+            _ => 1,
+        }
+    }
+
+    // meta_info returns the information of the meta page, which must be initialized.
+    fn meta_info(&mut self, page_id: u64) -> Info {
+        let meta = if page_id == 0 { self.meta0 } else { self.meta1 };
+        let mut info: Info = From::from(meta.unwrap());
+        let data = self.read_page(page_id);
+        let reserved = &data[80..];
+        let end = reserved.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        info.reserved = utils::hex(&reserved[..end]);
+        info
+    }
+
+    fn get_meta(&mut self) -> bolt::Meta {
+        if self.meta0.is_none() && self.meta1.is_none() {
+            panic!("meta0 and meta1 are not initialized");
+        }
+
+        if self.meta0.is_none() {
             return self.meta1.unwrap();
         }
 
@@ -268,84 +1332,1135 @@ impl DB {
         self.meta1.unwrap()
     }
 
-    fn read_page_u64(&mut self, page: &[u8], offset: u16) -> u64 {
-        let ptr: *const u8 = page.as_ptr();
-        unsafe {
-            let offset_ptr = ptr.offset(offset as isize);
-            let value_ptr = std::slice::from_raw_parts(offset_ptr, 8);
-            u64::from_le_bytes(value_ptr.try_into().unwrap())
+    pub(crate) fn limits(&self) -> ReadLimits {
+        self.options.limits
+    }
+
+    fn read_page_u64(&mut self, page: &[u8], offset: usize) -> u64 {
+        // The pages are decoded in the native byte order, see Endian.
+        utils::read_value::<u64>(page, offset)
+    }
+
+    // collect_tree returns the shape of the tree of the bucket from the root page.
+    fn collect_tree(&mut self, bucket: Vec<Vec<u8>>, root_page_id: u64) -> TreeStats {
+        let mut stats = TreeStats {
+            bucket,
+            ..Default::default()
+        };
+        (stats.min_leaf_depth, stats.max_leaf_depth) = self
+            .collect_tree_stats(root_page_id, 1, &mut stats, &mut PgidBitmap::new())
+            .unwrap_or((1, 1));
+        stats
+    }
+
+    // collect_bucket_info walks the pages of the bucket from the page, which is at
+    // the depth of the bucket's tree. The pages are visited once, so a corrupted
+    // tree with a cycle is walked to the end.
+    fn collect_bucket_info(
+        &mut self,
+        page_id: u64,
+        depth: u64,
+        info: &mut BucketInfo,
+        visited: &mut PgidBitmap,
+    ) {
+        if !visited.insert(page_id) {
+            return;
+        }
+        let data = self.read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        info.depth = info.depth.max(depth);
+        info.pages += page.overflow as u64 + 1;
+        info.capacity += data.len() as u64;
+
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            let branch_elements = self.read_page_branch_elements(&data);
+            info.used += PAGE_HEADER_SIZE as u64
+                + branch_elements
+                    .iter()
+                    .map(|elem| (16 + elem.key.len()) as u64)
+                    .sum::<u64>();
+            for elem in branch_elements {
+                self.collect_bucket_info(elem.pgid, depth + 1, info, visited);
+            }
+            return;
+        }
+
+        info.used += PAGE_HEADER_SIZE as u64;
+        for i in 0..page.count as usize {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            let elem = bolt::LeafPageElement::try_from(&data[start..]).unwrap();
+            info.used += 16 + elem.ksize as u64 + elem.vsize as u64;
+            info.inline_size += 16 + elem.ksize as u64 + elem.vsize as u64;
+            if elem.flags == 0x01 {
+                info.child_buckets += 1;
+            } else {
+                info.keys += 1;
+            }
+        }
+    }
+
+    // collect_tree_stats walks the tree from the page, and returns the min and max
+    // depth of the leaves in the subtree. It's None if the page is already visited.
+    fn collect_tree_stats(
+        &mut self,
+        page_id: u64,
+        depth: u64,
+        stats: &mut TreeStats,
+        visited: &mut PgidBitmap,
+    ) -> Option<(u64, u64)> {
+        if !visited.insert(page_id) {
+            return None;
+        }
+        let data = self.read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        if !page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            stats.leaves += 1;
+            stats.total_leaf_depth += depth;
+            return Some((depth, depth));
+        }
+
+        let branch_elements = self.read_page_branch_elements(&data);
+        *stats
+            .branching
+            .entry(branch_elements.len() as u64)
+            .or_default() += 1;
+        let ranges: Vec<(u64, u64)> = branch_elements
+            .iter()
+            .filter_map(|elem| self.collect_tree_stats(elem.pgid, depth + 1, stats, visited))
+            .collect();
+        if ranges.windows(2).any(|pair| pair[0] != pair[1]) {
+            stats.unbalanced.push(page_id);
+        }
+        Some(
+            ranges
+                .into_iter()
+                .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+                .unwrap_or((depth, depth)),
+        )
+    }
+
+    // collect_tree_pages walks the tree from the page, and records the bucket path
+    // which each page (including overflow pages) belongs to.
+    fn collect_tree_pages(
+        &mut self,
+        page_id: u64,
+        bucket: &[Vec<u8>],
+        pages: &mut BTreeMap<u64, Vec<Vec<u8>>>,
+    ) {
+        if pages.contains_key(&page_id) {
+            return;
+        }
+
+        let data = self.read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        for id in page_id..=(page_id + page.overflow as u64) {
+            pages.insert(id, bucket.to_vec());
+        }
+
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            for elem in self.read_page_branch_elements(&data) {
+                self.collect_tree_pages(elem.pgid, bucket, pages);
+            }
+        } else if page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+            for elem in self.read_page_leaf_elements(&data) {
+                if let LeafElement::Bucket { name, pgid, .. } = elem {
+                    let mut child_bucket = bucket.to_vec();
+                    child_bucket.push(name);
+                    self.collect_tree_pages(pgid, &child_bucket, pages);
+                }
+            }
         }
     }
 
+    // subtree_hash hashes the page's content together with the hashes of it's child
+    // pages and the root pages of it's sub buckets. The page which is already visited
+    // is hashed as 0, so a corrupted tree with a cycle is hashed to the end.
+    fn subtree_hash(&mut self, page_id: u64, visited: &mut PgidBitmap) -> u64 {
+        if !visited.insert(page_id) {
+            return 0;
+        }
+        let data = self.read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let mut hashes: Vec<u8> = utils::fnv64(&data).to_be_bytes().to_vec();
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            for elem in self.read_page_branch_elements(&data) {
+                hashes.extend_from_slice(&self.subtree_hash(elem.pgid, visited).to_be_bytes());
+            }
+        } else if page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+            for elem in self.read_page_leaf_elements(&data) {
+                if let LeafElement::Bucket { pgid, .. } = elem {
+                    hashes.extend_from_slice(&self.subtree_hash(pgid, visited).to_be_bytes());
+                }
+            }
+        }
+        utils::fnv64(&hashes)
+    }
+
     fn read_freelist(&mut self, page: &[u8], count: u16) -> Vec<u64> {
+        self.try_read_freelist(page, count)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn try_read_freelist(&mut self, page: &[u8], count: u16) -> Result<Vec<u64>, DatabaseError> {
+        // The real count is stored in the first element if it overflows u16.
+        let (count, start) = if count == 0xFFFF {
+            (self.read_page_u64(page, 16), 24)
+        } else {
+            (count as u64, 16)
+        };
+        let page_id = utils::read_value::<u64>(page, 0);
+        self.options.limits.check_elements(page_id, count)?;
+        if count > ((page.len() - start) / 8) as u64 {
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "the {} ids of freelist page {} exceed the page size {}",
+                    count,
+                    page_id,
+                    page.len()
+                ),
+            });
+        }
         let mut freelist: Vec<u64> = Vec::with_capacity(count as usize);
-        for i in 0..count {
-            freelist.push(self.read_page_u64(page, i * 8 + 16));
+        for i in 0..count as usize {
+            freelist.push(self.read_page_u64(page, start + i * 8));
         }
-        freelist
+        Ok(freelist)
     }
 
     pub fn build(ancla_options: AnclaOptions) -> Rc<RefCell<DB>> {
-        let file = File::open(ancla_options.db_path.clone()).unwrap();
+        let snapshot_path = if ancla_options.snapshot_before_read {
+            Some(DB::snapshot(&ancla_options.db_path).unwrap())
+        } else {
+            None
+        };
+        let source = match &snapshot_path {
+            Some(path) => FileSource::open(path.to_str().unwrap()).unwrap(),
+            None => FileSource::open(&ancla_options.db_path).unwrap(),
+        };
+        let cache_identity = if ancla_options.page_cache {
+            FileIdentity::of(&ancla_options.db_path).ok()
+        } else {
+            None
+        };
+
+        let db = DB::open_from_source(ancla_options, source);
+        db.borrow_mut().snapshot_path = snapshot_path;
+        if let Some(identity) = cache_identity {
+            let mut db = db.borrow_mut();
+            db.page_datas = cache::load(&identity);
+            db.cache_identity = Some(identity);
+        }
+        db
+    }
+
+    // snapshot copies the file to the temporary directory, the copy is cheap if the
+    // filesystem supports reflink (e.g. btrfs, xfs), since std::fs::copy uses
+    // copy_file_range on linux.
+    fn snapshot(path: &str) -> io::Result<PathBuf> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let snapshot_path = env::temp_dir().join(format!("ancla-{}-{}.db", process::id(), nanos));
+        fs::copy(path, &snapshot_path)?;
+        debug!(path, snapshot = %snapshot_path.display(), "snapshot the database");
+        Ok(snapshot_path)
+    }
+
+    // open_from_bytes opens the database which is already loaded into memory, e.g.
+    // downloaded from an object store or read from stdin.
+    pub fn open_from_bytes(ancla_options: AnclaOptions, data: Vec<u8>) -> Rc<RefCell<DB>> {
+        DB::open_from_source(ancla_options, MemorySource::new(data))
+    }
+
+    // open_from_reader opens the database from any random access reader, the
+    // `db_path` of the options is not used in this case.
+    pub fn open_from_reader(
+        ancla_options: AnclaOptions,
+        reader: impl Read + Seek + 'static,
+    ) -> Rc<RefCell<DB>> {
+        DB::open_from_source(ancla_options, ReaderSource::new(reader))
+    }
+
+    // open_from_source opens the database from the storage abstraction, e.g. an
+    // object in remote storage.
+    pub fn open_from_source(
+        ancla_options: AnclaOptions,
+        source: impl PageSource + 'static,
+    ) -> Rc<RefCell<DB>> {
+        #[cfg(feature = "disk-cache")]
+        let disk_cache = match &ancla_options.disk_cache_dir {
+            Some(dir) if !ancla_options.db_path.is_empty() => {
+                DiskCache::new(dir, &ancla_options.db_path)
+                    .inspect_err(|e| warn!(%e, "open the disk cache failed"))
+                    .ok()
+            }
+            _ => None,
+        };
         Rc::new(RefCell::new(DB {
             options: ancla_options,
-            file,
-            pages: BTreeMap::new(),
+            source: Box::new(source),
             page_datas: BTreeMap::new(),
             meta0: None,
             meta1: None,
+            page_size: 4096,
+            cache_identity: None,
+            snapshot_path: None,
+            read_stats: MetricsSnapshot::default(),
+            #[cfg(feature = "disk-cache")]
+            disk_cache,
         }))
     }
 
-    pub fn iter_buckets(db: Rc<RefCell<DB>>) -> impl Iterator<Item = Bucket> {
+    // read_stats returns the IO counters of this database since it's opened, unlike
+    // the process-wide metrics they only count the reads of this database.
+    pub fn read_stats(db: Rc<RefCell<DB>>) -> MetricsSnapshot {
+        db.borrow().read_stats
+    }
+
+    pub fn info(db: Rc<RefCell<DB>>) -> Info {
+        let mut db = db.borrow_mut();
+        db.initialize();
+        let page_id = db.meta_page_id();
+        db.meta_info(page_id)
+    }
+
+    // info_with_page_types returns the information with the page type counts, all
+    // reachable pages are read. The progress is called with the number of pages
+    // counted and the max page id after each page.
+    pub fn info_with_page_types(db: Rc<RefCell<DB>>, mut progress: impl FnMut(u64, u64)) -> Info {
+        let mut info = DB::info(db.clone());
+        let mut counts = PageTypeCounts::default();
+        let mut counted = 0;
+        for page in DB::iter_pages(db) {
+            match page.typ {
+                PageType::Meta => counts.meta += 1,
+                PageType::Freelist => counts.freelist += 1,
+                PageType::DataBranch => counts.branch += 1,
+                PageType::DataLeaf => counts.leaf += 1,
+                PageType::Free => counts.free += 1,
+            }
+            counts.overflow += page.overflow;
+            counted += page.overflow + 1;
+            progress(counted, info.max_page_id);
+        }
+        counts.unreachable = info.max_page_id.saturating_sub(counted);
+        info.page_types = Some(counts);
+        info
+    }
+
+    // try_info is the same as info, but the page size which mismatches the meta is
+    // returned as PageSizeMismatch instead of panic.
+    pub fn try_info(db: Rc<RefCell<DB>>) -> Result<Info, DatabaseError> {
+        let mut db = db.borrow_mut();
+        db.try_initialize()?;
+        let page_id = db.meta_page_id();
+        Ok(db.meta_info(page_id))
+    }
+
+    // analyze_tx estimates the pages written by the last transaction, which are the
+    // pages reachable from the current meta but not from the previous one.
+    pub fn analyze_tx(db: Rc<RefCell<DB>>) -> TxAnalysis {
+        let mut db = db.borrow_mut();
+        db.initialize();
+        let meta0 = db.meta_info(0);
+        let meta1 = db.meta_info(1);
+        let (current, previous) = if meta0.txid > meta1.txid {
+            (&meta0, &meta1)
+        } else {
+            (&meta1, &meta0)
+        };
+
+        let mut current_pages = BTreeMap::new();
+        db.collect_tree_pages(current.root_page_id, &[], &mut current_pages);
+        let mut previous_pages = BTreeMap::new();
+        db.collect_tree_pages(previous.root_page_id, &[], &mut previous_pages);
+
+        let mut changed_pages: Vec<ChangedPage> = current_pages
+            .into_iter()
+            .filter(|(id, _)| !previous_pages.contains_key(id))
+            .map(|(id, bucket)| ChangedPage {
+                id,
+                bucket: Some(bucket),
+            })
+            .collect();
+        if current.freelist_page_id != previous.freelist_page_id {
+            changed_pages.push(ChangedPage {
+                id: current.freelist_page_id,
+                bucket: None,
+            });
+            changed_pages.sort_by_key(|page| page.id);
+        }
+
+        TxAnalysis {
+            meta0,
+            meta1,
+            changed_pages,
+        }
+    }
+
+    // diff_pages compares the database files page by page, and returns the pages of
+    // the new one which are different from the old one or beyond the end of it. The
+    // pages are at different offsets if the page sizes differ, so it returns
+    // Unsupported for them.
+    pub fn diff_pages(
+        old: Rc<RefCell<DB>>,
+        new: Rc<RefCell<DB>>,
+    ) -> Result<Vec<ChangedPage>, DatabaseError> {
+        let mut new = new.borrow_mut();
+        new.try_initialize()?;
+        let mut old = old.borrow_mut();
+        old.try_initialize()?;
+        if old.page_size != new.page_size {
+            return Err(DatabaseError::Unsupported {
+                reason: format!(
+                    "the page size {} of the old database differs from the page size {}",
+                    old.page_size, new.page_size
+                ),
+            });
+        }
+
+        let meta = new.get_meta();
+        let mut buckets = BTreeMap::new();
+        new.collect_tree_pages(meta.root_pgid.into(), &[], &mut buckets);
+        let old_max_page_id: u64 = old.get_meta().max_pgid.into();
+        let page_size = new.page_size;
+        let mut changed = Vec::new();
+        for id in 0..meta.max_pgid.into() {
+            // The pages are compared as they are stored, the old one may be
+            // truncated.
+            let is_changed = id >= old_max_page_id || {
+                let data = new.try_read(id * page_size, page_size as usize)?;
+                old.try_read(id * page_size, page_size as usize)
+                    .map_or(true, |old_data| old_data != data)
+            };
+            if is_changed {
+                changed.push(ChangedPage {
+                    id,
+                    bucket: buckets.get(&id).cloned(),
+                });
+            }
+        }
+        Ok(changed)
+    }
+
+    // freelist returns the pages in the freelist of the current meta, ordered by id.
+    // bolt writes the pending pages, which are freed by the last transaction but may
+    // still be read by the readers of the previous one, into the freelist with the
+    // free pages. They are detected as the pages reachable from the previous meta.
+    pub fn freelist(db: Rc<RefCell<DB>>) -> Vec<FreePage> {
+        let mut db = db.borrow_mut();
+        db.initialize();
+        let meta = db.get_meta();
+        let freelist_page_id: u64 = meta.freelist_pgid.into();
+        // The freelist isn't persisted if it's u64::MAX (NoFreelistSync).
+        if freelist_page_id == u64::MAX {
+            return Vec::new();
+        }
+        let data = db.read_page(freelist_page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let mut ids = db.read_freelist(&data, page.count);
+        ids.sort_unstable();
+
+        let mut previous_pages = BTreeMap::new();
+        if let (Some(meta0), Some(meta1)) = (db.meta0, db.meta1) {
+            let previous = if meta.txid == meta0.txid {
+                meta1
+            } else {
+                meta0
+            };
+            db.collect_tree_pages(previous.root_pgid.into(), &[], &mut previous_pages);
+            let previous_freelist: u64 = previous.freelist_pgid.into();
+            if previous_freelist != u64::MAX && previous_freelist != freelist_page_id {
+                let data = db.read_page(previous_freelist);
+                let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+                for id in previous_freelist..=previous_freelist + page.overflow as u64 {
+                    previous_pages.insert(id, Vec::new());
+                }
+            }
+        }
+
+        ids.into_iter()
+            .map(|id| FreePage {
+                id,
+                pending: previous_pages.contains_key(&id),
+            })
+            .collect()
+    }
+
+    // page_hash returns the content hash of the page, including it's overflow pages.
+    pub fn page_hash(db: Rc<RefCell<DB>>, page_id: u64) -> u64 {
+        let data = db.borrow_mut().read_page(page_id);
+        utils::fnv64(&data)
+    }
+
+    // read_range reads the raw bytes of the database file, the range is truncated
+    // at the end of the last page.
+    pub fn read_range(db: Rc<RefCell<DB>>, offset: u64, length: u64) -> Vec<u8> {
+        let max_page_id = DB::info(db.clone()).max_page_id;
+        let page_size = db.borrow().page_size;
+        let end = offset.saturating_add(length).min(max_page_id * page_size);
+        if end <= offset {
+            return Vec::new();
+        }
+        db.borrow_mut().read(offset, (end - offset) as usize)
+    }
+
+    // page_layout returns the regions of the page, the page must not be a free page
+    // because the content of it is meaningless.
+    pub fn page_layout(db: Rc<RefCell<DB>>, page_id: u64) -> PageLayout {
+        let data = db.borrow_mut().read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let header = 0..PAGE_HEADER_SIZE as u64;
+        let elements_end = PAGE_HEADER_SIZE as u64 + page.count as u64 * 16;
+
+        let (typ, regions) = if page.flags.contains(bolt::PageFlag::MetaPageFlag) {
+            (
+                PageType::Meta,
+                vec![(header, PageRegion::Header), (16..80, PageRegion::Meta)],
+            )
+        } else if page.flags.contains(bolt::PageFlag::FreelistPageFlag) {
+            let freelist = 16..(16 + page.count as u64 * 8);
+            (
+                PageType::Freelist,
+                vec![
+                    (header, PageRegion::Header),
+                    (freelist, PageRegion::Freelist),
+                ],
+            )
+        } else {
+            let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+            // The keys and values of the corrupted page are not annotated.
+            let kv_end = calculate_used(&data, &page).map_or(elements_end, |(_, end)| end);
+            let typ = if is_branch {
+                PageType::DataBranch
+            } else {
+                PageType::DataLeaf
+            };
+            (
+                typ,
+                vec![
+                    (header, PageRegion::Header),
+                    (16..elements_end, PageRegion::Elements),
+                    (elements_end..kv_end, PageRegion::KeyValues),
+                ],
+            )
+        };
+
+        PageLayout {
+            page_id,
+            typ,
+            overflow: page.overflow as u64,
+            regions,
+        }
+    }
+
+    // locate returns the page, region, element and bucket which the offset of the
+    // file belongs to. All pages are visited, but the keys and values are not read,
+    // it's still expensive to call it for many offsets.
+    pub fn locate(db: Rc<RefCell<DB>>, offset: u64) -> Location {
+        db.borrow_mut().initialize();
+        let page_size = db.borrow().page_size;
+        let page_id = offset / page_size;
+        let pages: BTreeMap<u64, PageInfo> = DB::iter_pages(db.clone())
+            .map(|page| (page.id, page))
+            .collect();
+        let mut location = Location {
+            offset,
+            page_id,
+            owner_page_id: None,
+            typ: None,
+            region: None,
+            element_index: None,
+            bucket: None,
+        };
+        let Some(page) = pages
+            .range(..=page_id)
+            .next_back()
+            .map(|(_, page)| *page)
+            .filter(|page| page_id <= page.id + page.overflow)
+        else {
+            return location;
+        };
+        location.owner_page_id = Some(page.id);
+        location.typ = Some(page.typ);
+        if page.typ == PageType::Free {
+            return location;
+        }
+
+        let relative = offset - page.id * page_size;
+        location.region = Some(DB::page_layout(db.clone(), page.id).region(relative));
+        if !matches!(page.typ, PageType::DataBranch | PageType::DataLeaf) {
+            return location;
+        }
+
+        let data = db.borrow_mut().read_page(page.id);
+        location.element_index = element_at(&data, relative);
+
+        // The bucket elements on the path from the root page are the bucket path, so
+        // the page referenced twice belongs to the same bucket as in page_path.
+        if let PagePath::Tree { steps, .. } = DB::page_path(db, page.id) {
+            location.bucket = Some(
+                steps
+                    .into_iter()
+                    .filter(|step| step.kind == ElementKind::Bucket)
+                    .map(|step| step.key)
+                    .collect(),
+            );
+        }
+        location
+    }
+
+    // page_elements returns the elements of the branch or leaf page, it's empty for
+    // other pages.
+    pub fn page_elements(db: Rc<RefCell<DB>>, page_id: u64) -> Vec<PageElement> {
+        let data = db.borrow_mut().read_page(page_id);
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+        if !is_branch && !page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+            return Vec::new();
+        }
+        calculate_used(&data, &page).unwrap_or_else(|err| panic!("{}", err));
+
+        let mut elements = Vec::with_capacity(page.count as usize);
+        for i in 0..page.count as usize {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            let element = if is_branch {
+                let element = bolt::BranchPageElement::try_from(&data[start..]).unwrap();
+                let key_start = start + element.pos as usize;
+                PageElement {
+                    page_id,
+                    index: i as u64,
+                    kind: ElementKind::Branch,
+                    flags: 0,
+                    key: data[key_start..key_start + element.ksize as usize].to_vec(),
+                    ksize: element.ksize as u64,
+                    vsize: 0,
+                    child_page_id: Some(element.pgid.0),
+                }
+            } else {
+                let element = bolt::LeafPageElement::try_from(&data[start..]).unwrap();
+                let key_start = start + element.pos as usize;
+                let key_end = key_start + element.ksize as usize;
+                let (kind, child_page_id) = if element.flags == 0x01 {
+                    let bucket: bolt::Bucket = TryFrom::try_from(&data[key_end..]).unwrap();
+                    (
+                        ElementKind::Bucket,
+                        Some(bucket.root.0).filter(|&root| root != 0),
+                    )
+                } else {
+                    (ElementKind::KeyValue, None)
+                };
+                PageElement {
+                    page_id,
+                    index: i as u64,
+                    kind,
+                    flags: element.flags,
+                    key: data[key_start..key_end].to_vec(),
+                    ksize: element.ksize as u64,
+                    vsize: element.vsize as u64,
+                    child_page_id,
+                }
+            };
+            elements.push(element);
+        }
+        elements
+    }
+
+    // page_path returns how the page is reached from the meta. The tree is walked
+    // from the root page until the page is found, the element pointing to each page
+    // is recorded as the reverse index, so the path is climbed back from the page.
+    pub fn page_path(db: Rc<RefCell<DB>>, page_id: u64) -> PagePath {
+        let meta = DB::read_tx(db.clone()).meta;
+        if page_id < 2 {
+            return PagePath::Meta;
+        }
+        // The freelist isn't persisted if it's u64::MAX (NoFreelistSync).
+        let freelist_page_id: u64 = meta.freelist_pgid.into();
+        if freelist_page_id != u64::MAX
+            && (freelist_page_id..=freelist_page_id + db.borrow_mut().overflow(freelist_page_id))
+                .contains(&page_id)
+        {
+            return PagePath::Freelist;
+        }
+
+        let root_page_id: u64 = meta.root_pgid.into();
+        let mut parents: HashMap<u64, PageStep> = HashMap::new();
+        let mut stack = vec![root_page_id];
+        let mut found = None;
+        while let Some(id) = stack.pop() {
+            let overflow = db.borrow_mut().overflow(id);
+            if (id..=id + overflow).contains(&page_id) {
+                found = Some(id);
+                break;
+            }
+            for element in DB::page_elements(db.clone(), id) {
+                let Some(child_page_id) = element.child_page_id else {
+                    continue;
+                };
+                // The page referenced twice is reached by the first reference.
+                if child_page_id == root_page_id || parents.contains_key(&child_page_id) {
+                    continue;
+                }
+                parents.insert(
+                    child_page_id,
+                    PageStep {
+                        page_id: id,
+                        index: element.index,
+                        kind: element.kind,
+                        key: element.key,
+                        child_page_id,
+                    },
+                );
+                stack.push(child_page_id);
+            }
+        }
+
+        let Some(found) = found else {
+            return match DB::freelist(db).into_iter().find(|page| page.id == page_id) {
+                Some(page) => PagePath::Free {
+                    pending: page.pending,
+                },
+                None => PagePath::Unreachable,
+            };
+        };
+        let mut steps = Vec::new();
+        let mut current = found;
+        while let Some(step) = parents.remove(&current) {
+            current = step.page_id;
+            steps.push(step);
+        }
+        steps.reverse();
+        PagePath::Tree {
+            root_page_id,
+            steps,
+            overflow_of: Some(found).filter(|&id| id != page_id),
+        }
+    }
+
+    // iter_page_elements iterates the elements of all reachable branch and leaf pages,
+    // the pages are visited in the order of the page iterator.
+    pub fn iter_page_elements(db: Rc<RefCell<DB>>) -> impl Iterator<Item = PageElement> {
+        let filter = PageFilter::builder()
+            .types(vec![PageType::DataBranch, PageType::DataLeaf])
+            .build();
+        DB::iter_pages_filtered(db.clone(), filter)
+            .flat_map(move |page| DB::page_elements(db.clone(), page.id))
+    }
+
+    // get_bucket returns the bucket at the path, the first element of the path is
+    // the name of the top level bucket.
+    pub fn get_bucket(db: Rc<RefCell<DB>>, path: &[&[u8]]) -> Option<Bucket> {
+        DB::read_tx(db).get_bucket(path)
+    }
+
+    // tree_stats returns the shape of the B-tree of the root bucket and all buckets,
+    // in depth-first order.
+    pub fn tree_stats(db: Rc<RefCell<DB>>) -> Vec<TreeStats> {
         db.borrow_mut().initialize();
         let meta = db.borrow_mut().get_meta();
 
+        let mut trees = vec![db
+            .borrow_mut()
+            .collect_tree(Vec::new(), meta.root_pgid.into())];
+        for item in DB::iter_items(db.clone(), None) {
+            let DbItem::Bucket(bucket) = item else {
+                continue;
+            };
+            if bucket.is_inline {
+                trees.push(TreeStats {
+                    bucket: bucket.path,
+                    leaves: 1,
+                    min_leaf_depth: 1,
+                    max_leaf_depth: 1,
+                    total_leaf_depth: 1,
+                    ..Default::default()
+                });
+                continue;
+            }
+            trees.push(db.borrow_mut().collect_tree(bucket.path, bucket.page_id));
+        }
+        trees
+    }
+
+    // estimate_key_count estimates the number of keys of the bucket at the path, see
+    // Bucket::estimate_key_count.
+    // It's None if the bucket doesn't exist.
+    pub fn estimate_key_count(
+        db: Rc<RefCell<DB>>,
+        path: &[&[u8]],
+        max_error: f64,
+    ) -> Result<Option<KeyCountEstimate>, DatabaseError> {
+        DB::get_bucket(db, path)
+            .map(|bucket| bucket.estimate_key_count(max_error))
+            .transpose()
+    }
+
+    // first_key returns the first key-value pair of the bucket at the path, see
+    // Bucket::first. It's None if the bucket doesn't exist or doesn't have any key.
+    pub fn first_key(db: Rc<RefCell<DB>>, path: &[&[u8]]) -> Option<(Vec<u8>, Bytes)> {
+        DB::get_bucket(db, path)?.first()
+    }
+
+    // locate_key returns where the key of the bucket at the path is stored, see
+    // Bucket::locate. It's None if the bucket or the key doesn't exist.
+    pub fn locate_key(db: Rc<RefCell<DB>>, path: &[&[u8]], key: &[u8]) -> Option<KeyLocation> {
+        DB::get_bucket(db, path)?.locate(key)
+    }
+
+    // sample_keys returns n key-value pairs of the bucket at the path chosen at
+    // random, see Bucket::sample.
+    pub fn sample_keys(
+        db: Rc<RefCell<DB>>,
+        path: &[&[u8]],
+        n: usize,
+        seed: u64,
+    ) -> Option<Vec<(Vec<u8>, Bytes)>> {
+        DB::get_bucket(db, path).map(|bucket| bucket.sample(n, seed))
+    }
+
+    // last_key returns the last key-value pair of the bucket at the path, see
+    // Bucket::last.
+    pub fn last_key(db: Rc<RefCell<DB>>, path: &[&[u8]]) -> Option<(Vec<u8>, Bytes)> {
+        DB::get_bucket(db, path)?.last()
+    }
+
+    // iter_bucket_items_rev iterates the items of the bucket at the path in the
+    // reverse order, see Bucket::iter_items_rev.
+    pub fn iter_bucket_items_rev(
+        db: Rc<RefCell<DB>>,
+        path: &[&[u8]],
+    ) -> Option<BucketItemRevIterator> {
+        DB::get_bucket(db, path).map(|bucket| bucket.iter_items_rev())
+    }
+
+    // bucket_info returns the statistics of the bucket at the path.
+    pub fn bucket_info(db: Rc<RefCell<DB>>, path: &[&[u8]]) -> Option<BucketInfo> {
+        DB::get_bucket(db, path).map(|bucket| bucket.info())
+    }
+
+    // get_bucket_by_id returns the bucket with the identifier, it walks the bucket
+    // tree until the bucket is found.
+    pub fn get_bucket_by_id(db: Rc<RefCell<DB>>, id: &BucketIdentifier) -> Option<Bucket> {
+        let mut stack: Vec<Bucket> = DB::iter_buckets(db).collect();
+        stack.reverse();
+        while let Some(bucket) = stack.pop() {
+            if bucket.id() == *id {
+                return Some(bucket);
+            }
+
+            let mut child_buckets: Vec<Bucket> = bucket.iter_buckets().collect();
+            child_buckets.reverse();
+            stack.append(&mut child_buckets);
+        }
+        None
+    }
+
+    // iter_items iterates all key-value pairs and buckets of the database in
+    // depth-first order, the buckets deeper than max_depth levels are not visited.
+    pub fn iter_items(db: Rc<RefCell<DB>>, max_depth: Option<usize>) -> DbItemIterator {
+        DB::iter_items_with(db, ReadOptions::builder().max_depth(max_depth).build())
+    }
+
+    // iter_items_with iterates the items of the database which are selected by the
+    // options, in depth-first order.
+    pub fn iter_items_with(db: Rc<RefCell<DB>>, options: ReadOptions) -> DbItemIterator {
+        DB::read_tx(db).iter_items_with(options)
+    }
+
+    pub fn iter_buckets(db: Rc<RefCell<DB>>) -> impl Iterator<Item = Bucket> {
+        DB::read_tx(db).iter_buckets()
+    }
+
+    pub fn iter_pages(db: Rc<RefCell<DB>>) -> impl Iterator<Item = PageInfo> {
+        DB::iter_pages_filtered(db, PageFilter::default())
+    }
+
+    // iter_pages_filtered iterates the pages which match the filter, the pages are
+    // filtered during the traversal so the unmatched pages are never returned, and
+    // the free pages and meta pages are not read at all if they are not wanted.
+    pub fn iter_pages_filtered(
+        db: Rc<RefCell<DB>>,
+        filter: PageFilter,
+    ) -> impl Iterator<Item = PageInfo> {
+        DB::iter_pages_with(db, ReadOptions::builder().page_filter(filter).build())
+    }
+
+    // iter_pages_with iterates the pages which match the page filter of the options,
+    // the headers of the pages are checked if verify_checksums is set.
+    pub fn iter_pages_with(
+        db: Rc<RefCell<DB>>,
+        options: ReadOptions,
+    ) -> impl Iterator<Item = PageInfo> {
+        DB::read_tx(db).iter_pages_with(options)
+    }
+
+    // begin_read begins the read-only transaction at the current meta, the reads of
+    // it are consistent even if the file is changed later.
+    pub fn begin_read(db: Rc<RefCell<DB>>) -> Result<ReadTx, DatabaseError> {
+        db.borrow_mut().try_initialize()?;
+        let mut inner = db.borrow_mut();
+        let meta_page_id = inner.meta_page_id();
+        let meta = inner.get_meta();
+        drop(inner);
+        Ok(ReadTx {
+            db,
+            meta_page_id,
+            meta,
+        })
+    }
+
+    // read_tx is the same as begin_read, but it panics if the database can't be
+    // initialized, which is the same as the other reads.
+    fn read_tx(db: Rc<RefCell<DB>>) -> ReadTx {
+        DB::begin_read(db).unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+// ReadTx is the read-only transaction, all reads of it start from the meta which is
+// chosen when it begins, so each logical operation sees a consistent root.
+#[derive(Clone)]
+pub struct ReadTx {
+    db: Rc<RefCell<DB>>,
+    meta_page_id: u64,
+    meta: bolt::Meta,
+}
+
+impl ReadTx {
+    pub fn txid(&self) -> u64 {
+        self.meta.txid
+    }
+
+    pub fn info(&self) -> Info {
+        self.db.borrow_mut().meta_info(self.meta_page_id)
+    }
+
+    pub fn iter_buckets(&self) -> impl Iterator<Item = Bucket> {
         BucketIterator {
-            db: db.clone(),
+            db: self.db.clone(),
             parent_bucket: None,
+            parent_page_id: self.meta.root_pgid.into(),
             stack: vec![IterItem {
-                page_id: meta.root_pgid,
+                page_id: self.meta.root_pgid,
                 index: 0,
             }],
         }
     }
 
-    pub fn iter_pages(db: Rc<RefCell<DB>>) -> impl Iterator<Item = PageInfo> {
-        db.borrow_mut().initialize();
-        let meta = db.borrow_mut().get_meta();
+    pub fn get_bucket(&self, path: &[&[u8]]) -> Option<Bucket> {
+        let (name, path) = path.split_first()?;
+        let mut bucket = self.iter_buckets().find(|bucket| bucket.name == *name)?;
+        for name in path {
+            bucket = bucket.iter_buckets().find(|bucket| bucket.name == *name)?;
+        }
+        Some(bucket)
+    }
 
-        PageIterator {
-            db: db.clone(),
-            stack: vec![
-                PageIterItem {
-                    parent_page_id: None,
-                    page_id: 0,
-                    typ: PageType::Meta,
-                },
-                PageIterItem {
+    pub fn iter_items_with(&self, options: ReadOptions) -> DbItemIterator {
+        let mut iter = DbItemIterator {
+            db: self.db.clone(),
+            options,
+            frames: Vec::new(),
+            error: None,
+        };
+        let (_, matched) = iter.options.filter_bucket(&[]);
+        iter.error = iter
+            .push_frame(Vec::new(), 0, self.meta.root_pgid.into(), matched)
+            .err();
+        iter
+    }
+
+    pub fn iter_pages_with(&self, options: ReadOptions) -> impl Iterator<Item = PageInfo> {
+        let filter = options.page_filter;
+        let meta = self.meta;
+
+        let mut stack = VecDeque::new();
+        if filter.matches_type(PageType::Meta) {
+            for page_id in [0, 1] {
+                stack.push_back(PageIterItem {
                     parent_page_id: None,
-                    page_id: 1,
+                    page_id,
                     typ: PageType::Meta,
-                },
-                PageIterItem {
-                    parent_page_id: None,
-                    page_id: meta.freelist_pgid.into(),
-                    typ: PageType::Freelist,
-                },
-                PageIterItem {
-                    parent_page_id: None,
-                    page_id: meta.root_pgid.into(),
-                    typ: PageType::DataBranch,
-                },
-            ],
+                });
+            }
+        }
+        // The freelist isn't persisted if it's u64::MAX (NoFreelistSync).
+        if meta.freelist_pgid != bolt::Pgid(u64::MAX)
+            && (filter.matches_type(PageType::Freelist) || filter.matches_type(PageType::Free))
+        {
+            stack.push_back(PageIterItem {
+                parent_page_id: None,
+                page_id: meta.freelist_pgid.into(),
+                typ: PageType::Freelist,
+            });
+        }
+        stack.push_back(PageIterItem {
+            parent_page_id: None,
+            page_id: meta.root_pgid.into(),
+            typ: PageType::DataBranch,
+        });
+
+        PageIterator {
+            db: self.db.clone(),
+            filter,
+            verify: options.verify_checksums,
+            cancel: options.cancel,
+            stack,
+        }
+    }
+}
+
+// Endian is the byte order of the database file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn native() -> Endian {
+        if cfg!(target_endian = "little") {
+            Endian::Little
+        } else {
+            Endian::Big
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endian::Little => "little",
+            Endian::Big => "big",
+        }
+    }
+
+    fn other(&self) -> Endian {
+        match self {
+            Endian::Little => Endian::Big,
+            Endian::Big => Endian::Little,
+        }
+    }
+
+    fn is_swapped(&self) -> bool {
+        *self != Endian::native()
+    }
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Default for Endian {
+    fn default() -> Self {
+        Endian::native()
+    }
+}
+
+// ReadOptions selects what the iterators read, the options of the new features are
+// added here instead of the parameters of the iterators.
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct ReadOptions {
+    // check the id of each page read by the iterators, and the flags of the pages of
+    // the buckets. bolt doesn't have the checksum of the pages other than the meta,
+    // so the header is checked, the iterators panic on the mismatched page.
+    #[builder(default)]
+    pub verify_checksums: bool,
+    // iterate the key-value pairs of the inline buckets, the inline buckets are
+    // still returned if it's false.
+    #[builder(default = true)]
+    pub follow_inline: bool,
+    // the buckets deeper than max_depth levels are not visited.
+    #[builder(default)]
+    pub max_depth: Option<usize>,
+    // only the buckets matched by these patterns and their sub buckets are returned
+    // with their items, all items are returned if it's empty.
+    #[builder(default)]
+    pub buckets_filter: Vec<BucketPattern>,
+    #[builder(default)]
+    pub page_filter: PageFilter,
+    // the page and item iterators stop once the token is cancelled, e.g. by the UI
+    // thread, so the long traversal can be aborted.
+    #[builder(default)]
+    pub cancel: CancellationToken,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions::builder().build()
+    }
+}
+
+impl ReadOptions {
+    // filter_bucket returns whether the bucket is visited, and whether it's items
+    // are returned. The ancestors of the filtered buckets are visited to find them,
+    // but their items are not returned.
+    fn filter_bucket(&self, path: &[Vec<u8>]) -> (bool, bool) {
+        if self.buckets_filter.is_empty() {
+            return (true, true);
         }
+        let matched = self
+            .buckets_filter
+            .iter()
+            .any(|pattern| pattern.matches_ancestor(path));
+        let ancestor = self
+            .buckets_filter
+            .iter()
+            .any(|pattern| pattern.may_match_below(path));
+        (matched || ancestor, matched)
+    }
+}
+
+// verify_page panics if the page read for the page id has another id, the page may
+// be overwritten by other data.
+fn verify_page(page: &bolt::Page, page_id: u64) -> Result<(), DatabaseError> {
+    if page.id.0 != page_id {
+        return Err(DatabaseError::Corrupted {
+            reason: format!("page {} has unexpected id {}", page_id, page.id.0),
+        });
+    }
+    Ok(())
+}
+
+// element_data returns the range of the page which the i-th element points to, it's
+// Corrupted if the range exceeds the page.
+fn element_data<'a>(
+    page: &bolt::Page,
+    data: &'a [u8],
+    i: usize,
+    range: std::ops::Range<usize>,
+) -> Result<&'a [u8], DatabaseError> {
+    data.get(range).ok_or_else(|| DatabaseError::Corrupted {
+        reason: format!("element {} of page {} exceeds the page", i, page.id.0),
+    })
+}
+
+// PageFilter selects the pages returned by the page iterator.
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PageFilter {
+    // only the pages whose id is not less than start_id are returned.
+    #[builder(default)]
+    pub start_id: u64,
+    // only the pages of these types are returned, all pages are returned if it's empty.
+    #[builder(default)]
+    pub types: Vec<PageType>,
+}
+
+impl PageFilter {
+    fn matches_type(&self, typ: PageType) -> bool {
+        self.types.is_empty() || self.types.contains(&typ)
+    }
+
+    fn matches(&self, page_id: u64, typ: PageType) -> bool {
+        page_id >= self.start_id && self.matches_type(typ)
     }
 }
 
 struct PageIterator {
     db: Rc<RefCell<DB>>,
-    stack: Vec<PageIterItem>,
+    filter: PageFilter,
+    verify: bool,
+    cancel: CancellationToken,
+    stack: VecDeque<PageIterItem>,
 }
 
 struct PageIterItem {
@@ -358,81 +2473,115 @@ impl Iterator for PageIterator {
     type Item = PageInfo;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.stack.is_empty() {
-            return None;
+        while let Some(item) = self.stack.pop_front() {
+            if self.cancel.is_cancelled() {
+                self.stack.clear();
+                return None;
+            }
+            // The free pages don't have children, so they can be skipped without
+            // reading, other pages must be read to find their children.
+            if item.typ == PageType::Free && !self.filter.matches(item.page_id, item.typ) {
+                continue;
+            }
+
+            let page = self.visit(item);
+            if self.filter.matches(page.id, page.typ) {
+                return Some(page);
+            }
         }
 
-        let item = self.stack.remove(0);
+        None
+    }
+}
+
+impl PageIterator {
+    // visit reads the page and pushes it's children to the stack.
+    fn visit(&mut self, item: PageIterItem) -> PageInfo {
+        let _span = debug_span!("iter_pages", page_id = item.page_id).entered();
         if item.typ == PageType::Free {
-            return Some(PageInfo {
+            return PageInfo {
                 id: item.page_id,
                 typ: PageType::Free,
                 overflow: 0,
-                capacity: 4096,
+                capacity: self.db.borrow().page_size,
                 used: 0,
+                slack: 0,
                 parent_page_id: None,
-            });
+            };
         }
 
         let data = self.db.borrow_mut().read_page(item.page_id);
         let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        if self.verify {
+            verify_page(&page, item.page_id).unwrap_or_else(|err| panic!("{}", err));
+        }
         if page.flags.contains(bolt::PageFlag::MetaPageFlag) {
-            Some(PageInfo {
+            PageInfo {
                 id: item.page_id,
                 typ: PageType::Meta,
                 overflow: page.overflow as u64,
-                capacity: 4096,
+                capacity: data.len() as u64,
                 used: 80,
+                slack: 0,
                 parent_page_id: None,
-            })
+            }
         } else if page.flags.contains(bolt::PageFlag::FreelistPageFlag) {
-            let freelist = self.db.borrow_mut().read_freelist(&data, page.count);
+            let freelist = if self.filter.matches_type(PageType::Free) {
+                self.db.borrow_mut().read_freelist(&data, page.count)
+            } else {
+                Vec::new()
+            };
             for &i in &freelist {
                 // See
                 // 1. https://stackoverflow.com/questions/59123462/why-is-iterating-over-a-collection-via-for-loop-considered-a-move-in-rust
                 // 2. https://doc.rust-lang.org/reference/expressions/loop-expr.html#iterator-loops
-                self.stack.push(PageIterItem {
+                self.stack.push_back(PageIterItem {
                     parent_page_id: None,
                     page_id: i,
                     typ: PageType::Free,
                 });
             }
 
-            return Some(PageInfo {
+            PageInfo {
                 id: item.page_id,
                 typ: PageType::Freelist,
                 overflow: page.overflow as u64,
-                capacity: 4096,
+                capacity: data.len() as u64,
                 used: 16 + (page.count as u64 * 8),
+                slack: 0,
                 parent_page_id: None,
-            });
+            }
         } else if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            let (used, end) = calculate_used(&data, &page).unwrap_or_else(|err| panic!("{}", err));
             let branch_elements = self.db.borrow_mut().read_page_branch_elements(&data);
             for branch_item in branch_elements {
-                self.stack.push(PageIterItem {
+                self.stack.push_back(PageIterItem {
                     parent_page_id: Some(item.page_id),
                     page_id: branch_item.pgid,
                     typ: PageType::DataBranch,
                 });
             }
 
-            return Some(PageInfo {
+            PageInfo {
                 id: item.page_id,
                 typ: PageType::DataBranch,
                 overflow: page.overflow as u64,
-                capacity: 4096,
-                used: 16 + (page.count as u64 * 12),
+                capacity: data.len() as u64,
+                used,
+                slack: end - used,
                 parent_page_id: item.parent_page_id,
-            });
+            }
         } else {
+            let (used, end) = calculate_used(&data, &page).unwrap_or_else(|err| panic!("{}", err));
             let leaf_elements = self.db.borrow_mut().read_page_leaf_elements(&data);
             for leaf_item in leaf_elements {
                 if let LeafElement::Bucket {
                     name: _,
                     pgid: pg_id,
+                    ..
                 } = leaf_item
                 {
-                    self.stack.push(PageIterItem {
+                    self.stack.push_back(PageIterItem {
                         parent_page_id: Some(item.page_id),
                         page_id: pg_id,
                         typ: PageType::DataLeaf,
@@ -440,21 +2589,113 @@ impl Iterator for PageIterator {
                 }
             }
 
-            return Some(PageInfo {
+            PageInfo {
                 id: item.page_id,
                 typ: PageType::DataLeaf,
                 overflow: page.overflow as u64,
-                capacity: 4096,
-                used: 16 + (page.count as u64 * 12),
+                capacity: data.len() as u64,
+                used,
+                slack: end - used,
                 parent_page_id: item.parent_page_id,
+            }
+        }
+    }
+}
+
+// find_leaf_element returns the index and the element of the key in the leaf page.
+fn find_leaf_element(data: &[u8], key: &[u8]) -> Option<(u64, bolt::LeafPageElement)> {
+    let page: bolt::Page = TryFrom::try_from(data).unwrap();
+    (0..page.count as u64).find_map(|i| {
+        let start = PAGE_HEADER_SIZE + i as usize * 16;
+        let elem = bolt::LeafPageElement::try_from(data.get(start..)?).unwrap();
+        let key_start = start + elem.pos as usize;
+        (data.get(key_start..key_start + elem.ksize as usize)? == key).then_some((i, elem))
+    })
+}
+
+// element_at returns the index of the element whose header, key or value contains
+// the offset which is relative to the start of the branch or leaf page.
+fn element_at(data: &[u8], offset: u64) -> Option<u64> {
+    let page: bolt::Page = TryFrom::try_from(data).unwrap();
+    let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+    let elements_end = (PAGE_HEADER_SIZE + page.count as usize * 16) as u64;
+    if offset < PAGE_HEADER_SIZE as u64 {
+        return None;
+    }
+    if offset < elements_end {
+        return Some((offset - PAGE_HEADER_SIZE as u64) / 16);
+    }
+
+    (0..page.count as u64).find(|&i| {
+        let start = PAGE_HEADER_SIZE + i as usize * 16;
+        let Some(element) = data.get(start..start + 16) else {
+            return false;
+        };
+        let (pos, size) = if is_branch {
+            let element = bolt::BranchPageElement::try_from(element).unwrap();
+            (element.pos as u64, element.ksize as u64)
+        } else {
+            let element = bolt::LeafPageElement::try_from(element).unwrap();
+            (
+                element.pos as u64,
+                element.ksize as u64 + element.vsize as u64,
+            )
+        };
+        let key_start = start as u64 + pos;
+        (key_start..key_start + size).contains(&offset)
+    })
+}
+
+// calculate_used returns the bytes used by the header, elements, keys and values of
+// the branch or leaf page, and the end of the keys and values which are stored after
+// the elements. The bytes between them which are not used are the slack.
+//
+// It returns Corrupted if the elements or their keys and values are out of the page.
+fn calculate_used(data: &[u8], page: &bolt::Page) -> Result<(u64, u64), DatabaseError> {
+    let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+    let capacity = data.len() as u64;
+    let mut used = PAGE_HEADER_SIZE as u64 + page.count as u64 * 16;
+    if used > capacity {
+        return Err(DatabaseError::Corrupted {
+            reason: format!(
+                "the {} elements of page {} exceed the page size {}",
+                page.count, page.id.0, capacity
+            ),
+        });
+    }
+
+    let mut end = used;
+    for i in 0..page.count as u64 {
+        let start = (16 + i * 16) as usize;
+        let (pos, size) = if is_branch {
+            let element = bolt::BranchPageElement::try_from(&data[start..])?;
+            (element.pos as u64, element.ksize as u64)
+        } else {
+            let element = bolt::LeafPageElement::try_from(&data[start..])?;
+            (
+                element.pos as u64,
+                element.ksize as u64 + element.vsize as u64,
+            )
+        };
+        let element_end = start as u64 + pos + size;
+        if element_end > capacity {
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "the element {} of page {} ends at {}, which exceeds the page size {}",
+                    i, page.id.0, element_end, capacity
+                ),
             });
         }
+        used += size;
+        end = end.max(element_end);
     }
+    Ok((used, end))
 }
 
 struct BucketIterator {
     db: Rc<RefCell<DB>>,
     parent_bucket: Option<Bucket>,
+    parent_page_id: u64,
     stack: Vec<IterItem>,
 }
 
@@ -473,6 +2714,12 @@ impl Iterator for BucketIterator {
             }
 
             let item = self.stack.index_mut(self.stack.len() - 1);
+            let _span = debug_span!(
+                "iter_buckets",
+                page_id = u64::from(item.page_id),
+                index = item.index
+            )
+            .entered();
             let data = self.db.borrow_mut().read_page(item.page_id.into());
             let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
             if page.flags.contains(bolt::PageFlag::LeafPageFlag) {
@@ -480,32 +2727,18 @@ impl Iterator for BucketIterator {
                 if item.index < leaf_elements.len() {
                     let elem = leaf_elements[item.index].clone();
                     item.index += 1;
-                    match elem {
-                        LeafElement::Bucket { name, pgid } => {
-                            return Some(Bucket {
-                                parent_bucket: self
-                                    .parent_bucket
-                                    .as_ref()
-                                    .map_or_else(Vec::new, |bucket| bucket.name.clone()),
-                                is_inline: false,
-                                page_id: pgid,
-                                name,
-                                db: self.db.clone(),
-                            });
-                        }
-                        LeafElement::InlineBucket { name, items: _ } => {
-                            return Some(Bucket {
-                                parent_bucket: self
-                                    .parent_bucket
-                                    .as_ref()
-                                    .map_or_else(Vec::new, |bucket| bucket.name.clone()),
-                                is_inline: true,
-                                page_id: 0,
-                                name,
-                                db: self.db.clone(),
-                            });
-                        }
-                        LeafElement::KeyValue(_) => {}
+                    let parent_path = self
+                        .parent_bucket
+                        .as_ref()
+                        .map_or(&[][..], |bucket| bucket.path.as_slice());
+                    let bucket = Bucket::from_element(
+                        self.db.clone(),
+                        parent_path,
+                        self.parent_page_id,
+                        elem,
+                    );
+                    if bucket.is_some() {
+                        return bucket;
                     }
                     continue;
                 }
@@ -529,11 +2762,360 @@ impl Iterator for BucketIterator {
     }
 }
 
+// DbItem is the item of the database, which is either a bucket or a key-value pair.
+pub enum DbItem {
+    Bucket(Bucket),
+    KeyValue {
+        // the path of the bucket which the key-value pair belongs to.
+        bucket: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        // the value is reference-counted, cloning it doesn't copy the bytes.
+        value: Bytes,
+    },
+}
+
+pub struct DbItemIterator {
+    db: Rc<RefCell<DB>>,
+    options: ReadOptions,
+    // the buckets which are being iterated, the last one is the deepest.
+    frames: Vec<ItemFrame>,
+    // the error of reading the root page, it's returned by the first try_next.
+    error: Option<DatabaseError>,
+}
+
+struct ItemFrame {
+    // the path of the bucket.
+    bucket: Vec<Vec<u8>>,
+    // the levels below the bucket which the iteration starts at.
+    depth: usize,
+    // the root page id of the bucket, it's the parent of the sub buckets.
+    page_id: u64,
+    // the items of the bucket are returned, see ReadOptions::filter_bucket.
+    matched: bool,
+    // the decoded pages from the root page to the current leaf page.
+    cursors: Vec<ItemCursor>,
+}
+
+enum ItemCursor {
+    Branch {
+        elements: Vec<BranchElement>,
+        index: usize,
+    },
+    Leaf {
+        elements: Vec<LeafElement>,
+        index: usize,
+    },
+}
+
+impl DbItemIterator {
+    fn push_bucket(
+        &mut self,
+        bucket: Bucket,
+        depth: usize,
+        matched: bool,
+    ) -> Result<(), DatabaseError> {
+        if !bucket.is_inline {
+            return self.push_frame(bucket.path, depth, bucket.page_id, matched);
+        }
+        if !self.options.follow_inline {
+            return Ok(());
+        }
+
+        let elements = bucket
+            .inline_items
+            .into_iter()
+            .map(LeafElement::KeyValue)
+            .collect();
+        self.frames.push(ItemFrame {
+            bucket: bucket.path,
+            depth,
+            page_id: 0,
+            matched,
+            cursors: vec![ItemCursor::Leaf { elements, index: 0 }],
+        });
+        Ok(())
+    }
+
+    fn push_frame(
+        &mut self,
+        bucket: Vec<Vec<u8>>,
+        depth: usize,
+        page_id: u64,
+        matched: bool,
+    ) -> Result<(), DatabaseError> {
+        let cursor = self.read_cursor(page_id)?;
+        self.frames.push(ItemFrame {
+            bucket,
+            depth,
+            page_id,
+            matched,
+            cursors: vec![cursor],
+        });
+        Ok(())
+    }
+
+    fn read_cursor(&mut self, page_id: u64) -> Result<ItemCursor, DatabaseError> {
+        let mut db = self.db.borrow_mut();
+        let data = db.try_read_page(page_id)?;
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        if self.options.verify_checksums {
+            verify_page(&page, page_id)?;
+            if !page
+                .flags
+                .intersects(bolt::PageFlag::BranchPageFlag | bolt::PageFlag::LeafPageFlag)
+            {
+                return Err(DatabaseError::Corrupted {
+                    reason: format!(
+                        "page {} has unexpected flags {:#x}",
+                        page_id,
+                        page.flags.as_u16()
+                    ),
+                });
+            }
+        }
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            return Ok(ItemCursor::Branch {
+                elements: db.try_read_page_branch_elements(&data)?,
+                index: 0,
+            });
+        }
+
+        Ok(ItemCursor::Leaf {
+            elements: db.try_read_page_leaf_elements(&data)?,
+            index: 0,
+        })
+    }
+
+    // try_next is the same as next, but the page which can't be read or decoded is
+    // returned as the error instead of panic, the iteration ends after it. The items
+    // before it are returned as usual so a partially corrupted database can still
+    // be read.
+    pub fn try_next(&mut self) -> Result<Option<DbItem>, DatabaseError> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let item = self.read_next();
+        if item.is_err() {
+            self.frames.clear();
+        }
+        item
+    }
+
+    // try_iter iterates the items as try_next.
+    pub fn try_iter(mut self) -> impl Iterator<Item = Result<DbItem, DatabaseError>> {
+        std::iter::from_fn(move || self.try_next().transpose())
+    }
+
+    fn read_next(&mut self) -> Result<Option<DbItem>, DatabaseError> {
+        loop {
+            if self.options.cancel.is_cancelled() {
+                self.frames.clear();
+                return Ok(None);
+            }
+            let Some(frame) = self.frames.last_mut() else {
+                return Ok(None);
+            };
+            let Some(cursor) = frame.cursors.last_mut() else {
+                self.frames.pop();
+                continue;
+            };
+
+            match cursor {
+                ItemCursor::Branch { elements, index } => {
+                    let Some(elem) = elements.get(*index) else {
+                        frame.cursors.pop();
+                        continue;
+                    };
+                    *index += 1;
+
+                    let page_id = elem.pgid;
+                    let cursor = self.read_cursor(page_id)?;
+                    // The frame is borrowed again because read_cursor borrows self.
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.cursors.push(cursor);
+                    }
+                }
+                ItemCursor::Leaf { elements, index } => {
+                    let Some(elem) = elements.get(*index) else {
+                        frame.cursors.pop();
+                        continue;
+                    };
+                    *index += 1;
+
+                    if let LeafElement::KeyValue(kv) = elem {
+                        if !frame.matched {
+                            continue;
+                        }
+                        return Ok(Some(DbItem::KeyValue {
+                            bucket: frame.bucket.clone(),
+                            key: kv.key.clone(),
+                            value: kv.value.clone(),
+                        }));
+                    }
+
+                    let depth = frame.depth + 1;
+                    let bucket = Bucket::from_element(
+                        self.db.clone(),
+                        &frame.bucket,
+                        frame.page_id,
+                        elem.clone(),
+                    )
+                    .expect("the element is a bucket");
+                    // Prune the sub buckets which are deeper than max_depth or not
+                    // selected by the filter, so their pages are never read.
+                    let (visited, matched) = self.options.filter_bucket(&bucket.path);
+                    let max_depth = self.options.max_depth;
+                    if visited && max_depth.map_or(true, |max_depth| depth <= max_depth) {
+                        self.push_bucket(bucket.clone(), depth, matched)?;
+                        if matched {
+                            return Ok(Some(DbItem::Bucket(bucket)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for DbItemIterator {
+    type Item = DbItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+// BucketItemRevIterator iterates the items of a bucket from the last key to the
+// first, the sub buckets are returned but their items are not visited.
+pub struct BucketItemRevIterator {
+    bucket: Bucket,
+    // the cursors of the pages from the root page, the index is the number of the
+    // elements which are not visited yet.
+    cursors: Vec<ItemCursor>,
+    // the error of reading the root page, it's returned by the first try_next.
+    error: Option<DatabaseError>,
+}
+
+impl BucketItemRevIterator {
+    fn new(bucket: Bucket) -> Self {
+        let cursor = if bucket.is_inline {
+            let elements: Vec<LeafElement> = bucket
+                .inline_items
+                .iter()
+                .cloned()
+                .map(LeafElement::KeyValue)
+                .collect();
+            Ok(ItemCursor::Leaf {
+                index: elements.len(),
+                elements,
+            })
+        } else {
+            Self::read_cursor(&bucket.db, bucket.page_id)
+        };
+        let (cursors, error) = match cursor {
+            Ok(cursor) => (vec![cursor], None),
+            Err(err) => (Vec::new(), Some(err)),
+        };
+        BucketItemRevIterator {
+            bucket,
+            cursors,
+            error,
+        }
+    }
+
+    fn read_cursor(db: &Rc<RefCell<DB>>, page_id: u64) -> Result<ItemCursor, DatabaseError> {
+        let mut db = db.borrow_mut();
+        let data = db.try_read_page(page_id)?;
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            let elements = db.try_read_page_branch_elements(&data)?;
+            return Ok(ItemCursor::Branch {
+                index: elements.len(),
+                elements,
+            });
+        }
+
+        let elements = db.try_read_page_leaf_elements(&data)?;
+        Ok(ItemCursor::Leaf {
+            index: elements.len(),
+            elements,
+        })
+    }
+
+    // try_next is the same as next, but the page which can't be read or decoded is
+    // returned as the error instead of panic, the iteration ends after it.
+    pub fn try_next(&mut self) -> Result<Option<DbItem>, DatabaseError> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let item = self.read_next();
+        if item.is_err() {
+            self.cursors.clear();
+        }
+        item
+    }
+
+    // try_iter iterates the items as try_next.
+    pub fn try_iter(mut self) -> impl Iterator<Item = Result<DbItem, DatabaseError>> {
+        std::iter::from_fn(move || self.try_next().transpose())
+    }
+
+    fn read_next(&mut self) -> Result<Option<DbItem>, DatabaseError> {
+        loop {
+            let Some(cursor) = self.cursors.last_mut() else {
+                return Ok(None);
+            };
+            match cursor {
+                ItemCursor::Branch { elements, index } => {
+                    if *index == 0 {
+                        self.cursors.pop();
+                        continue;
+                    }
+                    *index -= 1;
+
+                    let cursor = Self::read_cursor(&self.bucket.db, elements[*index].pgid)?;
+                    self.cursors.push(cursor);
+                }
+                ItemCursor::Leaf { elements, index } => {
+                    if *index == 0 {
+                        self.cursors.pop();
+                        continue;
+                    }
+                    *index -= 1;
+
+                    let elem = elements[*index].clone();
+                    if let LeafElement::KeyValue(kv) = elem {
+                        return Ok(Some(DbItem::KeyValue {
+                            bucket: self.bucket.path.clone(),
+                            key: kv.key,
+                            value: kv.value,
+                        }));
+                    }
+                    let bucket = Bucket::from_element(
+                        self.bucket.db.clone(),
+                        &self.bucket.path,
+                        self.bucket.page_id,
+                        elem,
+                    )
+                    .expect("the element is a bucket");
+                    return Ok(Some(DbItem::Bucket(bucket)));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for BucketItemRevIterator {
+    type Item = DbItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
 // bucket -- list all bucket
-// check -- is page double free、is all page reachable
 // compact --
 // dump -- print pages
-// page-item -- print page items
 // get -- print key value
 // info -- print page size
 // keys -- print keys
@@ -544,5 +3126,512 @@ impl Iterator for BucketIterator {
 
 #[derive(TypedBuilder)]
 pub struct AnclaOptions {
+    #[builder(default)]
     db_path: String,
+    // share the pages with the databases opened from the same file in the process,
+    // until the size or modification time of the file changes. It's only used by
+    // DB::build.
+    #[builder(default)]
+    page_cache: bool,
+    // the page size which the pages are read with, it's detected from the meta if
+    // not set. The initialization fails if it mismatches the meta's, unless
+    // force_page_size is set.
+    #[builder(default)]
+    page_size: Option<u32>,
+    #[builder(default)]
+    force_page_size: bool,
+    // the byte order which the database is written in, bolt writes the native byte
+    // order of the machine. The pages are swapped when they are read if it's not
+    // the native one.
+    #[builder(default)]
+    endian: Endian,
+    // accept the versions in bolt::COMPAT_VERSIONS with a warning, the pages are
+    // read with the layout of the current version.
+    #[builder(default)]
+    compat: bool,
+    // copy the file to a temporary file before reading, so the pages are not changed
+    // by a running writer during the reading. It's only used by DB::build.
+    #[builder(default)]
+    snapshot_before_read: bool,
+    // keep the pages read in a sidecar file of the directory, they are loaded by the
+    // next opening of the unchanged file. It's only used if db_path is set, and the
+    // disk-cache feature is enabled.
+    #[builder(default)]
+    #[cfg_attr(not(feature = "disk-cache"), allow(dead_code))]
+    disk_cache_dir: Option<PathBuf>,
+    // the limits of the sizes which are read from the page headers, use
+    // ReadLimits::unlimited for the trusted files with the huge pages.
+    #[builder(default)]
+    limits: ReadLimits,
+}
+
+// ReadLimits caps the sizes which are read from the page headers, so a corrupted
+// header fails with a clear error instead of allocating too much memory.
+#[derive(Debug, Clone, Copy, TypedBuilder)]
+pub struct ReadLimits {
+    // the elements of a page, it only matters for the freelist page whose count is
+    // stored as u64 if it overflows u16.
+    #[builder(default = 1 << 24)]
+    pub max_elements: u64,
+    // the overflow pages of a page, the default allows the values up to 1GiB with
+    // the 4KB pages.
+    #[builder(default = 1 << 18)]
+    pub max_overflow: u64,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits::builder().build()
+    }
+}
+
+impl ReadLimits {
+    pub fn unlimited() -> Self {
+        ReadLimits {
+            max_elements: u64::MAX,
+            max_overflow: u64::MAX,
+        }
+    }
+
+    // check_overflow returns Corrupted if the overflow of the page header exceeds
+    // the limit.
+    fn check_overflow(&self, page_id: u64, overflow: u32) -> Result<(), DatabaseError> {
+        if overflow as u64 > self.max_overflow {
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "page {} has {} overflow pages, which exceeds the limit {}",
+                    page_id, overflow, self.max_overflow
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    // check_elements returns Corrupted if the element count exceeds the limit, the
+    // count of the branch and leaf pages is u16 so only the freelist is checked.
+    fn check_elements(&self, page_id: u64, count: u64) -> Result<(), DatabaseError> {
+        if count > self.max_elements {
+            return Err(DatabaseError::Corrupted {
+                reason: format!(
+                    "page {} has {} elements, which exceeds the limit {}",
+                    page_id, count, self.max_elements
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{bucket_header, database, freelist, leaf, metas, open, unsync_freelist};
+
+    // page builds a page of 4096 bytes with the header and the 16 bytes elements, the
+    // keys and values are not written since calculate_used only reads the elements.
+    fn page(flags: bolt::PageFlag, elements: &[[u32; 4]]) -> Vec<u8> {
+        let mut data = vec![0u8; 4096];
+        data[0..8].copy_from_slice(&7u64.to_le_bytes());
+        data[8..10].copy_from_slice(&flags.as_u16().to_le_bytes());
+        data[10..12].copy_from_slice(&(elements.len() as u16).to_le_bytes());
+        for (i, element) in elements.iter().enumerate() {
+            for (j, field) in element.iter().enumerate() {
+                let offset = PAGE_HEADER_SIZE + i * 16 + j * 4;
+                data[offset..offset + 4].copy_from_slice(&field.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn calculate(data: &[u8]) -> Result<(u64, u64), DatabaseError> {
+        let page: bolt::Page = TryFrom::try_from(data).unwrap();
+        calculate_used(data, &page)
+    }
+
+    fn assert_corrupted<T>(result: Result<T, DatabaseError>, expected: &str) {
+        match result {
+            Err(DatabaseError::Corrupted { reason }) => {
+                assert!(reason.contains(expected), "unexpected reason: {}", reason)
+            }
+            Err(err) => panic!("expected Corrupted, got {:?}", err),
+            Ok(_) => panic!("expected Corrupted, got Ok"),
+        }
+    }
+
+    #[test]
+    fn calculate_used_leaf() {
+        // The leaf elements are flags, pos, ksize and vsize, pos is relative to the
+        // element.
+        let data = page(
+            bolt::PageFlag::LeafPageFlag,
+            &[[0, 32, 3, 5], [0, 24, 2, 6]],
+        );
+        assert_eq!(calculate(&data).unwrap(), (16 + 32 + 16, 16 + 32 + 16));
+    }
+
+    #[test]
+    fn calculate_used_leaf_out_of_range() {
+        let pos = page(
+            bolt::PageFlag::LeafPageFlag,
+            &[[0, 32, 3, 5], [0, 4096, 1, 1]],
+        );
+        assert_corrupted(calculate(&pos), "the element 1 of page 7 ends at 4130");
+
+        let ksize = page(bolt::PageFlag::LeafPageFlag, &[[0, 16, 4096, 0]]);
+        assert_corrupted(calculate(&ksize), "the element 0 of page 7 ends at 4128");
+
+        // The sizes are summed in u64, so they don't overflow.
+        let vsize = page(
+            bolt::PageFlag::LeafPageFlag,
+            &[[0, 32, 3, 5], [0, 16, 1, u32::MAX]],
+        );
+        assert_corrupted(calculate(&vsize), "the element 1 of page 7");
+    }
+
+    #[test]
+    fn calculate_used_branch_out_of_range() {
+        // The branch elements are pos, ksize and the 8 bytes pgid.
+        let ok = page(
+            bolt::PageFlag::BranchPageFlag,
+            &[[32, 4, 3, 0], [20, 4, 4, 0]],
+        );
+        assert_eq!(calculate(&ok).unwrap(), (16 + 32 + 8, 16 + 32 + 8));
+
+        let pos = page(
+            bolt::PageFlag::BranchPageFlag,
+            &[[32, 4, 3, 0], [u32::MAX, 4, 4, 0]],
+        );
+        assert_corrupted(calculate(&pos), "the element 1 of page 7");
+
+        let ksize = page(bolt::PageFlag::BranchPageFlag, &[[16, 4081, 3, 0]]);
+        assert_corrupted(calculate(&ksize), "the element 0 of page 7 ends at 4113");
+    }
+
+    #[test]
+    fn page_capacity_follows_page_size() {
+        for page_size in [8192, 16384] {
+            let db = open(database(page_size));
+            assert_eq!(DB::info(db.clone()).page_size, page_size as u32);
+            let mut pages: Vec<(u64, PageType, u64)> = DB::iter_pages(db)
+                .map(|page| (page.id, page.typ, page.capacity))
+                .collect();
+            pages.sort_by_key(|page| page.0);
+            let page_size = page_size as u64;
+            assert_eq!(
+                pages,
+                vec![
+                    (0, PageType::Meta, page_size),
+                    (1, PageType::Meta, page_size),
+                    (2, PageType::Freelist, page_size),
+                    (3, PageType::DataLeaf, page_size),
+                    (4, PageType::DataLeaf, page_size),
+                    (5, PageType::Free, page_size),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn locate_follows_page_size() {
+        for page_size in [8192u64, 16384] {
+            let db = open(database(page_size as usize));
+            let location = DB::locate(db.clone(), page_size + 20);
+            assert_eq!(location.page_id, 1);
+            assert_eq!(location.region, Some(PageRegion::Meta));
+
+            let location = DB::locate(db.clone(), 4 * page_size + 34);
+            assert_eq!(location.page_id, 4);
+            assert_eq!(location.typ, Some(PageType::DataLeaf));
+            assert_eq!(location.region, Some(PageRegion::KeyValues));
+            assert_eq!(location.element_index, Some(0));
+            assert_eq!(location.bucket, Some(vec![b"b".to_vec()]));
+
+            let location = DB::locate(db.clone(), 3 * page_size + 40);
+            assert_eq!(location.region, Some(PageRegion::KeyValues));
+            assert_eq!(location.bucket, Some(Vec::new()));
+
+            let location = DB::locate(db, 5 * page_size + 100);
+            assert_eq!(location.owner_page_id, Some(5));
+            assert_eq!(location.typ, Some(PageType::Free));
+        }
+    }
+
+    // The page sizes which bolt is used with besides the default 4096.
+    const PAGE_SIZES: [usize; 3] = [512, 8192, 65536];
+
+    #[test]
+    fn open_page_sizes() {
+        for page_size in PAGE_SIZES {
+            let info = DB::try_info(open(database(page_size))).unwrap();
+            assert_eq!(info.page_size, page_size as u32);
+            assert_eq!(info.txid, 1);
+            assert_eq!(info.root_page_id, 3);
+            assert_eq!(info.freelist_page_id, 2);
+            assert_eq!(info.max_page_id, 6);
+
+            let options = AnclaOptions::builder()
+                .page_size(Some(page_size as u32))
+                .build();
+            let db = DB::open_from_bytes(options, database(page_size));
+            assert_eq!(DB::try_info(db).unwrap().page_size, page_size as u32);
+
+            let options = AnclaOptions::builder().page_size(Some(4096)).build();
+            let db = DB::open_from_bytes(options, database(page_size));
+            match DB::try_info(db) {
+                Err(DatabaseError::PageSizeMismatch { meta, requested }) => {
+                    assert_eq!((meta, requested), (page_size as u32, 4096))
+                }
+                other => panic!("expected PageSizeMismatch, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    // Item is the bucket path of an item, and the key and value if it's a key-value
+    // pair.
+    type Item = (Vec<Vec<u8>>, Option<(Vec<u8>, Vec<u8>)>);
+
+    #[test]
+    fn iter_items_page_sizes() {
+        for page_size in PAGE_SIZES {
+            let items: Vec<Item> = DB::iter_items(open(database(page_size)), None)
+                .try_iter()
+                .map(|item| match item.unwrap() {
+                    DbItem::Bucket(bucket) => (bucket.path().to_vec(), None),
+                    DbItem::KeyValue { bucket, key, value } => {
+                        (bucket, Some((key, value.to_vec())))
+                    }
+                })
+                .collect();
+            assert_eq!(
+                items,
+                vec![
+                    (vec![b"b".to_vec()], None),
+                    (
+                        vec![b"b".to_vec()],
+                        Some((b"key".to_vec(), b"value".to_vec()))
+                    ),
+                ]
+            );
+
+            let pages: Vec<(u64, PageType)> = DB::iter_pages(open(database(page_size)))
+                .map(|page| (page.id, page.typ))
+                .collect();
+            assert_eq!(pages.len(), 6);
+            assert!(pages.contains(&(4, PageType::DataLeaf)));
+            assert!(pages.contains(&(5, PageType::Free)));
+        }
+    }
+
+    #[test]
+    fn lookup_page_sizes() {
+        for page_size in PAGE_SIZES {
+            let db = open(database(page_size));
+            let bucket = DB::get_bucket(db.clone(), &[b"b"]).unwrap();
+            assert_eq!(bucket.get(b"key").unwrap(), *b"value".as_slice());
+            assert!(bucket.get(b"missing").is_none());
+            assert!(DB::get_bucket(db.clone(), &[b"c"]).is_none());
+
+            let (key, value) = DB::first_key(db.clone(), &[b"b"]).unwrap();
+            assert_eq!(
+                (key.as_slice(), value.as_ref()),
+                (&b"key"[..], &b"value"[..])
+            );
+            assert_eq!(DB::last_key(db.clone(), &[b"b"]).unwrap().0, b"key");
+
+            let location = DB::locate_key(db.clone(), &[b"b"], b"key").unwrap();
+            assert_eq!(location.page_id, 4);
+            assert_eq!(location.index, 0);
+            assert_eq!(location.key_offset, 4 * page_size as u64 + 32);
+            assert_eq!(location.value_offset, 4 * page_size as u64 + 35);
+
+            let estimate = DB::estimate_key_count(db, &[b"b"], 0.1).unwrap().unwrap();
+            assert_eq!(estimate, KeyCountEstimate::exact(1));
+        }
+    }
+
+    #[test]
+    fn locate_double_referenced_page() {
+        // The buckets a and b share the root page 4, it belongs to the bucket which
+        // page_path reaches it from.
+        let mut data = metas(4096, 5);
+        data.extend(freelist(4096, &[]));
+        data.extend(leaf(
+            4096,
+            3,
+            &[
+                (0x01, b"a", &bucket_header(4)),
+                (0x01, b"b", &bucket_header(4)),
+            ],
+        ));
+        data.extend(leaf(4096, 4, &[(0, b"key", b"value")]));
+        let db = open(data);
+
+        let PagePath::Tree { steps, .. } = DB::page_path(db.clone(), 4) else {
+            panic!("page 4 is not in the tree");
+        };
+        assert_eq!(steps.len(), 1);
+        let location = DB::locate(db, 4 * 4096 + 40);
+        assert_eq!(location.bucket, Some(vec![steps[0].key.clone()]));
+    }
+
+    // cyclic_tree returns the database whose page 4, the root page of the bucket b,
+    // is a branch page whose child is itself.
+    fn cyclic_tree() -> Rc<RefCell<DB>> {
+        let mut data = database(4096);
+        let page = &mut data[4 * 4096..5 * 4096];
+        page[8..10].copy_from_slice(&bolt::PageFlag::BranchPageFlag.as_u16().to_le_bytes());
+        bolt::BranchPageElement {
+            pos: 16,
+            ksize: 3,
+            pgid: bolt::Pgid(4),
+        }
+        .encode(&mut page[16..32]);
+        open(data)
+    }
+
+    #[test]
+    fn diff_pages_page_size() {
+        let mut data = database(4096);
+        data[4 * 4096 + 40] = b'V';
+        let changed: Vec<_> = DB::diff_pages(open(database(4096)), open(data))
+            .unwrap()
+            .into_iter()
+            .map(|page| (page.id, page.bucket))
+            .collect();
+        assert_eq!(changed, vec![(4, Some(vec![b"b".to_vec()]))]);
+
+        match DB::diff_pages(open(database(4096)), open(database(8192))) {
+            Err(DatabaseError::Unsupported { reason }) => assert_eq!(
+                reason,
+                "the page size 4096 of the old database differs from the page size 8192"
+            ),
+            other => panic!("expected Unsupported, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn walk_cyclic_tree() {
+        let db = cyclic_tree();
+
+        let bucket = DB::get_bucket(db.clone(), &[b"b"]).unwrap();
+        let info = bucket.info();
+        assert_eq!((info.depth, info.pages), (1, 1));
+        assert_ne!(bucket.hash(), 0);
+        let stats = db.borrow_mut().collect_tree(vec![b"b".to_vec()], 4);
+        assert_eq!(stats.leaves, 0);
+        assert_eq!(stats.branching, BTreeMap::from([(1, 1)]));
+    }
+
+    #[test]
+    fn estimate_key_count_exact() {
+        let estimate = DB::estimate_key_count(open(database(4096)), &[b"b"], 0.1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(estimate, KeyCountEstimate::exact(1));
+        assert!(DB::estimate_key_count(open(database(4096)), &[b"c"], 0.1)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn estimate_key_count_cyclic_tree() {
+        let bucket = DB::get_bucket(cyclic_tree(), &[b"b"]).unwrap();
+        match bucket.estimate_key_count(0.1) {
+            Err(DatabaseError::Corrupted { reason }) => {
+                assert_eq!(reason, "page 4 is reached twice from the root page 4")
+            }
+            other => panic!("expected Corrupted, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn estimate_key_count_too_many_elements() {
+        let mut data = database(4096);
+        data[4 * 4096 + 10..4 * 4096 + 12].copy_from_slice(&300u16.to_le_bytes());
+        let bucket = DB::get_bucket(open(data), &[b"b"]).unwrap();
+        match bucket.estimate_key_count(0.1) {
+            Err(DatabaseError::Corrupted { reason }) => assert_eq!(
+                reason,
+                "page 4 has 300 elements, only 255 elements fit in the page"
+            ),
+            other => panic!("expected Corrupted, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn page_path_without_freelist() {
+        let mut data = database(4096);
+        unsync_freelist(&mut data, 4096);
+        let db = open(data);
+        assert!(matches!(
+            DB::page_path(db.clone(), 2),
+            PagePath::Unreachable
+        ));
+        assert!(matches!(
+            DB::page_path(db.clone(), 4),
+            PagePath::Tree { steps, .. } if steps.len() == 1
+        ));
+        assert!(matches!(DB::page_path(db, u64::MAX), PagePath::Unreachable));
+    }
+
+    #[test]
+    fn iter_pages_without_freelist() {
+        let mut data = database(4096);
+        unsync_freelist(&mut data, 4096);
+        let mut pages: Vec<(u64, PageType)> = DB::iter_pages(open(data))
+            .map(|page| (page.id, page.typ))
+            .collect();
+        pages.sort_by_key(|page| page.0);
+        assert_eq!(
+            pages,
+            vec![
+                (0, PageType::Meta),
+                (1, PageType::Meta),
+                (3, PageType::DataLeaf),
+                (4, PageType::DataLeaf),
+            ]
+        );
+    }
+
+    #[test]
+    fn try_info_checksum_mismatch() {
+        let mut data = database(4096);
+        // The txid of the meta page 1 is changed without the checksum.
+        data[4096 + 64] = 3;
+        assert_corrupted(DB::try_info(open(data)), "checksum mismatch");
+    }
+
+    #[test]
+    fn iter_items_element_out_of_page() {
+        let mut data = database(4096);
+        // The value of the only element of page 4 overruns the page.
+        let element = 4 * 4096 + 16;
+        data[element + 12..element + 16].copy_from_slice(&8192u32.to_le_bytes());
+        // The root page of the bucket is read before the bucket is returned.
+        let mut items = DB::iter_items(open(data), None);
+        assert_corrupted(items.try_next(), "element 0 of page 4 exceeds the page");
+        // The iteration ends at the error.
+        assert!(matches!(items.try_next(), Ok(None)));
+    }
+
+    #[test]
+    fn try_info_exceeds_read_limits() {
+        let mut data = database(4096);
+        data[12..16].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        let db = DB::open_from_bytes(AnclaOptions::builder().build(), data);
+        match DB::try_info(db) {
+            Err(DatabaseError::Corrupted { reason }) => assert_eq!(
+                reason,
+                "page 0 has 2147483647 overflow pages, which exceeds the limit 262144"
+            ),
+            other => panic!("expected Corrupted, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn calculate_used_too_many_elements() {
+        let mut data = page(bolt::PageFlag::LeafPageFlag, &[]);
+        data[10..12].copy_from_slice(&300u16.to_le_bytes());
+        assert_corrupted(calculate(&data), "the 300 elements of page 7");
+    }
 }
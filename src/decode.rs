@@ -0,0 +1,438 @@
+use crate::{errors::DatabaseError, utils::hex};
+use std::{fmt, str::FromStr};
+
+// KeyFormat is how the keys are interpreted when they are rendered, the keys
+// which can't be decoded in the format are rendered as text.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum KeyFormat {
+    // the printable ASCII is kept, other bytes are escaped as `\xNN`.
+    #[default]
+    Text,
+    Hex,
+    // 8 bytes big-endian unsigned integer, e.g. the keys of NextSequence.
+    U64,
+    // the revision keys of etcd, see Revision.
+    Revision,
+    Uuid,
+    // 8 bytes big-endian unix time, see decode_timestamp.
+    Timestamp,
+}
+
+impl KeyFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyFormat::Text => "text",
+            KeyFormat::Hex => "hex",
+            KeyFormat::U64 => "u64",
+            KeyFormat::Revision => "revision",
+            KeyFormat::Uuid => "uuid",
+            KeyFormat::Timestamp => "timestamp",
+        }
+    }
+
+    // render renders the key in the format, it falls back to text if the key
+    // can't be decoded.
+    pub fn render(&self, key: &[u8]) -> String {
+        let decoded = match self {
+            KeyFormat::Text => None,
+            KeyFormat::Hex => Some(hex(key)),
+            KeyFormat::U64 => decode_u64(key).map(|v| v.to_string()),
+            KeyFormat::Revision => decode_revision(key).map(|v| v.to_string()),
+            KeyFormat::Uuid => decode_uuid(key),
+            KeyFormat::Timestamp => decode_timestamp(key),
+        };
+        decoded.unwrap_or_else(|| key.escape_ascii().to_string())
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KeyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(KeyFormat::Text),
+            "hex" => Ok(KeyFormat::Hex),
+            "u64" => Ok(KeyFormat::U64),
+            "revision" => Ok(KeyFormat::Revision),
+            "uuid" => Ok(KeyFormat::Uuid),
+            "timestamp" => Ok(KeyFormat::Timestamp),
+            _ => Err(format!("unknown key format: {}", s)),
+        }
+    }
+}
+
+// NameEncoding is how the bucket names which are not valid UTF-8 are rendered,
+// bolt doesn't require the names to be UTF-8.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum NameEncoding {
+    // the invalid bytes are replaced by U+FFFD.
+    #[default]
+    Lossy,
+    // the invalid bytes are escaped as `\xNN`, the valid characters are kept.
+    Escape,
+    // the name which is not valid UTF-8 is an error.
+    Error,
+}
+
+impl NameEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NameEncoding::Lossy => "lossy",
+            NameEncoding::Escape => "escape",
+            NameEncoding::Error => "error",
+        }
+    }
+
+    // render renders the name in the encoding, DatabaseError::InvalidUtf8 is
+    // returned only for the error encoding.
+    pub fn render(&self, name: &[u8]) -> Result<String, DatabaseError> {
+        match self {
+            NameEncoding::Lossy => Ok(String::from_utf8_lossy(name).into_owned()),
+            NameEncoding::Escape => {
+                let mut output = String::with_capacity(name.len());
+                for chunk in name.utf8_chunks() {
+                    output.push_str(chunk.valid());
+                    for b in chunk.invalid() {
+                        output.push_str(&format!("\\x{:02x}", b));
+                    }
+                }
+                Ok(output)
+            }
+            NameEncoding::Error => std::str::from_utf8(name).map(str::to_string).map_err(|_| {
+                DatabaseError::InvalidUtf8 {
+                    name: name.escape_ascii().to_string(),
+                }
+            }),
+        }
+    }
+}
+
+impl fmt::Display for NameEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NameEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(NameEncoding::Lossy),
+            "escape" => Ok(NameEncoding::Escape),
+            "error" => Ok(NameEncoding::Error),
+            _ => Err(format!("unknown name encoding: {}", s)),
+        }
+    }
+}
+
+// Revision is the key of etcd's key bucket, it's `<main>_<sub>` with both parts in
+// 8 bytes big-endian, and a trailing `t` if the revision is a tombstone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Revision {
+    pub main: i64,
+    pub sub: i64,
+    pub tombstone: bool,
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.main, self.sub)?;
+        if self.tombstone {
+            f.write_str(" (tombstone)")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn decode_u64(key: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(key.try_into().ok()?))
+}
+
+pub fn decode_revision(key: &[u8]) -> Option<Revision> {
+    let tombstone = match key.len() {
+        17 => false,
+        18 if key[17] == b't' => true,
+        _ => return None,
+    };
+    if key[8] != b'_' {
+        return None;
+    }
+
+    Some(Revision {
+        main: i64::from_be_bytes(key[0..8].try_into().ok()?),
+        sub: i64::from_be_bytes(key[9..17].try_into().ok()?),
+        tombstone,
+    })
+}
+
+// decode_uuid formats the 16 bytes key in the hyphenated form.
+pub fn decode_uuid(key: &[u8]) -> Option<String> {
+    if key.len() != 16 {
+        return None;
+    }
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        hex(&key[0..4]),
+        hex(&key[4..6]),
+        hex(&key[6..8]),
+        hex(&key[8..10]),
+        hex(&key[10..16])
+    ))
+}
+
+// decode_timestamp formats the 8 bytes big-endian unix time in RFC 3339 (UTC). The
+// unit is guessed by the magnitude, so the seconds, milliseconds, microseconds
+// and nanoseconds after 1973 are all decoded.
+pub fn decode_timestamp(key: &[u8]) -> Option<String> {
+    let value = decode_u64(key)?;
+    let (secs, nanos, digits) = match value {
+        0..100_000_000_000 => (value, 0, 0),
+        100_000_000_000..100_000_000_000_000 => (value / 1_000, value % 1_000 * 1_000_000, 3),
+        100_000_000_000_000..100_000_000_000_000_000 => {
+            (value / 1_000_000, value % 1_000_000 * 1_000, 6)
+        }
+        _ => (value / 1_000_000_000, value % 1_000_000_000, 9),
+    };
+
+    let days = secs / 86400;
+    let secs_of_day = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    let mut output = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    );
+    if digits > 0 {
+        let fraction = format!("{:09}", nanos);
+        output.push('.');
+        output.push_str(&fraction[..digits]);
+    }
+    output.push('Z');
+    Some(output)
+}
+
+// civil_from_days converts the days since 1970-01-01 to the date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// ValueFormat is the encoding of a value detected by sniff_value, it's a guess by the
+// structure of the bytes, e.g. a short binary value may be valid protobuf.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ValueFormat {
+    Empty,
+    Json,
+    // the valid UTF-8 without control characters other than whitespaces.
+    Text,
+    Gob,
+    Msgpack,
+    Protobuf,
+    Binary,
+}
+
+impl ValueFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueFormat::Empty => "empty",
+            ValueFormat::Json => "json",
+            ValueFormat::Text => "text",
+            ValueFormat::Gob => "gob",
+            ValueFormat::Msgpack => "msgpack",
+            ValueFormat::Protobuf => "protobuf",
+            ValueFormat::Binary => "binary",
+        }
+    }
+}
+
+impl fmt::Display for ValueFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// The nesting depth which the msgpack values are checked to.
+const MAX_MSGPACK_DEPTH: usize = 32;
+
+// sniff_value detects the format of the value, the text formats are checked before
+// the binary ones, since the binary formats accept many short texts.
+pub fn sniff_value(value: &[u8]) -> ValueFormat {
+    if value.is_empty() {
+        return ValueFormat::Empty;
+    }
+    if let Ok(text) = std::str::from_utf8(value) {
+        let trimmed = text.trim();
+        let is_json = (trimmed.starts_with('{') && trimmed.ends_with('}'))
+            || (trimmed.starts_with('[') && trimmed.ends_with(']'));
+        if is_json {
+            return ValueFormat::Json;
+        }
+        if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+            return ValueFormat::Text;
+        }
+    }
+    if is_gob(value) {
+        return ValueFormat::Gob;
+    }
+    // Only the maps and arrays are accepted, any byte is a valid msgpack scalar.
+    if matches!(value[0], 0x80..=0x9f | 0xdc..=0xdf)
+        && skip_msgpack(value, 0, 0) == Some(value.len())
+    {
+        return ValueFormat::Msgpack;
+    }
+    if is_protobuf(value) {
+        return ValueFormat::Protobuf;
+    }
+    ValueFormat::Binary
+}
+
+// is_gob checks the first message of the gob stream, which is the length of the
+// message and the negative id of the type defined by it.
+fn is_gob(value: &[u8]) -> bool {
+    let mut pos = 0;
+    let Some(length) = read_gob_uint(value, &mut pos) else {
+        return false;
+    };
+    let Some(end) = usize::try_from(length)
+        .ok()
+        .and_then(|length| pos.checked_add(length))
+    else {
+        return false;
+    };
+    if length == 0 || end > value.len() {
+        return false;
+    }
+    // The signed integer is the unsigned one with the sign in the lowest bit.
+    read_gob_uint(value, &mut pos).is_some_and(|id| id & 1 == 1)
+}
+
+// read_gob_uint reads the unsigned integer of gob, it's a single byte if it's less
+// than 128, or the negated byte count followed by the big-endian bytes.
+fn read_gob_uint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let first = *data.get(*pos)?;
+    *pos += 1;
+    if first < 0x80 {
+        return Some(first as u64);
+    }
+    let count = (first as i8).unsigned_abs() as usize;
+    if count > 8 {
+        return None;
+    }
+    let bytes = data.get(*pos..*pos + count)?;
+    *pos += count;
+    Some(bytes.iter().fold(0, |v, &b| (v << 8) | b as u64))
+}
+
+// skip_msgpack returns the position after the msgpack value at pos.
+fn skip_msgpack(data: &[u8], pos: usize, depth: usize) -> Option<usize> {
+    if depth > MAX_MSGPACK_DEPTH {
+        return None;
+    }
+    let first = *data.get(pos)?;
+    let be = |size: usize| -> Option<usize> {
+        let bytes = data.get(pos + 1..pos + 1 + size)?;
+        Some(bytes.iter().fold(0, |v, &b| (v << 8) | b as usize))
+    };
+    // The header size and the size of the payload, or the number of the elements
+    // (the map has two elements per entry).
+    let (header, size, elements) = match first {
+        0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (1, 0, 0),
+        0x80..=0x8f => (1, 0, (first & 0x0f) as usize * 2),
+        0x90..=0x9f => (1, 0, (first & 0x0f) as usize),
+        0xa0..=0xbf => (1, (first & 0x1f) as usize, 0),
+        0xc4 | 0xd9 => (2, be(1)?, 0),
+        0xc5 | 0xda => (3, be(2)?, 0),
+        0xc6 | 0xdb => (5, be(4)?, 0),
+        0xc7 => (3, be(1)?, 0),
+        0xc8 => (4, be(2)?, 0),
+        0xc9 => (6, be(4)?, 0),
+        0xcc | 0xd0 => (2, 0, 0),
+        0xcd | 0xd1 => (3, 0, 0),
+        0xca | 0xce | 0xd2 => (5, 0, 0),
+        0xcb | 0xcf | 0xd3 => (9, 0, 0),
+        0xd4 => (3, 0, 0),
+        0xd5 => (4, 0, 0),
+        0xd6 => (6, 0, 0),
+        0xd7 => (10, 0, 0),
+        0xd8 => (18, 0, 0),
+        0xdc => (3, 0, be(2)?),
+        0xdd => (5, 0, be(4)?),
+        0xde => (3, 0, be(2)? * 2),
+        0xdf => (5, 0, be(4)? * 2),
+        0xc1 => return None,
+    };
+
+    let mut end = pos.checked_add(header)?.checked_add(size)?;
+    if end > data.len() || elements > data.len() - end {
+        return None;
+    }
+    for _ in 0..elements {
+        end = skip_msgpack(data, end, depth + 1)?;
+    }
+    Some(end)
+}
+
+// is_protobuf checks the value is a sequence of the protobuf fields, the deprecated
+// groups are not accepted.
+fn is_protobuf(value: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos < value.len() {
+        let Some(tag) = read_varint(value, &mut pos) else {
+            return false;
+        };
+        if tag >> 3 == 0 {
+            return false;
+        }
+        let size = match tag & 0x07 {
+            0 => match read_varint(value, &mut pos) {
+                Some(_) => 0,
+                None => return false,
+            },
+            1 => 8,
+            2 => match read_varint(value, &mut pos).and_then(|size| usize::try_from(size).ok()) {
+                Some(size) => size,
+                None => return false,
+            },
+            5 => 4,
+            _ => return false,
+        };
+        match pos.checked_add(size) {
+            Some(end) if end <= value.len() => pos = end,
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
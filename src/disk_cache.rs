@@ -0,0 +1,139 @@
+use crate::cache::Pages;
+use crate::utils;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::debug;
+
+// The magic of the sidecar file, it's changed if the format is changed.
+const MAGIC: &[u8; 8] = b"ANCLAPC1";
+
+// DiskCache is the sidecar file which keeps the pages read from a database, so the
+// repeated analysis of a cold large database doesn't read the pages from it again.
+// The file is named by the hash of the database path, and it's only valid for the
+// same file size, page size and txid.
+//
+// The file is the magic, the file size, page size and txid in little-endian u64,
+// followed by the zstd stream of the pages, each page is it's id, length and data.
+pub(crate) struct DiskCache {
+    path: PathBuf,
+    file_size: u64,
+    // the page size and txid of the loaded database, it's None until the pages
+    // are loaded, and nothing is stored in this case.
+    loaded: Option<(u64, u64)>,
+    // the number of pages loaded, the file is rewritten only if more pages are read.
+    loaded_pages: usize,
+}
+
+impl DiskCache {
+    pub(crate) fn new(dir: &Path, db_path: &str) -> io::Result<Self> {
+        let db_path = fs::canonicalize(db_path)?;
+        let file_size = fs::metadata(&db_path)?.len();
+        let name = format!(
+            "{:016x}.pages.zst",
+            utils::fnv64(db_path.as_os_str().as_encoded_bytes())
+        );
+        Ok(DiskCache {
+            path: dir.join(name),
+            file_size,
+            loaded: None,
+            loaded_pages: 0,
+        })
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.loaded.is_some()
+    }
+
+    // load returns the pages of the sidecar file, it's empty if the file doesn't
+    // exist or it's written for another version of the database.
+    pub(crate) fn load(&mut self, page_size: u64, txid: u64) -> Pages {
+        self.loaded = Some((page_size, txid));
+        match self.read(page_size, txid) {
+            Ok(pages) => {
+                debug!(path = %self.path.display(), pages = pages.len(), "load the disk cache");
+                self.loaded_pages = pages.len();
+                pages
+            }
+            Err(e) => {
+                debug!(path = %self.path.display(), %e, "skip the disk cache");
+                Pages::new()
+            }
+        }
+    }
+
+    fn read(&self, page_size: u64, txid: u64) -> io::Result<Pages> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut header = [0u8; 32];
+        reader.read_exact(&mut header)?;
+        let expected = Self::header(self.file_size, page_size, txid);
+        if header != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stale disk cache",
+            ));
+        }
+
+        let mut decoder = zstd::Decoder::new(reader)?;
+        let mut pages = Pages::new();
+        let mut entry = [0u8; 16];
+        loop {
+            match decoder.read_exact(&mut entry) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let page_id = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            let len = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+            // The data is read without allocating the length at once, a corrupted
+            // length ends with the stream.
+            let mut data = Vec::new();
+            (&mut decoder).take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            pages.insert(page_id.into(), Arc::new(data));
+        }
+        Ok(pages)
+    }
+
+    // store writes the pages to the sidecar file if more pages are read than
+    // loaded, the file is replaced atomically.
+    pub(crate) fn store(&self, pages: &Pages) -> io::Result<()> {
+        let Some((page_size, txid)) = self.loaded else {
+            return Ok(());
+        };
+        if pages.len() <= self.loaded_pages {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(&Self::header(self.file_size, page_size, txid))?;
+        let mut encoder = zstd::Encoder::new(writer, 0)?;
+        for (page_id, data) in pages {
+            encoder.write_all(&u64::from(*page_id).to_le_bytes())?;
+            encoder.write_all(&(data.len() as u64).to_le_bytes())?;
+            encoder.write_all(data)?;
+        }
+        encoder.finish()?.flush()?;
+        fs::rename(&tmp_path, &self.path)?;
+        debug!(path = %self.path.display(), pages = pages.len(), "store the disk cache");
+        Ok(())
+    }
+
+    fn header(file_size: u64, page_size: u64, txid: u64) -> [u8; 32] {
+        let mut header = [0u8; 32];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&file_size.to_le_bytes());
+        header[16..24].copy_from_slice(&page_size.to_le_bytes());
+        header[24..32].copy_from_slice(&txid.to_le_bytes());
+        header
+    }
+}
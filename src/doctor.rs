@@ -0,0 +1,2045 @@
+use crate::bitmap::PgidBitmap;
+use crate::bolt::{self, PAGE_HEADER_SIZE};
+use crate::errors::DatabaseError;
+use crate::{utils, DB};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    rc::Rc,
+    str::FromStr,
+};
+
+// Severity is the priority of a finding, the critical findings should be fixed first.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    // the data can't be read correctly.
+    Critical,
+    // the data is readable, but some space is wasted or a meta page is broken.
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Severity::Critical, Severity::Warning, Severity::Info]
+            .into_iter()
+            .find(|severity| severity.as_str() == s)
+            .ok_or_else(|| DatabaseError::IncompatibleValue {
+                reason: format!("unknown severity {}", s),
+            })
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Severity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// FindingCode identifies the kind of a finding, the codes are stable so the tools
+// can match them instead of the messages.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum FindingCode {
+    // meta-unreadable: the meta page is beyond the end of the file.
+    MetaUnreadable,
+    // meta-invalid: the flags, magic, version or checksum of the meta is wrong.
+    MetaInvalid,
+    // meta-unknown-flags: the meta has flags or reserved bytes bolt never sets.
+    MetaUnknownFlags,
+    // meta-same-txid: both meta pages have the same txid.
+    MetaSameTxid,
+    // no-valid-meta: both meta pages are invalid.
+    NoValidMeta,
+    // freelist-not-synced: the freelist isn't persisted (NoFreelistSync).
+    FreelistNotSynced,
+    // freelist-invalid-flags: the freelist page isn't a freelist page.
+    FreelistInvalidFlags,
+    // freelist-too-many-ids: the count of the freelist page exceeds the page.
+    FreelistTooManyIds,
+    // page-out-of-range: a page is referenced beyond the max page id.
+    PageOutOfRange,
+    // page-unreadable: a page is beyond the end of the file.
+    PageUnreadable,
+    // page-invalid-flags: a page of a bucket is neither a branch nor a leaf page.
+    PageInvalidFlags,
+    // page-double-freed: a page is in the freelist more than once.
+    PageDoubleFreed,
+    // page-free-in-use: a free page is used by a bucket.
+    PageFreeInUse,
+    // page-double-referenced: a page is used by more than one owner.
+    PageDoubleReferenced,
+    // page-unreachable: the pages are neither reachable nor free.
+    PageUnreachable,
+    // overflow-orphaned: the overflow pages of the unreachable pages.
+    OverflowOrphaned,
+    // overflow-pages: the pages are used as overflow pages.
+    OverflowPages,
+    // element-out-of-page: an element or it's key or value is out of the page.
+    ElementOutOfPage,
+    // bucket-header-too-small: the value of a bucket is less than 16 bytes.
+    BucketHeaderTooSmall,
+    // key-order: a key is not greater than the previous key of the page.
+    KeyOrder,
+    // key-duplicate: a key is equal to the previous key of the page.
+    KeyDuplicate,
+    // key-empty: a key is empty, bolt never writes it.
+    KeyEmpty,
+    // key-out-of-range: a key is out of the range of the parent branch page.
+    KeyOutOfRange,
+    // branch-key-mismatch: the key of a branch element isn't the first key of the
+    // child page.
+    BranchKeyMismatch,
+    // sequence-behind: the keys look assigned by NextSequence, but the sequence of
+    // the bucket is less than the largest one.
+    SequenceBehind,
+    // sequence-zero: the keys look assigned by NextSequence, but the sequence of
+    // the bucket is 0.
+    SequenceZero,
+}
+
+impl FindingCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FindingCode::MetaUnreadable => "meta-unreadable",
+            FindingCode::MetaInvalid => "meta-invalid",
+            FindingCode::MetaUnknownFlags => "meta-unknown-flags",
+            FindingCode::MetaSameTxid => "meta-same-txid",
+            FindingCode::NoValidMeta => "no-valid-meta",
+            FindingCode::FreelistNotSynced => "freelist-not-synced",
+            FindingCode::FreelistInvalidFlags => "freelist-invalid-flags",
+            FindingCode::FreelistTooManyIds => "freelist-too-many-ids",
+            FindingCode::PageOutOfRange => "page-out-of-range",
+            FindingCode::PageUnreadable => "page-unreadable",
+            FindingCode::PageInvalidFlags => "page-invalid-flags",
+            FindingCode::PageDoubleFreed => "page-double-freed",
+            FindingCode::PageFreeInUse => "page-free-in-use",
+            FindingCode::PageDoubleReferenced => "page-double-referenced",
+            FindingCode::PageUnreachable => "page-unreachable",
+            FindingCode::OverflowOrphaned => "overflow-orphaned",
+            FindingCode::OverflowPages => "overflow-pages",
+            FindingCode::ElementOutOfPage => "element-out-of-page",
+            FindingCode::BucketHeaderTooSmall => "bucket-header-too-small",
+            FindingCode::KeyOrder => "key-order",
+            FindingCode::KeyDuplicate => "key-duplicate",
+            FindingCode::KeyEmpty => "key-empty",
+            FindingCode::KeyOutOfRange => "key-out-of-range",
+            FindingCode::BranchKeyMismatch => "branch-key-mismatch",
+            FindingCode::SequenceBehind => "sequence-behind",
+            FindingCode::SequenceZero => "sequence-zero",
+        }
+    }
+}
+
+impl FindingCode {
+    const ALL: [FindingCode; 26] = [
+        FindingCode::MetaUnreadable,
+        FindingCode::MetaInvalid,
+        FindingCode::MetaUnknownFlags,
+        FindingCode::MetaSameTxid,
+        FindingCode::NoValidMeta,
+        FindingCode::FreelistNotSynced,
+        FindingCode::FreelistInvalidFlags,
+        FindingCode::FreelistTooManyIds,
+        FindingCode::PageOutOfRange,
+        FindingCode::PageUnreadable,
+        FindingCode::PageInvalidFlags,
+        FindingCode::PageDoubleFreed,
+        FindingCode::PageFreeInUse,
+        FindingCode::PageDoubleReferenced,
+        FindingCode::PageUnreachable,
+        FindingCode::OverflowOrphaned,
+        FindingCode::OverflowPages,
+        FindingCode::ElementOutOfPage,
+        FindingCode::BucketHeaderTooSmall,
+        FindingCode::KeyOrder,
+        FindingCode::KeyDuplicate,
+        FindingCode::KeyEmpty,
+        FindingCode::KeyOutOfRange,
+        FindingCode::BranchKeyMismatch,
+        FindingCode::SequenceBehind,
+        FindingCode::SequenceZero,
+    ];
+}
+
+impl FromStr for FindingCode {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FindingCode::ALL
+            .into_iter()
+            .find(|code| code.as_str() == s)
+            .ok_or_else(|| DatabaseError::IncompatibleValue {
+                reason: format!("unknown finding code {}", s),
+            })
+    }
+}
+
+impl fmt::Display for FindingCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FindingCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub code: FindingCode,
+    // the page which the finding is about, it's None if it's about the database.
+    pub page_id: Option<u64>,
+    // the path of the bucket which the page belongs to, it's empty for the root
+    // bucket and None if the finding isn't about a bucket.
+    pub bucket: Option<Vec<Vec<u8>>>,
+    pub message: String,
+    // how to inspect or fix the problem.
+    pub suggestion: String,
+}
+
+impl Finding {
+    // bucket_path returns the bucket path joined by `/`, it's empty for the root
+    // bucket.
+    pub fn bucket_path(&self) -> Option<String> {
+        self.bucket.as_ref().map(|path| {
+            path.iter()
+                .map(|name| String::from_utf8_lossy(name))
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Finding {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Finding", 6)?;
+        state.serialize_field("severity", &self.severity)?;
+        state.serialize_field("code", &self.code)?;
+        state.serialize_field("page_id", &self.page_id)?;
+        state.serialize_field("bucket", &self.bucket_path())?;
+        state.serialize_field("message", &self.message)?;
+        state.serialize_field("suggestion", &self.suggestion)?;
+        state.end()
+    }
+}
+
+// CheckReport is the findings of a check, it's the schema shared by the doctor and
+// the page report, so the tools can gate on the severity of the findings.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+}
+
+impl CheckReport {
+    // max_severity returns the most severe finding's severity, it's None if there
+    // is no finding.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).min()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CheckReport {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("CheckReport", 1)?;
+        state.serialize_field("findings", &self.findings)?;
+        state.end()
+    }
+}
+
+// Diagnosis is the result of the doctor, the findings are ordered by severity.
+#[derive(Debug, Clone)]
+pub struct Diagnosis {
+    pub findings: Vec<Finding>,
+    // it's false if the check stopped at the first critical finding, the statistics
+    // only cover the pages walked before it.
+    pub complete: bool,
+    // the meta which is used to walk the database, it's None if both meta pages
+    // are invalid.
+    pub meta_page_id: Option<u64>,
+    pub max_page_id: u64,
+    // the pages reachable from the root bucket, including overflow pages.
+    pub reachable_pages: u64,
+    pub free_pages: u64,
+    pub overflow_pages: u64,
+    // the pages which are neither reachable nor free.
+    pub leaked_pages: u64,
+}
+
+impl Diagnosis {
+    // is_healthy reports whether there is no critical finding.
+    pub fn is_healthy(&self) -> bool {
+        self.findings
+            .iter()
+            .all(|finding| finding.severity != Severity::Critical)
+    }
+
+    // check_report returns the findings as the shared report schema.
+    pub fn check_report(&self) -> CheckReport {
+        CheckReport {
+            findings: self.findings.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Diagnosis {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Diagnosis", 9)?;
+        state.serialize_field("healthy", &self.is_healthy())?;
+        state.serialize_field("complete", &self.complete)?;
+        state.serialize_field("meta_page_id", &self.meta_page_id)?;
+        state.serialize_field("max_page_id", &self.max_page_id)?;
+        state.serialize_field("reachable_pages", &self.reachable_pages)?;
+        state.serialize_field("free_pages", &self.free_pages)?;
+        state.serialize_field("overflow_pages", &self.overflow_pages)?;
+        state.serialize_field("leaked_pages", &self.leaked_pages)?;
+        state.serialize_field("findings", &self.findings)?;
+        state.end()
+    }
+}
+
+// PageReport is the pages which are not owned exactly once, it's found by the same
+// walk as the doctor.
+#[derive(Debug, Clone, Default)]
+pub struct PageReport {
+    // the pages which are neither reachable nor free, excluding the orphaned
+    // overflow pages. It's empty if the freelist isn't synced.
+    pub unreachable: Vec<u64>,
+    // the overflow pages of the unreachable branch and leaf pages, they may
+    // contain a value which can be recovered.
+    pub orphaned_overflow: Vec<u64>,
+    // the pages which are used more than once, e.g. by two buckets, or by a bucket
+    // and the freelist.
+    pub double_referenced: Vec<u64>,
+}
+
+impl PageReport {
+    // check_report returns a finding for each page of the report, the double
+    // referenced pages are critical since writing them corrupts the database.
+    pub fn check_report(&self) -> CheckReport {
+        let pages = self
+            .unreachable
+            .iter()
+            .map(|&id| {
+                (
+                    Severity::Warning,
+                    FindingCode::PageUnreachable,
+                    id,
+                    format!("page {} is neither reachable nor free", id),
+                    "the space is wasted, compact the database with `bbolt compact` to reclaim it",
+                )
+            })
+            .chain(self.orphaned_overflow.iter().map(|&id| {
+                (
+                    Severity::Warning,
+                    FindingCode::OverflowOrphaned,
+                    id,
+                    format!("page {} is an overflow page of an unreachable page", id),
+                    "it may contain a value which is lost",
+                )
+            }))
+            .chain(self.double_referenced.iter().map(|&id| {
+                (
+                    Severity::Critical,
+                    FindingCode::PageDoubleReferenced,
+                    id,
+                    format!("page {} is used more than once", id),
+                    "writing to one of the owners corrupts the others",
+                )
+            }));
+
+        CheckReport {
+            findings: pages
+                .map(|(severity, code, id, message, suggestion)| Finding {
+                    severity,
+                    code,
+                    page_id: Some(id),
+                    bucket: None,
+                    message,
+                    suggestion: suggestion.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Owner is what a page is used by.
+#[derive(Debug, Clone)]
+enum Owner {
+    Meta,
+    Freelist,
+    Free,
+    Bucket(Vec<Vec<u8>>),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Meta => f.write_str("the meta"),
+            Owner::Freelist => f.write_str("the freelist"),
+            Owner::Free => f.write_str("the free pages"),
+            Owner::Bucket(path) if path.is_empty() => f.write_str("the root bucket"),
+            Owner::Bucket(path) => {
+                let path: Vec<_> = path
+                    .iter()
+                    .map(|name| String::from_utf8_lossy(name))
+                    .collect();
+                write!(f, "bucket {}", path.join("/"))
+            }
+        }
+    }
+}
+
+// PageOwners is the kinds of the owners of the claimed pages, the pages of the meta
+// and the freelist are claimed but neither reachable nor free. The bucket of each
+// page isn't kept, so it takes a few bits per page on the huge databases.
+#[derive(Debug, Clone, Default)]
+struct PageOwners {
+    claimed: PgidBitmap,
+    // the pages of the buckets.
+    reachable: PgidBitmap,
+    free: PgidBitmap,
+}
+
+impl PageOwners {
+    fn insert(&mut self, page_id: u64, owner: &Owner) {
+        self.claimed.insert(page_id);
+        match owner {
+            Owner::Bucket(_) => self.reachable.insert(page_id),
+            Owner::Free => self.free.insert(page_id),
+            Owner::Meta | Owner::Freelist => false,
+        };
+    }
+}
+
+// KeyRange is the range of the keys of a page, it's bounded by the separator keys of
+// the parent branch page. The lower bound is inclusive and the upper is exclusive.
+#[derive(Debug, Clone, Default)]
+struct KeyRange {
+    // the parent branch page whose element points to the page, the lower bound is
+    // the key of the element.
+    parent_page_id: Option<u64>,
+    lower: Option<Vec<u8>>,
+    upper: Option<Vec<u8>>,
+}
+
+// SequenceStats is the sequence of a bucket and the keys which may be assigned by
+// NextSequence, the keys are usually the 8 bytes big-endian ids in that case.
+#[derive(Debug, Clone, Default)]
+struct SequenceStats {
+    sequence: u64,
+    keys: u64,
+    // the keys which are 8 bytes big-endian integers in 1..2^32, the larger ones
+    // are more likely timestamps or hashes.
+    ids: u64,
+    max_id: u64,
+}
+
+impl SequenceStats {
+    fn add_key(&mut self, key: &[u8]) {
+        self.keys += 1;
+        let Ok(key) = <[u8; 8]>::try_from(key) else {
+            return;
+        };
+        let id = u64::from_be_bytes(key);
+        if (1..1 << 32).contains(&id) {
+            self.ids += 1;
+            self.max_id = self.max_id.max(id);
+        }
+    }
+
+    // is_sequence_derived reports whether all keys look assigned by NextSequence, a
+    // single key is too weak to tell.
+    fn is_sequence_derived(&self) -> bool {
+        self.keys >= 2 && self.ids == self.keys
+    }
+}
+
+// FindingSink receives the findings of the doctor, the walk stops once the sink is
+// done, so the rest of the database isn't read.
+trait FindingSink {
+    fn push(&mut self, finding: Finding);
+
+    fn is_done(&self) -> bool;
+
+    fn into_findings(self) -> Vec<Finding>;
+}
+
+// CollectAll collects all findings, it's used to assess the extent of the damage.
+#[derive(Default)]
+struct CollectAll(Vec<Finding>);
+
+impl FindingSink for CollectAll {
+    fn push(&mut self, finding: Finding) {
+        self.0.push(finding);
+    }
+
+    fn is_done(&self) -> bool {
+        false
+    }
+
+    fn into_findings(self) -> Vec<Finding> {
+        self.0
+    }
+}
+
+// FailFast is done at the first critical finding, the findings before it are kept.
+#[derive(Default)]
+struct FailFast(Vec<Finding>);
+
+impl FindingSink for FailFast {
+    fn push(&mut self, finding: Finding) {
+        if !self.is_done() {
+            self.0.push(finding);
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.0
+            .iter()
+            .any(|finding| finding.severity == Severity::Critical)
+    }
+
+    fn into_findings(self) -> Vec<Finding> {
+        self.0
+    }
+}
+
+// Doctor checks the database without the page cache of the DB, all reads are
+// checked so the corrupted pages are reported instead of panic.
+struct Doctor<'a, S: FindingSink> {
+    db: &'a mut DB,
+    page_size: u64,
+    // the meta which is used to walk the database.
+    meta_page_id: Option<u64>,
+    max_page_id: u64,
+    free_pages: u64,
+    // the free pages are unknown if the freelist isn't synced, so are the leaked
+    // pages.
+    freelist_synced: bool,
+    leaked_pages: u64,
+    owners: PageOwners,
+    sink: S,
+    overflow_pages: u64,
+    report: PageReport,
+    // the sequence stats of the buckets except the root bucket.
+    sequences: BTreeMap<Vec<Vec<u8>>, SequenceStats>,
+    // the pages of the buckets which are not walked yet.
+    stack: Vec<(u64, Owner, KeyRange)>,
+    checked_pages: u64,
+}
+
+impl<'a, S: FindingSink> Doctor<'a, S> {
+    fn new(db: &'a mut DB, sink: S) -> Self {
+        // The default page size is used if the meta page 0 can't be read, the meta
+        // page 1 is checked with it.
+        let page_size = db.detect_page_size().unwrap_or(4096);
+        Doctor {
+            db,
+            page_size,
+            meta_page_id: None,
+            max_page_id: 0,
+            free_pages: 0,
+            freelist_synced: true,
+            leaked_pages: 0,
+            owners: PageOwners::default(),
+            sink,
+            overflow_pages: 0,
+            report: PageReport::default(),
+            sequences: BTreeMap::new(),
+            stack: Vec::new(),
+            checked_pages: 0,
+        }
+    }
+
+    fn report(
+        &mut self,
+        severity: Severity,
+        code: FindingCode,
+        page_id: Option<u64>,
+        message: String,
+        suggestion: String,
+    ) {
+        self.sink.push(Finding {
+            severity,
+            code,
+            page_id,
+            bucket: None,
+            message,
+            suggestion,
+        });
+    }
+
+    // report_owned reports the finding about the page of the owner, the bucket of
+    // the finding is set if the owner is a bucket.
+    fn report_owned(
+        &mut self,
+        owner: &Owner,
+        severity: Severity,
+        code: FindingCode,
+        page_id: u64,
+        message: String,
+        suggestion: String,
+    ) {
+        let bucket = match owner {
+            Owner::Bucket(path) => Some(path.clone()),
+            _ => None,
+        };
+        self.sink.push(Finding {
+            severity,
+            code,
+            page_id: Some(page_id),
+            bucket,
+            message,
+            suggestion,
+        });
+    }
+
+    fn inspect(&self, page_id: u64) -> String {
+        format!(
+            "inspect it with `dump --offset {}`",
+            page_id * self.page_size
+        )
+    }
+
+    // check_meta returns the meta if the meta page is valid.
+    fn check_meta(&mut self, page_id: u64) -> Option<bolt::Meta> {
+        let data = match self
+            .db
+            .try_read(page_id * self.page_size, self.page_size as usize)
+        {
+            Ok(data) => data,
+            Err(e) => {
+                self.report(
+                    Severity::Critical,
+                    FindingCode::MetaUnreadable,
+                    Some(page_id),
+                    format!("meta {} can't be read: {}", page_id, e),
+                    "the file is truncated, restore it from a backup".to_string(),
+                );
+                return None;
+            }
+        };
+
+        let page: bolt::Page = TryFrom::try_from(data.as_slice()).unwrap();
+        let meta: bolt::Meta = TryFrom::try_from(data.as_slice()).unwrap();
+        let problem = if !page.flags.contains(bolt::PageFlag::MetaPageFlag) {
+            Some(format!("unexpected page flags {:#x}", page.flags.as_u16()))
+        } else if meta.magic != bolt::MAGIC_NUMBER {
+            Some(format!("invalid magic number {:#x}", meta.magic))
+        } else if meta.version != bolt::DATAFILE_VERSION {
+            Some(format!("unsupported version {}", meta.version))
+        } else if meta.checksum != utils::fnv64(&data[16..72]) {
+            Some("checksum mismatch".to_string())
+        } else {
+            None
+        };
+
+        match problem {
+            Some(problem) => {
+                self.report(
+                    Severity::Warning,
+                    FindingCode::MetaInvalid,
+                    Some(page_id),
+                    format!("meta {} is invalid: {}", page_id, problem),
+                    format!(
+                        "bolt falls back to the other meta, {}",
+                        self.inspect(page_id)
+                    ),
+                );
+                None
+            }
+            None => {
+                // bolt never sets them, the database may be written by a fork.
+                if meta.flags != 0 || data[80..].iter().any(|&b| b != 0) {
+                    self.report(
+                        Severity::Info,
+                        FindingCode::MetaUnknownFlags,
+                        Some(page_id),
+                        format!(
+                            "meta {} has flags {:#x} or reserved bytes which bolt never sets",
+                            page_id, meta.flags
+                        ),
+                        format!(
+                            "the database may be written by a fork of bolt, {}",
+                            self.inspect(page_id)
+                        ),
+                    );
+                }
+                Some(meta)
+            }
+        }
+    }
+
+    // claim records the owner of the pages, a page must be owned only once. It
+    // returns false if the pages can't be read, or they are already walked as the
+    // pages of a bucket, which avoids walking a page twice.
+    fn claim(&mut self, page_id: u64, overflow: u64, owner: &Owner) -> bool {
+        if page_id.saturating_add(overflow) >= self.max_page_id {
+            self.report_owned(
+                owner,
+                Severity::Critical,
+                FindingCode::PageOutOfRange,
+                page_id,
+                format!(
+                    "page {} (overflow {}) of {} is beyond the max page id {}",
+                    page_id, overflow, owner, self.max_page_id
+                ),
+                "the file is truncated or the reference is corrupted, restore it from a backup"
+                    .to_string(),
+            );
+            return false;
+        }
+
+        let mut walked = false;
+        for id in page_id..=(page_id + overflow) {
+            if !self.owners.claimed.contains(id) {
+                continue;
+            }
+            let existing_bucket = self.owners.reachable.contains(id);
+            let existing_free = !existing_bucket && self.owners.free.contains(id);
+            walked |= existing_bucket;
+            self.report.double_referenced.push(id);
+            let (code, message, suggestion) = match owner {
+                Owner::Free if existing_free => (
+                    FindingCode::PageDoubleFreed,
+                    format!("page {} is freed more than once in the freelist", id),
+                    "bolt refuses to open it, compact the database with `bbolt compact`"
+                        .to_string(),
+                ),
+                Owner::Bucket(_) if existing_free => (
+                    FindingCode::PageFreeInUse,
+                    format!("page {} is free, but it's used by {}", id, owner),
+                    format!(
+                        "the page may be reused while it's referenced, {}",
+                        self.inspect(id)
+                    ),
+                ),
+                _ => {
+                    // Only the kind of the existing owner is kept, see PageOwners, the
+                    // bucket is looked up again.
+                    let existing = if existing_bucket {
+                        self.first_owner(id)
+                            .map_or("a bucket".to_string(), |owner| owner.to_string())
+                    } else if existing_free {
+                        Owner::Free.to_string()
+                    } else if id < 2 {
+                        Owner::Meta.to_string()
+                    } else {
+                        Owner::Freelist.to_string()
+                    };
+                    (
+                        FindingCode::PageDoubleReferenced,
+                        format!("page {} is used by both {} and {}", id, existing, owner),
+                        format!(
+                            "writing to one of them corrupts the other, {}",
+                            self.inspect(id)
+                        ),
+                    )
+                }
+            };
+            self.report_owned(owner, Severity::Critical, code, id, message, suggestion);
+        }
+        if walked {
+            return false;
+        }
+        for id in page_id..=(page_id + overflow) {
+            self.owners.insert(id, owner);
+        }
+        true
+    }
+
+    // first_owner returns the bucket which the page is claimed by first. The pages
+    // are walked in the same order as check_tree until the page is reached, they are
+    // not claimed, and the pages which can't be read are skipped.
+    fn first_owner(&mut self, page_id: u64) -> Option<Owner> {
+        let meta_page_id = self.meta_page_id?;
+        let meta = self
+            .db
+            .try_read(meta_page_id * self.page_size, 80)
+            .ok()
+            .and_then(|data| bolt::Meta::try_from(data.as_slice()).ok())?;
+        let mut visited = PgidBitmap::with_max_pgid(self.max_page_id);
+        let mut stack = vec![(u64::from(meta.root_pgid), Vec::new())];
+        while let Some((id, bucket)) = stack.pop() {
+            if id >= self.max_page_id || !visited.insert(id) {
+                continue;
+            }
+            let Some((page, data)) = self.peek_page(id) else {
+                continue;
+            };
+            if (id..=id + page.overflow as u64).contains(&page_id) {
+                return Some(Owner::Bucket(bucket));
+            }
+
+            let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+            if !is_branch && !page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+                continue;
+            }
+            let mut children = Vec::new();
+            for i in 0..page.count as usize {
+                let start = PAGE_HEADER_SIZE + i * 16;
+                let Some(element) = data.get(start..start + 16) else {
+                    break;
+                };
+                if is_branch {
+                    let element = bolt::BranchPageElement::try_from(element).unwrap();
+                    if start + element.pos as usize + element.ksize as usize > data.len() {
+                        break;
+                    }
+                    children.push(u64::from(element.pgid));
+                    continue;
+                }
+
+                let element = bolt::LeafPageElement::try_from(element).unwrap();
+                let key_start = start + element.pos as usize;
+                let value_start = key_start + element.ksize as usize;
+                let Some(value) = data.get(value_start..value_start + element.vsize as usize)
+                else {
+                    break;
+                };
+                if element.flags != 0x01 || value.len() < 16 {
+                    continue;
+                }
+                let root = utils::read_value::<u64>(value, 0);
+                if root != 0 {
+                    let mut child_bucket = bucket.clone();
+                    child_bucket.push(data[key_start..value_start].to_vec());
+                    stack.push((root, child_bucket));
+                }
+            }
+            stack.extend(children.into_iter().map(|child| (child, bucket.clone())));
+        }
+        None
+    }
+
+    // peek_page reads the page with it's overflow pages like read_page, but it's not
+    // claimed, and the errors are not reported.
+    fn peek_page(&mut self, page_id: u64) -> Option<(bolt::Page, Vec<u8>)> {
+        let header = self
+            .db
+            .try_read(page_id * self.page_size, PAGE_HEADER_SIZE)
+            .ok()?;
+        let page: bolt::Page = TryFrom::try_from(header.as_slice()).unwrap();
+        if page.overflow as u64 > self.db.limits().max_overflow {
+            return None;
+        }
+        let size = usize::try_from(self.page_size * (page.overflow as u64 + 1)).ok()?;
+        let data = self.db.try_read(page_id * self.page_size, size).ok()?;
+        Some((page, data))
+    }
+
+    // read_page reads the page with it's overflow pages, it's claimed by the owner.
+    fn read_page(&mut self, page_id: u64, owner: &Owner) -> Option<(bolt::Page, Vec<u8>)> {
+        if page_id >= self.max_page_id {
+            self.claim(page_id, 0, owner);
+            return None;
+        }
+
+        let header = self.db.try_read(page_id * self.page_size, PAGE_HEADER_SIZE);
+        let page = header.and_then(|header| {
+            let page: bolt::Page = TryFrom::try_from(header.as_slice()).unwrap();
+            if !self.claim(page_id, page.overflow as u64, owner) {
+                return Ok(None);
+            }
+            self.overflow_pages += page.overflow as u64;
+
+            let max_overflow = self.db.limits().max_overflow;
+            if page.overflow as u64 > max_overflow {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "it has {} overflow pages, which exceeds the limit {}",
+                        page.overflow, max_overflow
+                    ),
+                ));
+            }
+            let size = usize::try_from(self.page_size * (page.overflow as u64 + 1))
+                .map_err(|_| std::io::Error::other("the page exceeds the address space"))?;
+            let data = self.db.try_read(page_id * self.page_size, size)?;
+            Ok(Some((page, data)))
+        });
+
+        match page {
+            Ok(page) => page,
+            Err(e) => {
+                self.report_owned(
+                    owner,
+                    Severity::Critical,
+                    FindingCode::PageUnreadable,
+                    page_id,
+                    format!("page {} of {} can't be read: {}", page_id, owner, e),
+                    if e.kind() == std::io::ErrorKind::InvalidData {
+                        "the page header may be corrupted, restore it from a backup".to_string()
+                    } else {
+                        "the file is truncated, restore it from a backup".to_string()
+                    },
+                );
+                None
+            }
+        }
+    }
+
+    fn check_freelist(&mut self, page_id: u64) -> u64 {
+        let Some((page, data)) = self.read_page(page_id, &Owner::Freelist) else {
+            return 0;
+        };
+        if !page.flags.contains(bolt::PageFlag::FreelistPageFlag) {
+            self.report(
+                Severity::Critical,
+                FindingCode::FreelistInvalidFlags,
+                Some(page_id),
+                format!(
+                    "freelist page {} has unexpected flags {:#x}",
+                    page_id,
+                    page.flags.as_u16()
+                ),
+                "the free pages are unknown, compact the database with `bbolt compact`".to_string(),
+            );
+            return 0;
+        }
+
+        // The real count is stored in the first element if it overflows u16.
+        let (count, start) = if page.count == 0xFFFF {
+            (utils::read_value::<u64>(&data, PAGE_HEADER_SIZE), 1)
+        } else {
+            (page.count as u64, 0)
+        };
+        let capacity = ((data.len() - PAGE_HEADER_SIZE) / 8) as u64;
+        if count.saturating_add(start) > capacity {
+            self.report(
+                Severity::Critical,
+                FindingCode::FreelistTooManyIds,
+                Some(page_id),
+                format!(
+                    "freelist page {} has {} ids, but only {} fit in the page",
+                    page_id, count, capacity
+                ),
+                "the free pages are unknown, compact the database with `bbolt compact`".to_string(),
+            );
+            return 0;
+        }
+
+        for i in start..(start + count) {
+            if self.sink.is_done() {
+                break;
+            }
+            let id = utils::read_value::<u64>(&data, PAGE_HEADER_SIZE + i as usize * 8);
+            self.claim(id, 0, &Owner::Free);
+        }
+        count
+    }
+
+    // check_tree walks the pages of the buckets from the root bucket. The keys of
+    // each page must be strictly sorted, and within the range of the separator keys
+    // of it's parent branch page. It returns false if the walk is paused before a
+    // page, the rest of the pages are kept in the stack.
+    fn check_tree(&mut self, pause: &mut dyn FnMut() -> bool) -> bool {
+        while let Some((page_id, owner, range)) = self.stack.pop() {
+            if self.sink.is_done() {
+                break;
+            }
+            if pause() {
+                self.stack.push((page_id, owner, range));
+                return false;
+            }
+            self.checked_pages += 1;
+            let Some((page, data)) = self.read_page(page_id, &owner) else {
+                continue;
+            };
+            let Owner::Bucket(bucket) = &owner else {
+                unreachable!("the tree pages are owned by buckets");
+            };
+
+            let is_branch = page.flags.contains(bolt::PageFlag::BranchPageFlag);
+            if !is_branch && !page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+                self.report_owned(
+                    &owner,
+                    Severity::Critical,
+                    FindingCode::PageInvalidFlags,
+                    page_id,
+                    format!(
+                        "page {} of {} has unexpected flags {:#x}",
+                        page_id,
+                        owner,
+                        page.flags.as_u16()
+                    ),
+                    format!(
+                        "the page may be reused while it's referenced, {}",
+                        self.inspect(page_id)
+                    ),
+                );
+                continue;
+            }
+
+            let mut keys = Vec::with_capacity(page.count as usize);
+            let mut children = Vec::new();
+            for i in 0..page.count as usize {
+                let start = PAGE_HEADER_SIZE + i * 16;
+                let Some(element) = data.get(start..start + 16) else {
+                    self.report_element(page_id, i, &owner);
+                    break;
+                };
+
+                if is_branch {
+                    let element = bolt::BranchPageElement::try_from(element).unwrap();
+                    let key_start = start + element.pos as usize;
+                    let key_end = key_start + element.ksize as usize;
+                    if key_end > data.len() {
+                        self.report_element(page_id, i, &owner);
+                        break;
+                    }
+                    keys.push(data[key_start..key_end].to_vec());
+                    children.push(u64::from(element.pgid));
+                    continue;
+                }
+
+                let element = bolt::LeafPageElement::try_from(element).unwrap();
+                let key_start = start + element.pos as usize;
+                let value_start = key_start + element.ksize as usize;
+                let value_end = value_start + element.vsize as usize;
+                if value_end > data.len() {
+                    self.report_element(page_id, i, &owner);
+                    break;
+                }
+                keys.push(data[key_start..value_start].to_vec());
+                if element.flags != 0x01 {
+                    if !bucket.is_empty() {
+                        self.sequences
+                            .entry(bucket.clone())
+                            .or_default()
+                            .add_key(&data[key_start..value_start]);
+                    }
+                    continue;
+                }
+
+                let name = data[key_start..value_start].to_vec();
+                if element.vsize < 16 {
+                    self.report_owned(
+                        &owner,
+                        Severity::Critical,
+                        FindingCode::BucketHeaderTooSmall,
+                        page_id,
+                        format!(
+                            "bucket {} in page {} has a value of {} bytes",
+                            String::from_utf8_lossy(&name),
+                            page_id,
+                            element.vsize
+                        ),
+                        format!(
+                            "the bucket header is at least 16 bytes, {}",
+                            self.inspect(page_id)
+                        ),
+                    );
+                    continue;
+                }
+                // The inline buckets are stored in the value, they don't have pages.
+                let root = utils::read_value::<u64>(&data, value_start);
+                let mut child_bucket = bucket.clone();
+                child_bucket.push(name);
+                let stats = self.sequences.entry(child_bucket.clone()).or_default();
+                stats.sequence = utils::read_value::<u64>(&data, value_start + 8);
+                if root == 0 {
+                    let keys = inline_keys(&data[value_start + 16..value_end]);
+                    for key in &keys {
+                        stats.add_key(key);
+                    }
+                    let keys: Vec<Vec<u8>> = keys.into_iter().map(<[u8]>::to_vec).collect();
+                    let owner = Owner::Bucket(child_bucket);
+                    self.check_key_order(page_id, &owner, &keys, &KeyRange::default());
+                } else {
+                    self.stack
+                        .push((root, Owner::Bucket(child_bucket), KeyRange::default()));
+                }
+            }
+
+            self.check_key_order(page_id, &owner, &keys, &range);
+            // The child i of the branch page holds the keys from the separator i to
+            // the separator i+1, the last child is bounded by the parent range.
+            for (i, child) in children.into_iter().enumerate() {
+                let child_range = KeyRange {
+                    parent_page_id: Some(page_id),
+                    lower: Some(keys[i].clone()),
+                    upper: keys.get(i + 1).cloned().or_else(|| range.upper.clone()),
+                };
+                self.stack.push((child, owner.clone(), child_range));
+            }
+        }
+        true
+    }
+
+    // check_key_order reports the first empty key, the first key which isn't greater
+    // than the previous key, the keys out of the range of the parent branch page, and
+    // the first key which isn't the key of the parent's element.
+    fn check_key_order(&mut self, page_id: u64, owner: &Owner, keys: &[Vec<u8>], range: &KeyRange) {
+        if let Some(i) = keys.iter().position(|key| key.is_empty()) {
+            self.report_owned(
+                owner,
+                Severity::Warning,
+                FindingCode::KeyEmpty,
+                page_id,
+                format!("key {} of page {} of {} is empty", i, page_id, owner),
+                format!(
+                    "bolt doesn't allow the empty keys, the page may be written by another tool, {}",
+                    self.inspect(page_id)
+                ),
+            );
+        }
+
+        let unordered = (1..keys.len()).find(|&i| keys[i - 1] >= keys[i]);
+        if let Some(i) = unordered.filter(|&i| keys[i - 1] == keys[i]) {
+            self.report_owned(
+                owner,
+                Severity::Critical,
+                FindingCode::KeyDuplicate,
+                page_id,
+                format!(
+                    "key {} of page {} of {} appears more than once",
+                    keys[i].escape_ascii(),
+                    page_id,
+                    owner
+                ),
+                format!(
+                    "the lookups return only one of the values, the iterators return all of them, {}",
+                    self.inspect(page_id)
+                ),
+            );
+        } else if let Some(i) = unordered {
+            self.report_owned(
+                owner,
+                Severity::Critical,
+                FindingCode::KeyOrder,
+                page_id,
+                format!(
+                    "key {} of page {} of {} is not greater than the previous key {}",
+                    keys[i].escape_ascii(),
+                    page_id,
+                    owner,
+                    keys[i - 1].escape_ascii()
+                ),
+                format!(
+                    "the page may be modified by a bad surgery, the lookups of the keys may fail, {}",
+                    self.inspect(page_id)
+                ),
+            );
+        }
+
+        let out_of_range = keys.iter().find(|key| {
+            range.lower.as_ref().is_some_and(|lower| *key < lower)
+                || range.upper.as_ref().is_some_and(|upper| *key >= upper)
+        });
+        if let Some(key) = out_of_range {
+            self.report_owned(
+                owner,
+                Severity::Critical,
+                FindingCode::KeyOutOfRange,
+                page_id,
+                format!(
+                    "key {} of page {} of {} is out of the range of the parent branch page",
+                    key.escape_ascii(),
+                    page_id,
+                    owner
+                ),
+                format!(
+                    "the separator keys of the parent page may be stale, the lookups of the key may fail, {}",
+                    self.inspect(page_id)
+                ),
+            );
+        }
+
+        // bolt sets the key of the branch element to the first key of the child when
+        // the child is written, the first key less than it is out of the range.
+        let first = keys.first().zip(range.lower.as_ref());
+        if let Some((first, lower)) = first.filter(|(first, lower)| first > lower) {
+            self.report_owned(
+                owner,
+                Severity::Warning,
+                FindingCode::BranchKeyMismatch,
+                page_id,
+                format!(
+                    "the key {} of the element of branch page {} isn't the first key {} of page {} of {}",
+                    lower.escape_ascii(),
+                    range.parent_page_id.unwrap(),
+                    first.escape_ascii(),
+                    page_id,
+                    owner
+                ),
+                format!(
+                    "the keys may be deleted from the page without updating the parent, {}",
+                    self.inspect(range.parent_page_id.unwrap())
+                ),
+            );
+        }
+    }
+
+    // check_sequences reports the buckets whose keys look assigned by NextSequence,
+    // but the sequence is behind them, the next ids will collide with the keys.
+    fn check_sequences(&mut self) {
+        let sequences = std::mem::take(&mut self.sequences);
+        for (bucket, stats) in sequences {
+            if !stats.is_sequence_derived() || stats.sequence >= stats.max_id {
+                continue;
+            }
+            let owner = Owner::Bucket(bucket);
+            let (severity, code, message) = if stats.sequence == 0 {
+                (
+                    Severity::Info,
+                    FindingCode::SequenceZero,
+                    format!(
+                        "the {} keys of {} look like sequence numbers up to {}, but the sequence is 0",
+                        stats.keys, owner, stats.max_id
+                    ),
+                )
+            } else {
+                (
+                    Severity::Warning,
+                    FindingCode::SequenceBehind,
+                    format!(
+                        "the sequence of {} is {}, but it's keys look like sequence numbers up to {}",
+                        owner, stats.sequence, stats.max_id
+                    ),
+                )
+            };
+            self.sink.push(Finding {
+                severity,
+                code,
+                page_id: None,
+                bucket: match owner {
+                    Owner::Bucket(path) => Some(path),
+                    _ => None,
+                },
+                message,
+                suggestion: "NextSequence may return the ids which are already used, check the application which assigns the keys".to_string(),
+            });
+        }
+    }
+
+    fn report_element(&mut self, page_id: u64, index: usize, owner: &Owner) {
+        self.report_owned(
+            owner,
+            Severity::Critical,
+            FindingCode::ElementOutOfPage,
+            page_id,
+            format!(
+                "element {} of page {} of {} is out of the page",
+                index, page_id, owner
+            ),
+            format!(
+                "the following elements can't be read, {}",
+                self.inspect(page_id)
+            ),
+        );
+    }
+
+    // report_leaked reports the continuous ranges of the pages which are neither
+    // reachable nor free. The overflow pages of an unreachable page are reported
+    // separately, they may contain a value which can be recovered.
+    fn report_leaked(&mut self) -> u64 {
+        let leaked: Vec<u64> = (2..self.max_page_id)
+            .filter(|&id| !self.owners.claimed.contains(id))
+            .collect();
+
+        let mut heads = Vec::new();
+        let mut i = 0;
+        while i < leaked.len() {
+            let id = leaked[i];
+            i += 1;
+            self.report.unreachable.push(id);
+
+            let overflow = self.read_overflow(id);
+            let mut orphaned = false;
+            while i < leaked.len() && leaked[i] <= id + overflow && leaked[i] == leaked[i - 1] + 1 {
+                self.report.orphaned_overflow.push(leaked[i]);
+                orphaned = true;
+                i += 1;
+            }
+            if orphaned {
+                heads.push(id);
+            }
+        }
+
+        if !self.report.unreachable.is_empty() {
+            let message = format!(
+                "{} pages are neither reachable nor free: {}",
+                self.report.unreachable.len(),
+                format_ranges(&self.report.unreachable)
+            );
+            self.report(
+                Severity::Warning,
+                FindingCode::PageUnreachable,
+                None,
+                message,
+                "the space is wasted, compact the database with `bbolt compact` to reclaim it"
+                    .to_string(),
+            );
+        }
+        if !self.report.orphaned_overflow.is_empty() {
+            let message = format!(
+                "{} pages are the overflow pages of the unreachable pages {}: {}",
+                self.report.orphaned_overflow.len(),
+                format_ranges(&heads),
+                format_ranges(&self.report.orphaned_overflow)
+            );
+            self.report(
+                Severity::Warning,
+                FindingCode::OverflowOrphaned,
+                None,
+                message,
+                format!(
+                    "they may contain a value which is lost, {}",
+                    self.inspect(heads[0])
+                ),
+            );
+        }
+        leaked.len() as u64
+    }
+
+    // read_overflow returns the overflow of the page if it's a branch or leaf page,
+    // the page header may be garbage since it's not referenced.
+    fn read_overflow(&mut self, page_id: u64) -> u64 {
+        let Ok(header) = self.db.try_read(page_id * self.page_size, PAGE_HEADER_SIZE) else {
+            return 0;
+        };
+        let page: bolt::Page = TryFrom::try_from(header.as_slice()).unwrap();
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag)
+            || page.flags.contains(bolt::PageFlag::LeafPageFlag)
+        {
+            page.overflow as u64
+        } else {
+            0
+        }
+    }
+
+    // check_start checks the meta pages and the freelist, and pushes the root page
+    // of the root bucket to the stack. It returns false if both meta pages are
+    // invalid, there is nothing to walk.
+    fn check_start(&mut self) -> bool {
+        let metas = [self.check_meta(0), self.check_meta(1)];
+        let current = match metas {
+            [Some(meta0), Some(meta1)] => {
+                if meta0.txid == meta1.txid {
+                    self.report(
+                        Severity::Warning,
+                        FindingCode::MetaSameTxid,
+                        None,
+                        format!("both meta pages have the same txid {}", meta0.txid),
+                        "one of them should be written by the last transaction".to_string(),
+                    );
+                }
+                if meta0.txid >= meta1.txid {
+                    Some((0, meta0))
+                } else {
+                    Some((1, meta1))
+                }
+            }
+            [Some(meta0), None] => Some((0, meta0)),
+            [None, Some(meta1)] => Some((1, meta1)),
+            [None, None] => None,
+        };
+        let Some((meta_page_id, meta)) = current else {
+            self.report(
+                Severity::Critical,
+                FindingCode::NoValidMeta,
+                None,
+                "both meta pages are invalid".to_string(),
+                "the database can't be opened, restore it from a backup".to_string(),
+            );
+            return false;
+        };
+
+        self.meta_page_id = Some(meta_page_id);
+        self.max_page_id = meta.max_pgid.into();
+        self.owners.claimed = PgidBitmap::with_max_pgid(self.max_page_id);
+        self.owners.insert(0, &Owner::Meta);
+        self.owners.insert(1, &Owner::Meta);
+        // The freelist isn't persisted if it's u64::MAX (NoFreelistSync).
+        let freelist_page_id: u64 = meta.freelist_pgid.into();
+        if freelist_page_id == u64::MAX {
+            self.freelist_synced = false;
+            self.report(
+                Severity::Info,
+                FindingCode::FreelistNotSynced,
+                None,
+                "the freelist isn't synced, the free pages are unknown".to_string(),
+                "bolt rebuilds the freelist when the database is opened".to_string(),
+            );
+        } else {
+            self.free_pages = self.check_freelist(freelist_page_id);
+        }
+        self.stack.push((
+            meta.root_pgid.into(),
+            Owner::Bucket(Vec::new()),
+            KeyRange::default(),
+        ));
+        true
+    }
+
+    // check_end checks what's collected by the walk, it's called after all pages of
+    // the buckets are walked.
+    fn check_end(&mut self) {
+        self.check_sequences();
+
+        // The pages not walked are not leaked, they are unknown.
+        if self.freelist_synced && !self.sink.is_done() {
+            self.leaked_pages = self.report_leaked();
+        }
+        if self.overflow_pages > 0 {
+            let message = format!("{} pages are used as overflow pages", self.overflow_pages);
+            self.report(
+                Severity::Info,
+                FindingCode::OverflowPages,
+                None,
+                message,
+                "list them with `pages` to find the large values".to_string(),
+            );
+        }
+    }
+
+    fn finish(mut self) -> (Diagnosis, PageReport) {
+        let complete = !self.sink.is_done();
+        let mut findings = self.sink.into_findings();
+        findings.sort_by_key(|finding| finding.severity);
+        // A page is claimed once more for each extra owner.
+        let double_referenced: BTreeSet<u64> = self.report.double_referenced.drain(..).collect();
+        self.report.double_referenced = double_referenced.into_iter().collect();
+        let reachable_pages = self.owners.reachable.len();
+        let diagnosis = Diagnosis {
+            findings,
+            complete,
+            meta_page_id: self.meta_page_id,
+            max_page_id: self.max_page_id,
+            reachable_pages,
+            free_pages: self.free_pages,
+            overflow_pages: self.overflow_pages,
+            leaked_pages: self.leaked_pages,
+        };
+        (diagnosis, self.report)
+    }
+}
+
+impl<'a> Doctor<'a, CollectAll> {
+    // resume restores the doctor from the checkpoint, the meta pages must be the
+    // same as when the checkpoint is saved, otherwise the walked pages may be
+    // changed.
+    fn resume(db: &'a mut DB, checkpoint: DoctorCheckpoint) -> Result<Self, DatabaseError> {
+        let mut doctor = Doctor::new(db, CollectAll(checkpoint.findings));
+        if doctor.page_size != checkpoint.page_size || doctor.metas_hash() != checkpoint.metas_hash
+        {
+            return Err(DatabaseError::IncompatibleValue {
+                reason:
+                    "the database is changed since the checkpoint is saved, remove it to start over"
+                        .to_string(),
+            });
+        }
+
+        doctor.meta_page_id = Some(checkpoint.meta_page_id);
+        doctor.max_page_id = checkpoint.max_page_id;
+        doctor.free_pages = checkpoint.free_pages;
+        doctor.freelist_synced = checkpoint.freelist_synced;
+        doctor.owners = checkpoint.owners;
+        doctor.overflow_pages = checkpoint.overflow_pages;
+        doctor.report.double_referenced = checkpoint.double_referenced;
+        doctor.sequences = checkpoint.sequences;
+        doctor.stack = checkpoint.stack;
+        doctor.checked_pages = checkpoint.checked_pages;
+        Ok(doctor)
+    }
+
+    fn checkpoint(mut self) -> DoctorCheckpoint {
+        DoctorCheckpoint {
+            page_size: self.page_size,
+            metas_hash: self.metas_hash(),
+            meta_page_id: self.meta_page_id.unwrap_or_default(),
+            max_page_id: self.max_page_id,
+            free_pages: self.free_pages,
+            freelist_synced: self.freelist_synced,
+            checked_pages: self.checked_pages,
+            overflow_pages: self.overflow_pages,
+            owners: self.owners,
+            stack: self.stack,
+            sequences: self.sequences,
+            findings: self.sink.0,
+            double_referenced: std::mem::take(&mut self.report.double_referenced),
+        }
+    }
+
+    // metas_hash is the hash of both meta pages, they are rewritten by each commit.
+    fn metas_hash(&mut self) -> u64 {
+        let data = self
+            .db
+            .try_read(0, 2 * self.page_size as usize)
+            .unwrap_or_default();
+        utils::fnv64(&data)
+    }
+}
+
+// DoctorProgress is the result of the resumable doctor, it's paused if the walk
+// isn't finished.
+#[derive(Debug, Clone)]
+pub enum DoctorProgress {
+    Paused(DoctorCheckpoint),
+    Done(Diagnosis),
+}
+
+// DoctorCheckpoint is the state of the paused doctor: the pages walked with their
+// owners, the pages to walk and the findings so far. It's encoded to be saved
+// between the runs.
+#[derive(Debug, Clone)]
+pub struct DoctorCheckpoint {
+    page_size: u64,
+    metas_hash: u64,
+    meta_page_id: u64,
+    max_page_id: u64,
+    free_pages: u64,
+    freelist_synced: bool,
+    checked_pages: u64,
+    overflow_pages: u64,
+    owners: PageOwners,
+    stack: Vec<(u64, Owner, KeyRange)>,
+    sequences: BTreeMap<Vec<Vec<u8>>, SequenceStats>,
+    findings: Vec<Finding>,
+    double_referenced: Vec<u64>,
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"ANCLADOC";
+const CHECKPOINT_VERSION: u64 = 2;
+
+impl DoctorCheckpoint {
+    // checked_pages is the number of the pages of the buckets walked so far.
+    pub fn checked_pages(&self) -> u64 {
+        self.checked_pages
+    }
+
+    // queued_pages is the number of the pages to walk next, their children are
+    // unknown until they are read.
+    pub fn queued_pages(&self) -> u64 {
+        self.stack.len() as u64
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    // encode encodes the checkpoint, the integers are little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = CheckpointWriter(CHECKPOINT_MAGIC.to_vec());
+        w.u64(CHECKPOINT_VERSION);
+        for value in [
+            self.page_size,
+            self.metas_hash,
+            self.meta_page_id,
+            self.max_page_id,
+            self.free_pages,
+            self.freelist_synced as u64,
+            self.checked_pages,
+            self.overflow_pages,
+        ] {
+            w.u64(value);
+        }
+        for bitmap in [
+            &self.owners.claimed,
+            &self.owners.reachable,
+            &self.owners.free,
+        ] {
+            w.u64(bitmap.words().len() as u64);
+            for &word in bitmap.words() {
+                w.u64(word);
+            }
+        }
+
+        w.u64(self.stack.len() as u64);
+        for (page_id, owner, range) in &self.stack {
+            w.u64(*page_id);
+            w.owner(owner);
+            w.option(
+                range
+                    .parent_page_id
+                    .map(u64::to_le_bytes)
+                    .as_ref()
+                    .map(|b| &b[..]),
+            );
+            w.option(range.lower.as_deref());
+            w.option(range.upper.as_deref());
+        }
+
+        w.u64(self.sequences.len() as u64);
+        for (bucket, stats) in &self.sequences {
+            w.path(bucket);
+            w.u64(stats.sequence);
+            w.u64(stats.keys);
+            w.u64(stats.ids);
+            w.u64(stats.max_id);
+        }
+
+        w.u64(self.findings.len() as u64);
+        for finding in &self.findings {
+            w.bytes(finding.severity.as_str().as_bytes());
+            w.bytes(finding.code.as_str().as_bytes());
+            w.option(
+                finding
+                    .page_id
+                    .map(u64::to_le_bytes)
+                    .as_ref()
+                    .map(|b| &b[..]),
+            );
+            match &finding.bucket {
+                Some(bucket) => {
+                    w.u64(1);
+                    w.path(bucket);
+                }
+                None => w.u64(0),
+            }
+            w.bytes(finding.message.as_bytes());
+            w.bytes(finding.suggestion.as_bytes());
+        }
+
+        w.u64(self.double_referenced.len() as u64);
+        for &page_id in &self.double_referenced {
+            w.u64(page_id);
+        }
+        w.0
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DatabaseError> {
+        let mut r = CheckpointReader { data, offset: 0 };
+        if r.take(CHECKPOINT_MAGIC.len())? != CHECKPOINT_MAGIC {
+            return Err(r.invalid("it isn't a checkpoint of the doctor"));
+        }
+        let version = r.u64()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(r.invalid(&format!("unsupported version {}", version)));
+        }
+        let page_size = r.u64()?;
+        let metas_hash = r.u64()?;
+        let meta_page_id = r.u64()?;
+        let max_page_id = r.u64()?;
+        let free_pages = r.u64()?;
+        let freelist_synced = r.u64()? != 0;
+        let checked_pages = r.u64()?;
+        let overflow_pages = r.u64()?;
+
+        let mut bitmaps = Vec::new();
+        for _ in 0..3 {
+            let words = (0..r.count(8)?)
+                .map(|_| r.u64())
+                .collect::<Result<Vec<_>, _>>()?;
+            bitmaps.push(PgidBitmap::from_words(words));
+        }
+        let [claimed, reachable, free] = <[PgidBitmap; 3]>::try_from(bitmaps).unwrap();
+        let owners = PageOwners {
+            claimed,
+            reachable,
+            free,
+        };
+
+        let mut stack = Vec::new();
+        for _ in 0..r.count(40)? {
+            let page_id = r.u64()?;
+            let owner = r.owner()?;
+            let parent_page_id = r.option()?.map(|b| utils::read_value::<u64>(&b, 0));
+            let range = KeyRange {
+                parent_page_id,
+                lower: r.option()?,
+                upper: r.option()?,
+            };
+            stack.push((page_id, owner, range));
+        }
+
+        let mut sequences = BTreeMap::new();
+        for _ in 0..r.count(40)? {
+            let bucket = r.path()?;
+            let stats = SequenceStats {
+                sequence: r.u64()?,
+                keys: r.u64()?,
+                ids: r.u64()?,
+                max_id: r.u64()?,
+            };
+            sequences.insert(bucket, stats);
+        }
+
+        let mut findings = Vec::new();
+        for _ in 0..r.count(48)? {
+            let severity = r.string()?.parse()?;
+            let code = r.string()?.parse()?;
+            let page_id = r.option()?.map(|b| utils::read_value::<u64>(&b, 0));
+            let bucket = match r.u64()? {
+                0 => None,
+                _ => Some(r.path()?),
+            };
+            findings.push(Finding {
+                severity,
+                code,
+                page_id,
+                bucket,
+                message: r.string()?,
+                suggestion: r.string()?,
+            });
+        }
+
+        let double_referenced = (0..r.count(8)?)
+            .map(|_| r.u64())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DoctorCheckpoint {
+            page_size,
+            metas_hash,
+            meta_page_id,
+            max_page_id,
+            free_pages,
+            freelist_synced,
+            checked_pages,
+            overflow_pages,
+            owners,
+            stack,
+            sequences,
+            findings,
+            double_referenced,
+        })
+    }
+}
+
+struct CheckpointWriter(Vec<u8>);
+
+impl CheckpointWriter {
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u64(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    // option writes a flag before the bytes, 0 for None.
+    fn option(&mut self, value: Option<&[u8]>) {
+        match value {
+            Some(value) => {
+                self.u64(1);
+                self.bytes(value);
+            }
+            None => self.u64(0),
+        }
+    }
+
+    fn path(&mut self, path: &[Vec<u8>]) {
+        self.u64(path.len() as u64);
+        for name in path {
+            self.bytes(name);
+        }
+    }
+
+    fn owner(&mut self, owner: &Owner) {
+        match owner {
+            Owner::Meta => self.u64(0),
+            Owner::Freelist => self.u64(1),
+            Owner::Free => self.u64(2),
+            Owner::Bucket(path) => {
+                self.u64(3);
+                self.path(path);
+            }
+        }
+    }
+}
+
+struct CheckpointReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl CheckpointReader<'_> {
+    fn invalid(&self, reason: &str) -> DatabaseError {
+        DatabaseError::IncompatibleValue {
+            reason: format!("invalid checkpoint at offset {}: {}", self.offset, reason),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], DatabaseError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| self.invalid("it's truncated"))?;
+        let data = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(data)
+    }
+
+    fn u64(&mut self) -> Result<u64, DatabaseError> {
+        self.take(8).map(|data| utils::read_value::<u64>(data, 0))
+    }
+
+    // count reads the number of the following items, each of them takes at least
+    // min_size bytes, so the count can't exceed the rest of the data.
+    fn count(&mut self, min_size: usize) -> Result<usize, DatabaseError> {
+        let count = self.u64()?;
+        if count > ((self.data.len() - self.offset) / min_size) as u64 {
+            return Err(self.invalid(&format!("the count {} exceeds the data", count)));
+        }
+        Ok(count as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DatabaseError> {
+        let len = self.count(1)?;
+        self.take(len).map(<[u8]>::to_vec)
+    }
+
+    fn string(&mut self) -> Result<String, DatabaseError> {
+        String::from_utf8(self.bytes()?).map_err(|_| self.invalid("the string isn't UTF-8"))
+    }
+
+    fn option(&mut self) -> Result<Option<Vec<u8>>, DatabaseError> {
+        match self.u64()? {
+            0 => Ok(None),
+            _ => self.bytes().map(Some),
+        }
+    }
+
+    fn path(&mut self) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        (0..self.count(8)?).map(|_| self.bytes()).collect()
+    }
+
+    fn owner(&mut self) -> Result<Owner, DatabaseError> {
+        match self.u64()? {
+            0 => Ok(Owner::Meta),
+            1 => Ok(Owner::Freelist),
+            2 => Ok(Owner::Free),
+            3 => self.path().map(Owner::Bucket),
+            tag => Err(self.invalid(&format!("unknown owner tag {}", tag))),
+        }
+    }
+}
+
+impl DB {
+    // doctor checks the meta pages, the freelist and the pages of all buckets, and
+    // reports the problems ordered by severity. It doesn't panic on corrupted
+    // databases, unlike other methods.
+    pub fn doctor(db: Rc<RefCell<DB>>) -> Diagnosis {
+        DB::diagnose(db, CollectAll::default()).0
+    }
+
+    // doctor_fail_fast checks the database like doctor, but it stops at the first
+    // critical finding, the diagnosis isn't complete if it stops.
+    pub fn doctor_fail_fast(db: Rc<RefCell<DB>>) -> Diagnosis {
+        DB::diagnose(db, FailFast::default()).0
+    }
+
+    // unreachable_pages returns the pages which are unreachable or used more than
+    // once, they are found by walking the pages like the doctor. The walk runs on
+    // the calling thread: the pages are read through the page source of the DB,
+    // which isn't Send, and the children of a page are only known once it's read,
+    // so there is no parallelism to configure.
+    pub fn unreachable_pages(db: Rc<RefCell<DB>>) -> PageReport {
+        DB::diagnose(db, CollectAll::default()).1
+    }
+
+    // doctor_resume checks the database like doctor, but it can be paused and
+    // resumed later, e.g. to check a huge database in the limited windows. The walk
+    // starts from the checkpoint if it's given, and it's paused before a page once
+    // pause returns true. It fails if the database is changed since the checkpoint.
+    pub fn doctor_resume(
+        db: Rc<RefCell<DB>>,
+        checkpoint: Option<DoctorCheckpoint>,
+        mut pause: impl FnMut() -> bool,
+    ) -> Result<DoctorProgress, DatabaseError> {
+        let mut db = db.borrow_mut();
+        let mut doctor = match checkpoint {
+            Some(checkpoint) => Doctor::resume(&mut db, checkpoint)?,
+            None => {
+                let mut doctor = Doctor::new(&mut db, CollectAll::default());
+                if !doctor.check_start() {
+                    return Ok(DoctorProgress::Done(doctor.finish().0));
+                }
+                doctor
+            }
+        };
+        if !doctor.check_tree(&mut pause) {
+            return Ok(DoctorProgress::Paused(doctor.checkpoint()));
+        }
+        doctor.check_end();
+        Ok(DoctorProgress::Done(doctor.finish().0))
+    }
+
+    fn diagnose<S: FindingSink>(db: Rc<RefCell<DB>>, sink: S) -> (Diagnosis, PageReport) {
+        let mut db = db.borrow_mut();
+        let mut doctor = Doctor::new(&mut db, sink);
+        if doctor.check_start() {
+            doctor.check_tree(&mut || false);
+            doctor.check_end();
+        }
+        doctor.finish()
+    }
+}
+
+// inline_keys returns the keys of the key-value pairs of the inline page, the
+// elements out of the page are skipped.
+fn inline_keys(data: &[u8]) -> Vec<&[u8]> {
+    let Ok(page) = bolt::Page::try_from(data) else {
+        return Vec::new();
+    };
+    (0..page.count as usize)
+        .filter_map(|i| {
+            let start = PAGE_HEADER_SIZE + i * 16;
+            let element = bolt::LeafPageElement::try_from(data.get(start..start + 16)?).ok()?;
+            let key_start = start + element.pos as usize;
+            if element.flags == 0x01 {
+                return None;
+            }
+            data.get(key_start..key_start + element.ksize as usize)
+        })
+        .collect()
+}
+
+// format_ranges formats the ordered ids as continuous ranges, e.g. `2-4, 7`.
+fn format_ranges(ids: &[u64]) -> String {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &id in ids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == id => *end = id,
+            _ => ranges.push((id, id)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{bucket_header, database, freelist, leaf, metas, open};
+
+    const PAGE_SIZE: usize = 4096;
+
+    fn diagnose(items: &[(u32, &[u8], &[u8])]) -> Diagnosis {
+        let root = leaf(PAGE_SIZE, 3, &[(0x01, b"b", &bucket_header(4))]);
+        diagnose_pages(vec![root, leaf(PAGE_SIZE, 4, items)])
+    }
+
+    // diagnose_pages builds the database with the pages from page 3, which is the
+    // root page of the root bucket, and runs the doctor on it.
+    fn diagnose_pages(pages: Vec<Vec<u8>>) -> Diagnosis {
+        let mut data = metas(PAGE_SIZE, 3 + pages.len() as u64);
+        data.extend(freelist(PAGE_SIZE, &[]));
+        data.extend(pages.into_iter().flatten());
+        DB::doctor(open(data))
+    }
+
+    fn codes(diagnosis: &Diagnosis) -> Vec<FindingCode> {
+        diagnosis.findings.iter().map(|f| f.code).collect()
+    }
+
+    #[test]
+    fn doctor_healthy() {
+        let diagnosis = diagnose(&[(0, b"k1", b"v1"), (0, b"k2", b"v2")]);
+        assert_eq!(codes(&diagnosis), vec![]);
+    }
+
+    #[test]
+    fn doctor_page_size() {
+        for page_size in [8192, 16384] {
+            let diagnosis = DB::doctor(open(database(page_size)));
+            assert!(diagnosis.findings.is_empty(), "{:?}", diagnosis.findings);
+            assert_eq!(diagnosis.reachable_pages, 2);
+
+            // The offsets of the advices are in the pages of the database.
+            let mut data = database(page_size);
+            let page = 4 * page_size;
+            data[page + 16..page + 32]
+                .copy_from_slice(&[0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0]);
+            let diagnosis = DB::doctor(open(data));
+            assert_eq!(diagnosis.findings.len(), 1);
+            assert_eq!(diagnosis.findings[0].code, FindingCode::KeyEmpty);
+            assert!(diagnosis.findings[0]
+                .suggestion
+                .ends_with(&format!("`dump --offset {}`", page)));
+        }
+    }
+
+    #[test]
+    fn doctor_empty_key() {
+        let diagnosis = diagnose(&[(0, b"", b"v0"), (0, b"k1", b"v1")]);
+        assert_eq!(codes(&diagnosis), vec![FindingCode::KeyEmpty]);
+        let finding = &diagnosis.findings[0];
+        assert_eq!(finding.severity, Severity::Warning);
+        assert_eq!(finding.page_id, Some(4));
+        assert_eq!(finding.bucket, Some(vec![b"b".to_vec()]));
+    }
+
+    #[test]
+    fn doctor_empty_value() {
+        // bolt allows the empty values.
+        let diagnosis = diagnose(&[(0, b"k1", b""), (0, b"k2", b"")]);
+        assert_eq!(codes(&diagnosis), vec![]);
+    }
+
+    #[test]
+    fn doctor_duplicate_key() {
+        let diagnosis = diagnose(&[(0, b"k1", b"v1"), (0, b"k1", b"v2"), (0, b"k2", b"v3")]);
+        assert_eq!(codes(&diagnosis), vec![FindingCode::KeyDuplicate]);
+        let finding = &diagnosis.findings[0];
+        assert_eq!(finding.severity, Severity::Critical);
+        assert_eq!(finding.page_id, Some(4));
+        assert!(finding.message.contains("k1"), "{}", finding.message);
+    }
+
+    #[test]
+    fn doctor_double_referenced() {
+        // The bucket b is walked first as the pages are walked from a stack.
+        let root = leaf(
+            PAGE_SIZE,
+            3,
+            &[
+                (0x01, b"a", &bucket_header(4)),
+                (0x01, b"b", &bucket_header(4)),
+            ],
+        );
+        let diagnosis = diagnose_pages(vec![root, leaf(PAGE_SIZE, 4, &[(0, b"k1", b"v1")])]);
+        assert_eq!(codes(&diagnosis), vec![FindingCode::PageDoubleReferenced]);
+        assert_eq!(
+            diagnosis.findings[0].message,
+            "page 4 is used by both bucket b and bucket a"
+        );
+        assert_eq!(diagnosis.findings[0].bucket, Some(vec![b"a".to_vec()]));
+    }
+}
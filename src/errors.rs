@@ -4,4 +4,34 @@ use thiserror::Error;
 pub enum DatabaseError {
     #[error("data buffer is too small, expect {expect}, got {got}")]
     TooSmallData { expect: usize, got: usize },
+    #[error("invalid bucket identifier {id}")]
+    InvalidBucketIdentifier { id: String },
+    #[error("invalid bucket pattern {pattern}, the names must not be empty")]
+    InvalidBucketPattern { pattern: String },
+    #[error("the name {name} is not valid UTF-8")]
+    InvalidUtf8 { name: String },
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the database is locked by another process")]
+    Locked,
+    #[error("both meta pages are invalid")]
+    InvalidMeta,
+    #[error("unsupported version {found}, the supported version is {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("the page size {requested} mismatches the meta's page size {meta}")]
+    PageSizeMismatch { meta: u32, requested: u32 },
+    #[error("the database is corrupted: {reason}")]
+    Corrupted { reason: String },
+    #[error("unsupported database: {reason}")]
+    Unsupported { reason: String },
+    #[error("bucket {name} not found")]
+    BucketNotFound { name: String },
+    #[error("bucket {name} already exists")]
+    BucketExists { name: String },
+    #[error("key not found")]
+    KeyNotFound,
+    #[error("incompatible value: {reason}")]
+    IncompatibleValue { reason: String },
+    #[error("the key {path} conflicts between the merged databases")]
+    MergeConflict { path: String },
 }
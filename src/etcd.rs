@@ -0,0 +1,298 @@
+use crate::decode::{decode_revision, decode_u64, Revision};
+use crate::utils::hex;
+use crate::{DbItem, DB};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    rc::Rc,
+};
+
+// SnapshotHash is the result of checking the sha256 which `etcdctl snapshot save`
+// appends to the database.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SnapshotHash {
+    Valid(String),
+    Mismatch { expected: String, actual: String },
+    // the snapshot is copied from the data directory, which doesn't have the hash.
+    Missing,
+}
+
+// verify_snapshot_hash checks the sha256 of the snapshot, the size of the database
+// is a multiple of 512 bytes, so the snapshot has the hash if there are 32 more
+// bytes, the same as etcd.
+pub fn verify_snapshot_hash(path: &str) -> io::Result<SnapshotHash> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size % 512 != 32 {
+        return Ok(SnapshotHash::Missing);
+    }
+
+    let mut hasher = Sha256::new();
+    let mut remaining = size - 32;
+    let mut buf = vec![0u8; 1 << 20];
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        file.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    let mut expected = [0u8; 32];
+    file.read_exact(&mut expected)?;
+
+    let (expected, actual) = (hex(&expected), hex(&hasher.finalize()));
+    if expected != actual {
+        return Ok(SnapshotHash::Mismatch { expected, actual });
+    }
+    Ok(SnapshotHash::Valid(actual))
+}
+
+// EtcdMeta is the state of etcd which is stored in the database, the fields are
+// None if they are not found.
+#[derive(Debug, Clone, Default)]
+pub struct EtcdMeta {
+    // the index of the last raft entry which is applied.
+    pub consistent_index: Option<u64>,
+    pub term: Option<u64>,
+    // the latest revision, it's the last key of the `key` bucket.
+    pub revision: Option<Revision>,
+    pub compact_revision: Option<Revision>,
+}
+
+impl EtcdMeta {
+    pub fn read(db: Rc<RefCell<DB>>) -> EtcdMeta {
+        let mut meta = EtcdMeta::default();
+        if let Some(bucket) = DB::get_bucket(db.clone(), &[b"meta"]) {
+            meta.consistent_index = bucket
+                .get(b"consistent_index")
+                .and_then(|value| decode_u64(&value));
+            meta.term = bucket.get(b"term").and_then(|value| decode_u64(&value));
+            meta.compact_revision = bucket
+                .get(b"finishedCompactRev")
+                .and_then(|value| decode_revision(&value));
+        }
+        meta.revision = DB::get_bucket(db, &[b"key"])
+            .and_then(|bucket| bucket.last())
+            .and_then(|(key, _)| decode_revision(&key));
+        meta
+    }
+}
+
+// KeyValue is the key-value of etcd's mvcc, it's stored in the `key` bucket keyed by
+// the revision which it's modified at.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct KeyValue {
+    pub key: Vec<u8>,
+    pub create_revision: i64,
+    pub mod_revision: i64,
+    pub version: i64,
+    pub value: Vec<u8>,
+    pub lease: i64,
+}
+
+impl KeyValue {
+    // decode decodes the mvccpb.KeyValue protobuf.
+    pub fn decode(data: &[u8]) -> Option<KeyValue> {
+        let mut kv = KeyValue::default();
+        for field in ProtoFields::new(data) {
+            match field? {
+                (1, ProtoValue::Bytes(key)) => kv.key = key.to_vec(),
+                (2, ProtoValue::Varint(v)) => kv.create_revision = v as i64,
+                (3, ProtoValue::Varint(v)) => kv.mod_revision = v as i64,
+                (4, ProtoValue::Varint(v)) => kv.version = v as i64,
+                (5, ProtoValue::Bytes(value)) => kv.value = value.to_vec(),
+                (6, ProtoValue::Varint(v)) => kv.lease = v as i64,
+                _ => {}
+            }
+        }
+        Some(kv)
+    }
+}
+
+// latest_key_values returns the latest key-value of each key with the prefix from
+// the `key` bucket, ordered by the key. The deleted keys are excluded, the older
+// revisions are kept by etcd until they are compacted.
+pub fn latest_key_values(db: Rc<RefCell<DB>>, prefix: &[u8]) -> Vec<KeyValue> {
+    let Some(bucket) = DB::get_bucket(db, &[b"key"]) else {
+        return Vec::new();
+    };
+
+    let mut latest: BTreeMap<Vec<u8>, Option<KeyValue>> = BTreeMap::new();
+    for item in bucket.iter_items(Some(0)) {
+        let DbItem::KeyValue { key, value, .. } = item else {
+            continue;
+        };
+        let Some(kv) = KeyValue::decode(&value).filter(|kv| kv.key.starts_with(prefix)) else {
+            continue;
+        };
+        // The revisions are iterated in ascending order, so the later one wins.
+        let tombstone = decode_revision(&key).is_some_and(|revision| revision.tombstone);
+        latest.insert(kv.key.clone(), (!tombstone).then_some(kv));
+    }
+    latest.into_values().flatten().collect()
+}
+
+// Object is the identity of a resource which is decoded from the value.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Object {
+    pub api_version: String,
+    pub kind: String,
+    // it's None for the cluster-scoped resources.
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+// ObjectDecoder decodes the value of a schema, it returns None if the value isn't
+// in the schema.
+pub trait ObjectDecoder {
+    fn decode(&self, key: &[u8], value: &[u8]) -> Option<Object>;
+}
+
+// Decoders tries the registered decoders in order, the first decoded object is
+// returned. The Kubernetes decoder is registered by default.
+pub struct Decoders {
+    decoders: Vec<Box<dyn ObjectDecoder>>,
+}
+
+impl Default for Decoders {
+    fn default() -> Self {
+        Decoders {
+            decoders: vec![Box::new(KubernetesDecoder)],
+        }
+    }
+}
+
+impl Decoders {
+    pub fn register(&mut self, decoder: impl ObjectDecoder + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    pub fn decode(&self, key: &[u8], value: &[u8]) -> Option<Object> {
+        self.decoders
+            .iter()
+            .find_map(|decoder| decoder.decode(key, value))
+    }
+}
+
+// KubernetesDecoder decodes the protobuf objects of Kubernetes, which are stored
+// under `/registry/` in the runtime.Unknown envelope prefixed by `k8s\0`.
+pub struct KubernetesDecoder;
+
+impl ObjectDecoder for KubernetesDecoder {
+    fn decode(&self, key: &[u8], value: &[u8]) -> Option<Object> {
+        if !key.starts_with(b"/registry/") {
+            return None;
+        }
+        let unknown = value.strip_prefix(b"k8s\0")?;
+
+        let mut object = Object::default();
+        let mut raw: &[u8] = &[];
+        for field in ProtoFields::new(unknown) {
+            match field? {
+                (1, ProtoValue::Bytes(type_meta)) => {
+                    for field in ProtoFields::new(type_meta) {
+                        match field? {
+                            (1, ProtoValue::Bytes(v)) => object.api_version = utf8(v),
+                            (2, ProtoValue::Bytes(v)) => object.kind = utf8(v),
+                            _ => {}
+                        }
+                    }
+                }
+                (2, ProtoValue::Bytes(v)) => raw = v,
+                _ => {}
+            }
+        }
+
+        // The metadata is the first field of all objects.
+        for field in ProtoFields::new(raw) {
+            let (1, ProtoValue::Bytes(metadata)) = field? else {
+                continue;
+            };
+            for field in ProtoFields::new(metadata) {
+                match field? {
+                    (1, ProtoValue::Bytes(v)) => object.name = utf8(v),
+                    (3, ProtoValue::Bytes(v)) if !v.is_empty() => object.namespace = Some(utf8(v)),
+                    _ => {}
+                }
+            }
+            break;
+        }
+        Some(object)
+    }
+}
+
+fn utf8(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).into_owned()
+}
+
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    // the fixed 32 and 64 bits values, they are not used by the decoded messages.
+    Fixed,
+}
+
+// ProtoFields iterates the fields of a protobuf message, it yields None and stops
+// if the message is malformed.
+struct ProtoFields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ProtoFields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ProtoFields { data }
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for (i, &b) in self.data.iter().enumerate().take(10) {
+            value |= ((b & 0x7F) as u64) << (i * 7);
+            if b & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.data.len() {
+            return None;
+        }
+        let (value, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(value)
+    }
+
+    fn field(&mut self) -> Option<(u64, ProtoValue<'a>)> {
+        let tag = self.varint()?;
+        let value = match tag & 0x07 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => self.take(8).map(|_| ProtoValue::Fixed)?,
+            2 => {
+                let len = usize::try_from(self.varint()?).ok()?;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => self.take(4).map(|_| ProtoValue::Fixed)?,
+            _ => return None,
+        };
+        Some((tag >> 3, value))
+    }
+}
+
+impl<'a> Iterator for ProtoFields<'a> {
+    type Item = Option<(u64, ProtoValue<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_none() {
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
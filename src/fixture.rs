@@ -0,0 +1,102 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{bolt, AnclaOptions, DB};
+
+// The databases which the tests are run on, they're built with the encoders of the
+// write path so they're the same as the databases written by bolt.
+
+// page returns the page of the page size with the header encoded.
+pub(crate) fn page(page_size: usize, page_id: u64, flags: bolt::PageFlag, count: u16) -> Vec<u8> {
+    let mut data = vec![0u8; page_size];
+    bolt::Page {
+        id: bolt::Pgid(page_id),
+        flags,
+        count,
+        overflow: 0,
+    }
+    .encode(&mut data);
+    data
+}
+
+// leaf returns the leaf page with the items, each of them is the flags, key and value.
+pub(crate) fn leaf(page_size: usize, page_id: u64, items: &[(u32, &[u8], &[u8])]) -> Vec<u8> {
+    let mut data = page(
+        page_size,
+        page_id,
+        bolt::PageFlag::LeafPageFlag,
+        items.len() as u16,
+    );
+    let mut offset = 16 + 16 * items.len();
+    for (i, (flags, key, value)) in items.iter().enumerate() {
+        let element = 16 + 16 * i;
+        bolt::LeafPageElement {
+            flags: *flags,
+            pos: (offset - element) as u32,
+            ksize: key.len() as u32,
+            vsize: value.len() as u32,
+        }
+        .encode(&mut data[element..element + 16]);
+        data[offset..offset + key.len()].copy_from_slice(key);
+        offset += key.len();
+        data[offset..offset + value.len()].copy_from_slice(value);
+        offset += value.len();
+    }
+    data
+}
+
+// freelist returns the freelist page at page 2 with the free pages.
+pub(crate) fn freelist(page_size: usize, free: &[u64]) -> Vec<u8> {
+    let mut data = page(
+        page_size,
+        2,
+        bolt::PageFlag::FreelistPageFlag,
+        free.len() as u16,
+    );
+    for (i, id) in free.iter().enumerate() {
+        data[16 + 8 * i..24 + 8 * i].copy_from_slice(&id.to_le_bytes());
+    }
+    data
+}
+
+// bucket_header returns the value of the bucket whose root page is root.
+pub(crate) fn bucket_header(root: u64) -> Vec<u8> {
+    let mut header = root.to_le_bytes().to_vec();
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header
+}
+
+// metas returns the two meta pages whose root is page 3 and freelist is page 2.
+pub(crate) fn metas(page_size: usize, max_pgid: u64) -> Vec<u8> {
+    let mut meta = bolt::Meta::new(page_size as u32, 0);
+    meta.max_pgid = bolt::Pgid(max_pgid);
+    let mut data = meta.encode(page_size);
+    meta.txid = 1;
+    data.extend(meta.encode(page_size));
+    data
+}
+
+// database builds the database of the page size with the bucket `b` at page 4,
+// which holds `key`, the page 5 is free.
+pub(crate) fn database(page_size: usize) -> Vec<u8> {
+    let mut data = metas(page_size, 6);
+    data.extend(freelist(page_size, &[5]));
+    data.extend(leaf(page_size, 3, &[(0x01, b"b", &bucket_header(4))]));
+    data.extend(leaf(page_size, 4, &[(0, b"key", b"value")]));
+    data.extend(vec![0u8; page_size]);
+    data
+}
+
+// unsync_freelist sets the freelist of the metas to u64::MAX, which is how bolt
+// marks the freelist isn't persisted (NoFreelistSync).
+pub(crate) fn unsync_freelist(data: &mut [u8], page_size: usize) {
+    for page_id in 0..2 {
+        let page = &mut data[page_id * page_size..(page_id + 1) * page_size];
+        let mut meta = bolt::Meta::try_from(&page[..]).unwrap();
+        meta.freelist_pgid = bolt::Pgid(u64::MAX);
+        page.copy_from_slice(&meta.encode(page_size));
+    }
+}
+
+pub(crate) fn open(data: Vec<u8>) -> Rc<RefCell<DB>> {
+    DB::open_from_bytes(AnclaOptions::builder().build(), data)
+}
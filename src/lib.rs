@@ -1,9 +1,50 @@
-#[macro_use]
-extern crate prettytable;
-
+#[cfg(feature = "async")]
+pub mod aio;
+mod bitmap;
 mod bolt;
+mod bucket_id;
+mod bucket_path;
+mod bytes;
+mod cache;
+mod cancel;
 mod db;
+pub mod decode;
+#[cfg(feature = "disk-cache")]
+mod disk_cache;
+mod doctor;
 mod errors;
+#[cfg(feature = "etcd")]
+pub mod etcd;
+#[cfg(test)]
+mod fixture;
+pub mod metrics;
+pub mod prelude;
+mod source;
 mod utils;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "write")]
+mod write;
 
-pub use db::{AnclaOptions, Bucket, PageInfo, DB};
+pub use bitmap::PgidBitmap;
+pub use bucket_id::BucketIdentifier;
+pub use bucket_path::BucketPattern;
+pub use bytes::Bytes;
+pub use cache::clear_page_cache;
+pub use cancel::CancellationToken;
+pub use db::{
+    AnclaOptions, Bucket, BucketInfo, BucketItemRevIterator, ChangedPage, DbItem, DbItemIterator,
+    ElementKind, Endian, FreePage, Info, KeyCountEstimate, KeyLocation, Location, PageElement,
+    PageFilter, PageInfo, PageLayout, PagePath, PageRegion, PageStep, PageType, PageTypeCounts,
+    ReadLimits, ReadOptions, ReadTx, TreeStats, TxAnalysis, DB,
+};
+pub use doctor::{
+    CheckReport, Diagnosis, DoctorCheckpoint, DoctorProgress, Finding, FindingCode, PageReport,
+    Severity,
+};
+pub use errors::DatabaseError;
+#[cfg(feature = "http")]
+pub use source::HttpSource;
+pub use source::{ArchiveSource, FileSource, MemorySource, PageSource, ReaderSource};
+#[cfg(feature = "write")]
+pub use write::{ConflictPolicy, WriteForecast, WriteTx};
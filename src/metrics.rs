@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Metrics is the process-wide IO counters of all opened databases, they can be
+// exported in the prometheus text format.
+pub struct Metrics {
+    pages_read: AtomicU64,
+    bytes_read: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct MetricsSnapshot {
+    pub pages_read: u64,
+    pub bytes_read: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+static METRICS: Metrics = Metrics {
+    pages_read: AtomicU64::new(0),
+    bytes_read: AtomicU64::new(0),
+    cache_hits: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+};
+
+// metrics returns the process-wide metrics.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub(crate) fn record_page_read(&self) {
+        self.pages_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_bytes_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_cache(&self, hit: bool) {
+        if hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            pages_read: self.pages_read.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    // render_prometheus renders the metrics in the prometheus text exposition format.
+    // It's printed by `anclalet --metrics-dump`, there is no serve mode to expose it
+    // at `/metrics`.
+    pub fn render_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let counters = [
+            (
+                "ancla_pages_read_total",
+                "The number of pages read from the page source.",
+                snapshot.pages_read,
+            ),
+            (
+                "ancla_bytes_read_total",
+                "The number of bytes read from the page source.",
+                snapshot.bytes_read,
+            ),
+            (
+                "ancla_page_cache_hits_total",
+                "The number of page reads served by the page cache.",
+                snapshot.cache_hits,
+            ),
+            (
+                "ancla_page_cache_misses_total",
+                "The number of page reads missed the page cache.",
+                snapshot.cache_misses,
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, help, value) in counters {
+            output.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            ));
+        }
+        output
+    }
+}
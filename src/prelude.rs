@@ -0,0 +1,6 @@
+// The prelude is the stable surface of the library, `use ancla::prelude::*` imports
+// the types which most readers need. These types are only changed in a breaking way
+// with a new major version (or minor version before 1.0), the other public items
+// may still change between minor versions.
+pub use crate::db::{AnclaOptions, Bucket, DbItem, PageInfo, DB};
+pub use crate::errors::DatabaseError;
@@ -0,0 +1,334 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    sync::Arc,
+};
+
+// PageSource is the storage abstraction which the database pages are read from,
+// the reader only requires random access to the byte range of the pages, so the
+// database doesn't need to be a local file.
+pub trait PageSource {
+    // read_at fills the whole buffer with the bytes start at offset, it's an
+    // error if the source doesn't have enough bytes.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+// FileSource reads the pages from a local file.
+pub struct FileSource {
+    file: File,
+}
+
+impl FileSource {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(FileSource {
+            file: File::open(path)?,
+        })
+    }
+
+    // is_write_locked returns true if the file is locked exclusively, i.e. it's
+    // opened by a bolt writer (e.g. a running etcd), the pages may be changed
+    // while they are read. The readers of bolt only hold the shared lock.
+    #[cfg(feature = "write")]
+    pub fn is_write_locked(path: &str) -> io::Result<bool> {
+        use fs2::FileExt;
+
+        let file = File::open(path)?;
+        // The trait methods are called explicitly, since they are shadowed by the
+        // lock methods of std::fs::File in the newer toolchains.
+        match FileExt::try_lock_shared(&file) {
+            Ok(()) => {
+                FileExt::unlock(&file)?;
+                Ok(false)
+            }
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl PageSource for FileSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(io::SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)
+    }
+}
+
+// ArchiveSource reads the pages from a member of a tar or zip archive, e.g. a
+// snapshot tarball, without extracting it. The member must be stored without
+// compression so it's bytes are contiguous in the archive.
+pub struct ArchiveSource {
+    file: FileSource,
+    // the offset of the member's data in the archive.
+    offset: u64,
+    length: u64,
+}
+
+impl ArchiveSource {
+    pub fn open(path: &str, member: &str) -> io::Result<Self> {
+        let mut file = FileSource::open(path)?;
+        let size = file.file.metadata()?.len();
+        let mut magic = [0u8; 4];
+        file.read_at(0, &mut magic)?;
+        let (offset, length) = if magic == *b"PK\x03\x04" {
+            find_zip_member(&mut file, size, member)?
+        } else {
+            find_tar_member(&mut file, size, member)?
+        };
+
+        Ok(ArchiveSource {
+            file,
+            offset,
+            length,
+        })
+    }
+}
+
+impl PageSource for ArchiveSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if offset.saturating_add(buf.len() as u64) > self.length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.file.read_at(self.offset + offset, buf)
+    }
+}
+
+fn member_not_found(member: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("member {} not found in the archive", member),
+    )
+}
+
+// is_member reports whether the name of the entry is the member, the leading `./`
+// is ignored.
+fn is_member(name: &[u8], member: &str) -> bool {
+    let name = name.strip_prefix(b"./").unwrap_or(name);
+    name == member.strip_prefix("./").unwrap_or(member).as_bytes()
+}
+
+// find_tar_member returns the offset and length of the member's data, the entries
+// are 512 bytes headers followed by the data which is padded to 512 bytes.
+fn find_tar_member(file: &mut FileSource, size: u64, member: &str) -> io::Result<(u64, u64)> {
+    let mut offset = 0;
+    let mut long_name: Option<Vec<u8>> = None;
+    while offset + 512 <= size {
+        let mut header = [0u8; 512];
+        file.read_at(offset, &mut header)?;
+        // The archive ends with zero blocks.
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let length = tar_size(&header[124..136])?;
+        let data = offset + 512;
+        // The length is checked before the long name is allocated with it.
+        if length > size - data {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the tar entry exceeds the archive",
+            ));
+        }
+        offset = data + length.div_ceil(512) * 512;
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let field = |range: std::ops::Range<usize>| {
+                    let field = &header[range];
+                    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+                    field[..end].to_vec()
+                };
+                let (prefix, name) = (field(345..500), field(0..100));
+                if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                    [prefix, name].join(&b'/')
+                } else {
+                    name
+                }
+            }
+        };
+        match header[156] {
+            // The GNU long name is the data of the entry before the member.
+            b'L' => {
+                let mut name = vec![0u8; length as usize];
+                file.read_at(data, &mut name)?;
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                name.truncate(end);
+                long_name = Some(name);
+            }
+            b'0' | 0 if is_member(&name, member) => return Ok((data, length)),
+            _ => {}
+        }
+    }
+    Err(member_not_found(member))
+}
+
+// tar_size parses the octal size, or the base-256 size of GNU tar if the high bit
+// is set.
+fn tar_size(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold(0, |size, &b| (size << 8) | b as u64));
+    }
+
+    let digits: Vec<u8> = field
+        .iter()
+        .copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| (b'0'..=b'7').contains(&b))
+        .collect();
+    let digits = std::str::from_utf8(&digits).unwrap_or_default();
+    u64::from_str_radix(if digits.is_empty() { "0" } else { digits }, 8).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid tar size: {}", e),
+        )
+    })
+}
+
+// find_zip_member returns the offset and length of the member's data, the entries
+// are found in the central directory at the end of the archive.
+fn find_zip_member(file: &mut FileSource, size: u64, member: &str) -> io::Result<(u64, u64)> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    let u16_at = |data: &[u8], i: usize| u16::from_le_bytes([data[i], data[i + 1]]) as u64;
+    let u32_at = |data: &[u8], i: usize| {
+        u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as u64
+    };
+
+    // The end of central directory record is 22 bytes with a comment up to 64KiB.
+    let tail_len = size.min(22 + 0xFFFF);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.read_at(size - tail_len, &mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| invalid("the end of central directory isn't found"))?;
+    let entries = u16_at(&tail, eocd + 10);
+    let mut offset = u32_at(&tail, eocd + 16);
+    if entries == 0xFFFF || offset == 0xFFFF_FFFF {
+        return Err(invalid("zip64 archives are not supported"));
+    }
+
+    for _ in 0..entries {
+        let mut entry = [0u8; 46];
+        file.read_at(offset, &mut entry)?;
+        if !entry.starts_with(b"PK\x01\x02") {
+            return Err(invalid("invalid central directory entry"));
+        }
+        let name_len = u16_at(&entry, 28);
+        let mut name = vec![0u8; name_len as usize];
+        file.read_at(offset + 46, &mut name)?;
+        offset += 46 + name_len + u16_at(&entry, 30) + u16_at(&entry, 32);
+        if !is_member(&name, member) {
+            continue;
+        }
+
+        if u16_at(&entry, 10) != 0 {
+            return Err(invalid(
+                "the member is compressed, only stored members can be read",
+            ));
+        }
+        let length = u32_at(&entry, 20);
+        let header_offset = u32_at(&entry, 42);
+        if length == 0xFFFF_FFFF || header_offset == 0xFFFF_FFFF {
+            return Err(invalid("zip64 archives are not supported"));
+        }
+        // The extra field of the local header may differ from the central one.
+        let mut local = [0u8; 30];
+        file.read_at(header_offset, &mut local)?;
+        let data = header_offset + 30 + u16_at(&local, 26) + u16_at(&local, 28);
+        return Ok((data, length));
+    }
+    Err(member_not_found(member))
+}
+
+// MemorySource reads the pages from the database which is already loaded into memory.
+pub struct MemorySource {
+    data: Arc<Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new(data: Vec<u8>) -> Self {
+        MemorySource::shared(Arc::new(data))
+    }
+
+    // shared reads the pages from the data shared with other sources, so the
+    // databases opened on the same data don't copy it.
+    pub fn shared(data: Arc<Vec<u8>>) -> Self {
+        MemorySource { data }
+    }
+}
+
+impl PageSource for MemorySource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let start = usize::try_from(offset).map_err(|_| io::ErrorKind::UnexpectedEof)?;
+        let data = start
+            .checked_add(buf.len())
+            .and_then(|end| self.data.get(start..end))
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+// ReaderSource reads the pages from any reader which supports seek.
+pub struct ReaderSource<R: Read + Seek> {
+    reader: R,
+}
+
+impl<R: Read + Seek> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderSource { reader }
+    }
+}
+
+impl<R: Read + Seek> PageSource for ReaderSource<R> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.seek(io::SeekFrom::Start(offset))?;
+        self.reader.read_exact(buf)
+    }
+}
+
+// HttpSource reads the pages with ranged HTTP GETs, so only the pages which are
+// needed will be downloaded. An object in S3 (or any compatible object store) can
+// be read with a public or presigned url.
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    url: String,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    pub fn new(url: &str) -> Self {
+        HttpSource {
+            url: url.to_string(),
+            agent: ureq::Agent::new_with_defaults(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl PageSource for HttpSource {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let range = format!("bytes={}-{}", offset, offset + buf.len() as u64 - 1);
+        let response = self
+            .agent
+            .get(&self.url)
+            .header("Range", &range)
+            .call()
+            .map_err(io::Error::other)?;
+        // The server must honor the range, otherwise we would read the bytes from
+        // the beginning of the file.
+        if response.status() != 206 {
+            return Err(io::Error::other(format!(
+                "range request {} of {} is not supported, got status {}",
+                range,
+                self.url,
+                response.status()
+            )));
+        }
+        response.into_body().into_reader().read_exact(buf)
+    }
+}
@@ -1,3 +1,5 @@
+use fnv_rs::{Fnv64, FnvHasher};
+
 trait ByteReadMarker {}
 
 impl ByteReadMarker for u16 {}
@@ -12,3 +14,14 @@ pub(crate) fn read_value<T: ByteReadMarker>(data: &[u8], offset: usize) -> T {
         offset_ptr.read_unaligned()
     }
 }
+
+// fnv64 returns the FNV-1a hash of the data, which is the same algorithm that
+// bolt uses to checksum the meta page.
+pub(crate) fn fnv64(data: &[u8]) -> u64 {
+    u64::from_be_bytes(Fnv64::hash(data).as_bytes().try_into().unwrap())
+}
+
+// hex renders the bytes as lowercase hex without separators.
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,54 @@
+use crate::{AnclaOptions, Bucket, Bytes, DB};
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::prelude::*;
+
+// Inspector is the entry of the browser-based bolt inspector, the database is
+// loaded into memory because there is no file system in the browser.
+#[wasm_bindgen]
+pub struct Inspector {
+    db: Rc<RefCell<DB>>,
+}
+
+#[wasm_bindgen]
+impl Inspector {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>) -> Inspector {
+        Inspector {
+            db: DB::open_from_bytes(AnclaOptions::builder().build(), data),
+        }
+    }
+
+    // info returns the meta information of the database as a JSON string.
+    pub fn info(&self) -> String {
+        // The information is a plain struct, serializing it never fails.
+        serde_json::to_string(&DB::info(self.db.clone())).unwrap()
+    }
+
+    // listBuckets returns the path of all buckets, the names in the path are
+    // joined by `/`.
+    #[wasm_bindgen(js_name = listBuckets)]
+    pub fn list_buckets(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for bucket in DB::iter_buckets(self.db.clone()) {
+            list_buckets_inner(&bucket, String::new(), &mut paths);
+        }
+        paths
+    }
+
+    // get returns the value of the key in the bucket, the bucket is specified by
+    // it's path which is joined by `/`.
+    pub fn get(&self, bucket: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let path: Vec<&[u8]> = bucket.split('/').map(str::as_bytes).collect();
+        DB::get_bucket(self.db.clone(), &path)?
+            .get(key)
+            .map(Bytes::into_vec)
+    }
+}
+
+fn list_buckets_inner(bucket: &Bucket, prefix: String, paths: &mut Vec<String>) {
+    let path = prefix + &String::from_utf8_lossy(&bucket.name);
+    for child_bucket in bucket.iter_buckets() {
+        list_buckets_inner(&child_bucket, path.clone() + "/", paths);
+    }
+    paths.push(path);
+}
@@ -0,0 +1,1349 @@
+use crate::bolt::{self, Pgid, PAGE_HEADER_SIZE};
+use crate::db::{Bucket, DbItem, DB};
+use crate::errors::DatabaseError;
+use crate::utils;
+use fs2::FileExt;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    rc::Rc,
+    str::FromStr,
+};
+
+// MAX_PAGE_ELEMENTS is the max element count of a page, which is stored as u16.
+const MAX_PAGE_ELEMENTS: usize = 0xFFFF;
+
+// LeafItem is an element of a leaf page, the value of a bucket element is the
+// bucket header which may be followed by the inline page.
+#[derive(Debug, Clone)]
+struct LeafItem {
+    flags: u32,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct BranchItem {
+    key: Vec<u8>,
+    pgid: u64,
+}
+
+// ConflictPolicy decides which value is kept if a key exists in both of the merged
+// databases with different values, or it's a bucket in one and a value in another.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    Ours,
+    Theirs,
+    #[default]
+    Fail,
+}
+
+impl ConflictPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictPolicy::Ours => "ours",
+            ConflictPolicy::Theirs => "theirs",
+            ConflictPolicy::Fail => "fail",
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ours" => Ok(ConflictPolicy::Ours),
+            "theirs" => Ok(ConflictPolicy::Theirs),
+            "fail" => Ok(ConflictPolicy::Fail),
+            _ => Err(format!("unknown conflict policy: {}", s)),
+        }
+    }
+}
+
+// WriteForecast is what the commit of a transaction would change, see
+// WriteTx::forecast. The pages freed include the previous freelist and the pages
+// written include the new one.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct WriteForecast {
+    pub page_size: u64,
+    pub pages_freed: u64,
+    pub pages_written: u64,
+    pub file_size: u64,
+    // the file is grown if the pages are allocated beyond the end of it, it's never
+    // truncated.
+    pub new_file_size: u64,
+}
+
+impl WriteForecast {
+    // reclaimed_bytes is the size of the pages which become free after the commit,
+    // it's negative if more pages are written than freed.
+    pub fn reclaimed_bytes(&self) -> i64 {
+        (self.pages_freed as i64 - self.pages_written as i64) * self.page_size as i64
+    }
+}
+
+// MergeItem is a key of the merged bucket from one of the databases.
+enum MergeItem {
+    Value(Vec<u8>),
+    Bucket(Bucket),
+}
+
+enum Op<'a> {
+    Put(&'a [u8]),
+    Delete,
+    CreateBucket,
+    DeleteBucket,
+    CompactBucket,
+}
+
+// WriteTx modifies a closed database file offline, the pages are written in the
+// copy-on-write way as bolt does: the modified pages are written to free pages,
+// and the meta which isn't current is overwritten by commit. So the database is
+// still valid with the previous meta if the commit is interrupted.
+//
+// The file is locked exclusively during the transaction, it fails if the database
+// is opened by another process.
+pub struct WriteTx {
+    file: File,
+    page_size: usize,
+    meta: bolt::Meta,
+    // the free pages which can be allocated by this transaction.
+    free: BTreeSet<u64>,
+    // the pages freed by this transaction, they are still used by the previous
+    // meta, so they can't be allocated before commit.
+    freed: BTreeSet<u64>,
+    // the pages written by this transaction, which are keyed by page id.
+    dirty: BTreeMap<u64, Vec<u8>>,
+}
+
+impl WriteTx {
+    pub fn begin(path: &str) -> Result<WriteTx, DatabaseError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        file.try_lock_exclusive()
+            .map_err(|_| DatabaseError::Locked)?;
+
+        let meta0 = read_meta(&mut file, 0, 0);
+        // The page size of the invalid meta can't be trusted, so the default one
+        // is used to find the other meta.
+        let page_size = meta0.map_or(4096, |meta| meta.page_size as u64);
+        let meta1 = read_meta(&mut file, 1, page_size);
+        let meta = [meta0, meta1]
+            .into_iter()
+            .flatten()
+            .max_by_key(|meta| meta.txid)
+            .ok_or(DatabaseError::InvalidMeta)?;
+        if meta.freelist_pgid.0 == u64::MAX {
+            return Err(DatabaseError::Unsupported {
+                reason: "the freelist isn't synced".to_string(),
+            });
+        }
+
+        let mut tx = WriteTx {
+            file,
+            page_size: meta.page_size as usize,
+            meta,
+            free: BTreeSet::new(),
+            freed: BTreeSet::new(),
+            dirty: BTreeMap::new(),
+        };
+        let freelist = tx.read_page(meta.freelist_pgid.0)?;
+        tx.free = decode_freelist(&freelist)?.into_iter().collect();
+        tx.free_page(meta.freelist_pgid.0, &freelist);
+        Ok(tx)
+    }
+
+    // create creates the empty database file and begins the transaction on it, it
+    // fails if the file already exists.
+    pub fn create(path: &str, page_size: u32) -> Result<WriteTx, DatabaseError> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let page_size_bytes = page_size as usize;
+        let mut data = vec![0u8; page_size_bytes * 4];
+        for txid in 0..2 {
+            let meta = bolt::Meta::new(page_size, txid).encode(page_size_bytes);
+            let start = txid as usize * page_size_bytes;
+            data[start..start + page_size_bytes].copy_from_slice(&meta);
+        }
+        bolt::Page {
+            id: Pgid(2),
+            flags: bolt::PageFlag::FreelistPageFlag,
+            count: 0,
+            overflow: 0,
+        }
+        .encode(&mut data[page_size_bytes * 2..]);
+        bolt::Page {
+            id: Pgid(3),
+            flags: bolt::PageFlag::LeafPageFlag,
+            count: 0,
+            overflow: 0,
+        }
+        .encode(&mut data[page_size_bytes * 3..]);
+        file.write_all(&data)?;
+        file.sync_all()?;
+        drop(file);
+
+        WriteTx::begin(path)
+    }
+
+    // export_bucket writes the bucket with it's sub buckets into a new database file,
+    // it's the only top level bucket of the new database. The pages are packed as
+    // compact_bucket does, and the page size is same as the source's.
+    pub fn export_bucket(
+        db: Rc<RefCell<DB>>,
+        path: &[&[u8]],
+        to: &str,
+    ) -> Result<(), DatabaseError> {
+        let bucket =
+            DB::get_bucket(db.clone(), path).ok_or_else(|| DatabaseError::BucketNotFound {
+                name: path
+                    .iter()
+                    .map(|name| String::from_utf8_lossy(name))
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            })?;
+
+        let mut tx = WriteTx::create(to, DB::info(db).page_size)?;
+        let value = tx.import_bucket(&bucket)?;
+        let mut items = Vec::new();
+        tx.take_items(tx.meta.root_pgid.0, &mut items)?;
+        items.push(LeafItem {
+            flags: 0x01,
+            key: bucket.name.clone(),
+            value,
+        });
+        tx.meta.root_pgid = Pgid(tx.build_tree(items)?);
+        tx.commit()
+    }
+
+    // merge writes the buckets of both databases into a new database file, the
+    // buckets with the same path are merged recursively, and the conflicting keys
+    // are resolved by the policy. The new file is removed if the merge fails.
+    pub fn merge(
+        ours: Rc<RefCell<DB>>,
+        theirs: Rc<RefCell<DB>>,
+        to: &str,
+        policy: ConflictPolicy,
+    ) -> Result<(), DatabaseError> {
+        let mut tx = WriteTx::create(to, DB::info(ours.clone()).page_size)?;
+        let result = tx.merge_root(ours, theirs, policy);
+        match result {
+            Ok(()) => tx.commit(),
+            Err(e) => {
+                drop(tx);
+                fs::remove_file(to)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn merge_root(
+        &mut self,
+        ours: Rc<RefCell<DB>>,
+        theirs: Rc<RefCell<DB>>,
+        policy: ConflictPolicy,
+    ) -> Result<(), DatabaseError> {
+        let ours = DB::iter_buckets(ours)
+            .map(|bucket| (bucket.name.clone(), MergeItem::Bucket(bucket)))
+            .collect();
+        let theirs = DB::iter_buckets(theirs)
+            .map(|bucket| (bucket.name.clone(), MergeItem::Bucket(bucket)))
+            .collect();
+        let mut items = Vec::new();
+        self.take_items(self.meta.root_pgid.0, &mut items)?;
+        items.extend(self.merge_items(ours, theirs, policy, &[])?);
+        self.meta.root_pgid = Pgid(self.build_tree(items)?);
+        Ok(())
+    }
+
+    // merge_items merges the keys of the bucket at the path, and returns the items
+    // of the merged bucket.
+    fn merge_items(
+        &mut self,
+        ours: BTreeMap<Vec<u8>, MergeItem>,
+        mut theirs: BTreeMap<Vec<u8>, MergeItem>,
+        policy: ConflictPolicy,
+        path: &[Vec<u8>],
+    ) -> Result<Vec<LeafItem>, DatabaseError> {
+        let mut merged = Vec::with_capacity(ours.len() + theirs.len());
+        for (key, item) in ours {
+            let item = match (item, theirs.remove(&key)) {
+                (item, None) => item,
+                (MergeItem::Value(ours), Some(MergeItem::Value(theirs))) if ours == theirs => {
+                    MergeItem::Value(ours)
+                }
+                (MergeItem::Bucket(ours), Some(MergeItem::Bucket(theirs))) => {
+                    let mut bucket_path = path.to_vec();
+                    bucket_path.push(key.clone());
+                    let items = self.merge_items(
+                        bucket_items(&ours),
+                        bucket_items(&theirs),
+                        policy,
+                        &bucket_path,
+                    )?;
+                    let sequence = ours.sequence.max(theirs.sequence);
+                    merged.push(LeafItem {
+                        flags: 0x01,
+                        key,
+                        value: self.bucket_value(items, sequence)?,
+                    });
+                    continue;
+                }
+                (ours, Some(theirs)) => match policy {
+                    ConflictPolicy::Ours => ours,
+                    ConflictPolicy::Theirs => theirs,
+                    ConflictPolicy::Fail => {
+                        return Err(DatabaseError::MergeConflict {
+                            path: path
+                                .iter()
+                                .chain([&key])
+                                .map(|name| String::from_utf8_lossy(name))
+                                .collect::<Vec<_>>()
+                                .join("/"),
+                        });
+                    }
+                },
+            };
+            merged.push(self.merge_item(key, item)?);
+        }
+        for (key, item) in theirs {
+            merged.push(self.merge_item(key, item)?);
+        }
+
+        merged.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(merged)
+    }
+
+    fn merge_item(&mut self, key: Vec<u8>, item: MergeItem) -> Result<LeafItem, DatabaseError> {
+        Ok(match item {
+            MergeItem::Value(value) => LeafItem {
+                flags: 0,
+                key,
+                value,
+            },
+            MergeItem::Bucket(bucket) => LeafItem {
+                flags: 0x01,
+                key,
+                value: self.import_bucket(&bucket)?,
+            },
+        })
+    }
+
+    // put sets the value of the key in the bucket, the bucket is specified by the
+    // names from the top level bucket.
+    pub fn put(&mut self, bucket: &[&[u8]], key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        // The sizes are stored as u32 in the page elements.
+        if key.len() > bolt::MAX_KEY_SIZE {
+            return Err(DatabaseError::IncompatibleValue {
+                reason: format!("the key size {} exceeds {}", key.len(), bolt::MAX_KEY_SIZE),
+            });
+        }
+        if value.len() > bolt::MAX_VALUE_SIZE {
+            return Err(DatabaseError::IncompatibleValue {
+                reason: format!(
+                    "the value size {} exceeds {}",
+                    value.len(),
+                    bolt::MAX_VALUE_SIZE
+                ),
+            });
+        }
+        self.update(bucket, key, Op::Put(value))
+    }
+
+    // delete removes the key from the bucket, it's an error if the key doesn't exist.
+    pub fn delete(&mut self, bucket: &[&[u8]], key: &[u8]) -> Result<(), DatabaseError> {
+        self.update(bucket, key, Op::Delete)
+    }
+
+    // create_bucket creates an empty inline bucket, the parent buckets must exist.
+    pub fn create_bucket(&mut self, path: &[&[u8]]) -> Result<(), DatabaseError> {
+        let (name, parent) = path.split_last().ok_or(DatabaseError::IncompatibleValue {
+            reason: "the bucket path is empty".to_string(),
+        })?;
+        self.update(parent, name, Op::CreateBucket)
+    }
+
+    // delete_bucket removes the bucket, the pages of it and it's sub buckets are
+    // freed.
+    pub fn delete_bucket(&mut self, path: &[&[u8]]) -> Result<(), DatabaseError> {
+        let (name, parent) = path.split_last().ok_or(DatabaseError::IncompatibleValue {
+            reason: "the bucket path is empty".to_string(),
+        })?;
+        self.update(parent, name, Op::DeleteBucket)
+    }
+
+    // commit writes the modified pages, the freelist and then the meta, the file is
+    // synced before the meta is written.
+    pub fn commit(mut self) -> Result<(), DatabaseError> {
+        let freelist_page_id = self.write_freelist();
+        let len = self.meta.max_pgid.0 * self.page_size as u64;
+        if self.file.metadata()?.len() < len {
+            self.file.set_len(len)?;
+        }
+        for (page_id, data) in &self.dirty {
+            self.file
+                .seek(SeekFrom::Start(page_id * self.page_size as u64))?;
+            self.file.write_all(data)?;
+        }
+        self.file.sync_all()?;
+
+        self.meta.freelist_pgid = Pgid(freelist_page_id);
+        self.meta.txid += 1;
+        let meta = self.meta.encode(self.page_size);
+        self.file
+            .seek(SeekFrom::Start(self.meta.txid % 2 * self.page_size as u64))?;
+        self.file.write_all(&meta)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    // forecast returns what commit would change without writing the file, the
+    // freelist is allocated as commit does, so the sizes are exact.
+    pub fn forecast(mut self) -> Result<WriteForecast, DatabaseError> {
+        let file_size = self.file.metadata()?.len();
+        self.write_freelist();
+        Ok(WriteForecast {
+            page_size: self.page_size as u64,
+            pages_freed: self.freed.len() as u64,
+            pages_written: self
+                .dirty
+                .values()
+                .map(|data| (data.len() / self.page_size) as u64)
+                .sum(),
+            file_size,
+            new_file_size: file_size.max(self.meta.max_pgid.0 * self.page_size as u64),
+        })
+    }
+
+    // write_freelist allocates the freelist page with the free and freed pages, and
+    // adds it to the dirty pages. It returns the page id of it.
+    fn write_freelist(&mut self) -> u64 {
+        // The freelist page is allocated before the ids are encoded, so it's
+        // excluded from the freelist. The size is estimated with the current ids,
+        // which is the upper bound.
+        let count = self.free.len() + self.freed.len();
+        let size = PAGE_HEADER_SIZE + 8 * (count + 1);
+        let pages = size.div_ceil(self.page_size) as u64;
+        let freelist_page_id = self.allocate(pages);
+
+        let ids: BTreeSet<u64> = self.free.union(&self.freed).copied().collect();
+        let mut data = vec![0u8; pages as usize * self.page_size];
+        let mut offset = PAGE_HEADER_SIZE;
+        let count = if ids.len() >= 0xFFFF {
+            data[offset..offset + 8].copy_from_slice(&(ids.len() as u64).to_le_bytes());
+            offset += 8;
+            0xFFFF
+        } else {
+            ids.len() as u16
+        };
+        for id in ids {
+            data[offset..offset + 8].copy_from_slice(&id.to_le_bytes());
+            offset += 8;
+        }
+        bolt::Page {
+            id: Pgid(freelist_page_id),
+            flags: bolt::PageFlag::FreelistPageFlag,
+            count,
+            overflow: (pages - 1) as u32,
+        }
+        .encode(&mut data);
+        self.dirty.insert(freelist_page_id, data);
+        freelist_page_id
+    }
+
+    // compact_bucket rewrites the pages of the bucket into fully packed pages, the
+    // pages of it's sub buckets are not changed. It's a no-op for the inline bucket.
+    pub fn compact_bucket(&mut self, path: &[&[u8]]) -> Result<(), DatabaseError> {
+        let (name, parent) = path.split_last().ok_or(DatabaseError::IncompatibleValue {
+            reason: "the bucket path is empty".to_string(),
+        })?;
+        self.update(parent, name, Op::CompactBucket)
+    }
+
+    fn update(&mut self, bucket: &[&[u8]], key: &[u8], op: Op) -> Result<(), DatabaseError> {
+        // The top level keys must be buckets.
+        if bucket.is_empty() && matches!(op, Op::Put(_) | Op::Delete) {
+            return Err(DatabaseError::IncompatibleValue {
+                reason: "the keys can't be stored in the root bucket".to_string(),
+            });
+        }
+
+        let mut path = bucket.to_vec();
+        path.push(key);
+        let pages = self.update_tree(self.meta.root_pgid.0, &path, &op)?;
+        self.meta.root_pgid = Pgid(self.build_root(pages)?);
+        Ok(())
+    }
+
+    // update_tree applies the operation to the tree from the page, the pages from
+    // it to the leaf page are copied. It returns the pages which replace the page
+    // with their first keys: the page is split if it doesn't fit in a page, and it's
+    // removed if it becomes empty.
+    fn update_tree(
+        &mut self,
+        page_id: u64,
+        path: &[&[u8]],
+        op: &Op,
+    ) -> Result<Vec<BranchItem>, DatabaseError> {
+        let data = self.read_page(page_id)?;
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        self.free_page(page_id, &data);
+
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            let mut items = decode_branch(&data)?;
+            // The key is in the last child whose first key isn't greater than it.
+            let index = items
+                .partition_point(|item| item.key.as_slice() <= path[0])
+                .saturating_sub(1);
+            let Some(item) = items.get(index) else {
+                return Err(corrupted(page_id, "empty branch page"));
+            };
+            let children = self.update_tree(item.pgid, path, op)?;
+            items.splice(index..=index, children);
+
+            let size: usize = items.iter().map(branch_item_size).sum();
+            let threshold = self.split_threshold(size, items.len());
+            return self.write_branches(items, threshold);
+        }
+
+        if !page.flags.contains(bolt::PageFlag::LeafPageFlag) {
+            return Err(corrupted(page_id, "unexpected page flags"));
+        }
+        let mut items = decode_leaf(&data)?;
+        self.apply(&mut items, path, op)?;
+        let size: usize = items.iter().map(leaf_item_size).sum();
+        let threshold = self.split_threshold(size, items.len());
+        self.write_leaves(items, threshold)
+    }
+
+    // split_threshold returns the size which the items of a page are split at. The
+    // items are kept in one page if they fit, otherwise the pages are half filled
+    // as bolt's default fill percent, which leaves the room for the next puts.
+    fn split_threshold(&self, size: usize, count: usize) -> usize {
+        if PAGE_HEADER_SIZE + size <= self.page_size && count <= MAX_PAGE_ELEMENTS {
+            usize::MAX
+        } else {
+            self.page_size / 2
+        }
+    }
+
+    // apply applies the operation to the items of a leaf page, the path is the names
+    // of the buckets from this page followed by the key.
+    fn apply(
+        &mut self,
+        items: &mut Vec<LeafItem>,
+        path: &[&[u8]],
+        op: &Op,
+    ) -> Result<(), DatabaseError> {
+        let name = path[0];
+        let position = items.binary_search_by(|item| item.key.as_slice().cmp(name));
+        let bucket_not_found = || DatabaseError::BucketNotFound {
+            name: String::from_utf8_lossy(name).to_string(),
+        };
+        if path.len() == 1 {
+            let is_bucket = position.is_ok_and(|index| items[index].flags & 0x01 != 0);
+            match (op, position) {
+                (Op::Put(_) | Op::Delete, Ok(_)) if is_bucket => {
+                    return Err(DatabaseError::IncompatibleValue {
+                        reason: "the key is a bucket".to_string(),
+                    });
+                }
+                (Op::CreateBucket, Ok(_)) if is_bucket => {
+                    return Err(DatabaseError::BucketExists {
+                        name: String::from_utf8_lossy(name).to_string(),
+                    });
+                }
+                (Op::CreateBucket | Op::DeleteBucket | Op::CompactBucket, Ok(_)) if !is_bucket => {
+                    return Err(DatabaseError::IncompatibleValue {
+                        reason: "the key is not a bucket".to_string(),
+                    });
+                }
+                (Op::CreateBucket, Err(index)) => {
+                    // The new bucket is an inline bucket with an empty leaf page.
+                    let mut value = bolt::Bucket {
+                        root: Pgid(0),
+                        sequence: 0,
+                    }
+                    .encode()
+                    .to_vec();
+                    value.extend_from_slice(&encode_leaf(&[])?);
+                    items.insert(
+                        index,
+                        LeafItem {
+                            flags: 0x01,
+                            key: name.to_vec(),
+                            value,
+                        },
+                    );
+                }
+                (Op::DeleteBucket, Ok(index)) => {
+                    let item = items.remove(index);
+                    let bucket: bolt::Bucket = TryFrom::try_from(item.value.as_slice())?;
+                    if bucket.root.0 != 0 {
+                        self.free_tree(bucket.root.0)?;
+                    } else {
+                        self.free_inline_tree(&item.value[16..])?;
+                    }
+                }
+                (Op::DeleteBucket | Op::CompactBucket, Err(_)) => return Err(bucket_not_found()),
+                (Op::CompactBucket, Ok(index)) => {
+                    let mut bucket: bolt::Bucket =
+                        TryFrom::try_from(items[index].value.as_slice())?;
+                    if bucket.root.0 != 0 {
+                        let mut bucket_items = Vec::new();
+                        self.take_items(bucket.root.0, &mut bucket_items)?;
+                        bucket.root = Pgid(self.build_tree(bucket_items)?);
+                        items[index].value = bucket.encode().to_vec();
+                    }
+                }
+                (Op::Put(value), Ok(index)) => items[index].value = value.to_vec(),
+                (Op::Put(value), Err(index)) => items.insert(
+                    index,
+                    LeafItem {
+                        flags: 0,
+                        key: name.to_vec(),
+                        value: value.to_vec(),
+                    },
+                ),
+                (Op::Delete, Ok(index)) => {
+                    items.remove(index);
+                }
+                (Op::Delete, Err(_)) => return Err(DatabaseError::KeyNotFound),
+                (Op::CreateBucket, Ok(_)) => unreachable!("the key is either a bucket or not"),
+            }
+            return Ok(());
+        }
+
+        let index = position.map_err(|_| bucket_not_found())?;
+        let item = &items[index];
+        if item.flags & 0x01 == 0 || item.value.len() < 16 {
+            return Err(bucket_not_found());
+        }
+
+        let mut bucket: bolt::Bucket = TryFrom::try_from(item.value.as_slice())?;
+        let value = if bucket.root.0 == 0 {
+            // The inline bucket is stored in the value, so the value is rewritten.
+            let mut inline_items = decode_leaf(&item.value[16..])?;
+            self.apply(&mut inline_items, &path[1..], op)?;
+            let page = encode_leaf(&inline_items)?;
+            // Same as bolt, the bucket can't be inline if it has sub buckets or it's
+            // larger than a quarter of the page.
+            let has_bucket = inline_items.iter().any(|item| item.flags & 0x01 != 0);
+            if has_bucket || 16 + page.len() > self.page_size / 4 {
+                bucket.root = Pgid(self.write_page(page));
+                bucket.encode().to_vec()
+            } else {
+                let mut value = bucket.encode().to_vec();
+                value.extend_from_slice(&page);
+                value
+            }
+        } else {
+            let pages = self.update_tree(bucket.root.0, &path[1..], op)?;
+            bucket.root = Pgid(self.build_root(pages)?);
+            bucket.encode().to_vec()
+        };
+        items[index].value = value;
+        Ok(())
+    }
+
+    // import_bucket writes the pages of the bucket which is read from another
+    // database, and returns the value of the bucket element.
+    fn import_bucket(&mut self, bucket: &Bucket) -> Result<Vec<u8>, DatabaseError> {
+        let items = bucket_items(bucket)
+            .into_iter()
+            .map(|(key, item)| self.merge_item(key, item))
+            .collect::<Result<_, _>>()?;
+        self.bucket_value(items, bucket.sequence)
+    }
+
+    // bucket_value writes the pages of the bucket which has the items, and returns
+    // the value of the bucket element.
+    fn bucket_value(
+        &mut self,
+        items: Vec<LeafItem>,
+        sequence: u64,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut header = bolt::Bucket {
+            root: Pgid(0),
+            sequence,
+        };
+        // Same as bolt, the bucket is inline if it doesn't have sub buckets and it's
+        // not larger than a quarter of the page.
+        let size: usize = items.iter().map(leaf_item_size).sum();
+        let has_bucket = items.iter().any(|item| item.flags & 0x01 != 0);
+        if has_bucket || 16 + PAGE_HEADER_SIZE + size > self.page_size / 4 {
+            header.root = Pgid(self.build_tree(items)?);
+            return Ok(header.encode().to_vec());
+        }
+        let mut value = header.encode().to_vec();
+        value.extend_from_slice(&encode_leaf(&items)?);
+        Ok(value)
+    }
+
+    // take_items collects the items of the leaf pages in the tree from the page, the
+    // pages of the tree are freed, but the pages of the sub buckets are not.
+    fn take_items(&mut self, page_id: u64, items: &mut Vec<LeafItem>) -> Result<(), DatabaseError> {
+        let data = self.read_page(page_id)?;
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        self.free_page(page_id, &data);
+
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            for item in decode_branch(&data)? {
+                self.take_items(item.pgid, items)?;
+            }
+        } else {
+            items.extend(decode_leaf(&data)?);
+        }
+        Ok(())
+    }
+
+    // build_tree writes the items into the leaf pages which are filled as much as
+    // possible, and builds the branch pages above them. It returns the root page id.
+    fn build_tree(&mut self, items: Vec<LeafItem>) -> Result<u64, DatabaseError> {
+        let children = self.write_leaves(items, self.page_size)?;
+        self.build_root(children)
+    }
+
+    // build_root builds the branch pages above the pages until there is a single
+    // root page, and returns it. The root is an empty leaf page if there are no
+    // pages, e.g. all keys of the bucket are deleted.
+    fn build_root(&mut self, mut children: Vec<BranchItem>) -> Result<u64, DatabaseError> {
+        if children.is_empty() {
+            return Ok(self.write_page(encode_leaf(&[])?));
+        }
+        while children.len() > 1 {
+            children = self.write_branches(children, self.page_size)?;
+        }
+        Ok(children[0].pgid)
+    }
+
+    // write_leaves writes the items into the leaf pages, which are split at the
+    // threshold, and returns the first key and page id of each page.
+    fn write_leaves(
+        &mut self,
+        items: Vec<LeafItem>,
+        threshold: usize,
+    ) -> Result<Vec<BranchItem>, DatabaseError> {
+        let mut pages = Vec::new();
+        for page in split_pages(items, threshold, leaf_item_size) {
+            let key = page[0].key.clone();
+            let pgid = self.write_page(encode_leaf(&page)?);
+            pages.push(BranchItem { key, pgid });
+        }
+        Ok(pages)
+    }
+
+    fn write_branches(
+        &mut self,
+        items: Vec<BranchItem>,
+        threshold: usize,
+    ) -> Result<Vec<BranchItem>, DatabaseError> {
+        let mut pages = Vec::new();
+        for page in split_pages(items, threshold, branch_item_size) {
+            let key = page[0].key.clone();
+            let pgid = self.write_page(encode_branch(&page)?);
+            pages.push(BranchItem { key, pgid });
+        }
+        Ok(pages)
+    }
+
+    // free_tree frees the pages of the tree from the page, including the pages of
+    // the sub buckets.
+    fn free_tree(&mut self, page_id: u64) -> Result<(), DatabaseError> {
+        let data = self.read_page(page_id)?;
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        self.free_page(page_id, &data);
+
+        if page.flags.contains(bolt::PageFlag::BranchPageFlag) {
+            for item in decode_branch(&data)? {
+                self.free_tree(item.pgid)?;
+            }
+        } else {
+            self.free_inline_tree(&data)?;
+        }
+        Ok(())
+    }
+
+    // free_inline_tree frees the pages of the sub buckets in the leaf page, the
+    // inline sub buckets don't have pages but their sub buckets may have.
+    fn free_inline_tree(&mut self, data: &[u8]) -> Result<(), DatabaseError> {
+        for item in decode_leaf(data)? {
+            if item.flags & 0x01 == 0 || item.value.len() < 16 {
+                continue;
+            }
+            let bucket: bolt::Bucket = TryFrom::try_from(item.value.as_slice())?;
+            if bucket.root.0 != 0 {
+                self.free_tree(bucket.root.0)?;
+            } else {
+                self.free_inline_tree(&item.value[16..])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_id: u64) -> Result<Vec<u8>, DatabaseError> {
+        // The pages written by this transaction aren't in the file yet.
+        if let Some(data) = self.dirty.get(&page_id) {
+            return Ok(data.clone());
+        }
+        if page_id >= self.meta.max_pgid.0 {
+            return Err(corrupted(page_id, "beyond the max page id"));
+        }
+
+        let mut data = vec![0u8; self.page_size];
+        self.file
+            .seek(SeekFrom::Start(page_id * self.page_size as u64))?;
+        self.file.read_exact(&mut data)?;
+
+        let page: bolt::Page = TryFrom::try_from(data.as_slice())?;
+        if page.overflow > 0 {
+            if page_id + page.overflow as u64 >= self.meta.max_pgid.0 {
+                return Err(corrupted(
+                    page_id,
+                    "the overflow pages are beyond the max page id",
+                ));
+            }
+            data.resize(self.page_size * (page.overflow as usize + 1), 0);
+            self.file.read_exact(&mut data[self.page_size..])?;
+        }
+        Ok(data)
+    }
+
+    // free_page frees the page with it's overflow pages, the page is read by
+    // read_page so the overflow is checked.
+    fn free_page(&mut self, page_id: u64, data: &[u8]) {
+        let overflow = utils::read_value::<u32>(data, 12) as u64;
+        // The page written by this transaction isn't referenced by the committed
+        // meta, so it can be allocated again in this transaction.
+        if self.dirty.remove(&page_id).is_some() {
+            self.free.extend(page_id..=page_id + overflow);
+            return;
+        }
+        self.freed.extend(page_id..=page_id + overflow);
+    }
+
+    // allocate allocates the continuous pages, the file is grown if there are not
+    // enough free pages.
+    fn allocate(&mut self, count: u64) -> u64 {
+        let mut start = 0;
+        let mut length = 0;
+        for &id in &self.free {
+            if length > 0 && start + length == id {
+                length += 1;
+            } else {
+                start = id;
+                length = 1;
+            }
+            if length == count {
+                for id in start..start + count {
+                    self.free.remove(&id);
+                }
+                return start;
+            }
+        }
+
+        let start = self.meta.max_pgid.0;
+        self.meta.max_pgid = Pgid(start + count);
+        start
+    }
+
+    // write_page allocates the pages for the encoded page and returns the page id.
+    fn write_page(&mut self, mut data: Vec<u8>) -> u64 {
+        let pages = data.len().div_ceil(self.page_size).max(1);
+        let page_id = self.allocate(pages as u64);
+        data.resize(pages * self.page_size, 0);
+        data[0..8].copy_from_slice(&page_id.to_le_bytes());
+        data[12..16].copy_from_slice(&((pages - 1) as u32).to_le_bytes());
+        self.dirty.insert(page_id, data);
+        page_id
+    }
+}
+
+// bucket_items returns the key-value pairs and the sub buckets of the bucket, which
+// are keyed by their keys.
+fn bucket_items(bucket: &Bucket) -> BTreeMap<Vec<u8>, MergeItem> {
+    let mut items: BTreeMap<Vec<u8>, MergeItem> = bucket
+        .iter_items(Some(0))
+        .filter_map(|item| match item {
+            DbItem::KeyValue { key, value, .. } => Some((key, MergeItem::Value(value.into_vec()))),
+            DbItem::Bucket(_) => None,
+        })
+        .collect();
+    for child in bucket.iter_buckets() {
+        items.insert(child.name.clone(), MergeItem::Bucket(child));
+    }
+    items
+}
+
+fn leaf_item_size(item: &LeafItem) -> usize {
+    16 + item.key.len() + item.value.len()
+}
+
+fn branch_item_size(item: &BranchItem) -> usize {
+    16 + item.key.len()
+}
+
+// split_pages splits the items into the pages whose header and items are not larger
+// than the threshold, a single item larger than it takes a page with the overflow
+// pages. The element count of a page is u16, so it's capped too.
+fn split_pages<T>(items: Vec<T>, threshold: usize, size_of: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+    let mut pages: Vec<Vec<T>> = Vec::new();
+    let mut page: Vec<T> = Vec::new();
+    let mut size = PAGE_HEADER_SIZE;
+    for item in items {
+        let item_size = size_of(&item);
+        if !page.is_empty()
+            && (size.saturating_add(item_size) > threshold || page.len() == MAX_PAGE_ELEMENTS)
+        {
+            pages.push(std::mem::take(&mut page));
+            size = PAGE_HEADER_SIZE;
+        }
+        size += item_size;
+        page.push(item);
+    }
+    if !page.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+// page_count returns the element count of the page header, it's an error instead of
+// truncated if the items don't fit in u16.
+fn page_count(len: usize) -> Result<u16, DatabaseError> {
+    u16::try_from(len).map_err(|_| DatabaseError::IncompatibleValue {
+        reason: format!(
+            "{} elements exceed the {} elements of a page",
+            len, MAX_PAGE_ELEMENTS
+        ),
+    })
+}
+
+fn corrupted(page_id: u64, reason: &str) -> DatabaseError {
+    DatabaseError::Corrupted {
+        reason: format!("page {}: {}", page_id, reason),
+    }
+}
+
+// read_meta returns the meta if the meta page is valid.
+fn read_meta(file: &mut File, page_id: u64, page_size: u64) -> Option<bolt::Meta> {
+    let mut data = [0u8; 80];
+    file.seek(SeekFrom::Start(page_id * page_size)).ok()?;
+    file.read_exact(&mut data).ok()?;
+    let meta = bolt::Meta::try_from(&data[..]).ok()?;
+    let valid = meta.magic == bolt::MAGIC_NUMBER
+        && meta.version == bolt::DATAFILE_VERSION
+        && meta.checksum == utils::fnv64(&data[16..72]);
+    valid.then_some(meta)
+}
+
+fn decode_freelist(data: &[u8]) -> Result<Vec<u64>, DatabaseError> {
+    let page: bolt::Page = TryFrom::try_from(data)?;
+    if !page.flags.contains(bolt::PageFlag::FreelistPageFlag) {
+        return Err(corrupted(page.id.0, "not a freelist page"));
+    }
+
+    let (count, start) = if page.count == 0xFFFF {
+        (utils::read_value::<u64>(data, PAGE_HEADER_SIZE) as usize, 1)
+    } else {
+        (page.count as usize, 0)
+    };
+    if PAGE_HEADER_SIZE + (start + count) * 8 > data.len() {
+        return Err(corrupted(page.id.0, "the freelist is out of the page"));
+    }
+    Ok((start..start + count)
+        .map(|i| utils::read_value::<u64>(data, PAGE_HEADER_SIZE + i * 8))
+        .collect())
+}
+
+fn decode_branch(data: &[u8]) -> Result<Vec<BranchItem>, DatabaseError> {
+    let page: bolt::Page = TryFrom::try_from(data)?;
+    let mut items = Vec::with_capacity(page.count as usize);
+    for i in 0..page.count as usize {
+        let start = PAGE_HEADER_SIZE + i * 16;
+        let element = bolt::BranchPageElement::try_from(data.get(start..).unwrap_or_default())?;
+        let key_start = start + element.pos as usize;
+        let key = data
+            .get(key_start..key_start + element.ksize as usize)
+            .ok_or_else(|| corrupted(page.id.0, "the element is out of the page"))?;
+        items.push(BranchItem {
+            key: key.to_vec(),
+            pgid: element.pgid.0,
+        });
+    }
+    Ok(items)
+}
+
+fn decode_leaf(data: &[u8]) -> Result<Vec<LeafItem>, DatabaseError> {
+    let page: bolt::Page = TryFrom::try_from(data)?;
+    let mut items = Vec::with_capacity(page.count as usize);
+    for i in 0..page.count as usize {
+        let start = PAGE_HEADER_SIZE + i * 16;
+        let element = bolt::LeafPageElement::try_from(data.get(start..).unwrap_or_default())?;
+        let key_start = start + element.pos as usize;
+        let value_start = key_start + element.ksize as usize;
+        let value_end = value_start + element.vsize as usize;
+        if value_end > data.len() {
+            return Err(corrupted(page.id.0, "the element is out of the page"));
+        }
+        items.push(LeafItem {
+            flags: element.flags,
+            key: data[key_start..value_start].to_vec(),
+            value: data[value_start..value_end].to_vec(),
+        });
+    }
+    Ok(items)
+}
+
+// encode_leaf encodes the leaf page without padding, the page id and overflow are
+// set when the page is allocated, they are 0 for the inline page.
+fn encode_leaf(items: &[LeafItem]) -> Result<Vec<u8>, DatabaseError> {
+    let count = page_count(items.len())?;
+    let mut data = vec![0u8; PAGE_HEADER_SIZE + items.len() * 16];
+    bolt::Page {
+        id: Pgid(0),
+        flags: bolt::PageFlag::LeafPageFlag,
+        count,
+        overflow: 0,
+    }
+    .encode(&mut data);
+
+    for (i, item) in items.iter().enumerate() {
+        let start = PAGE_HEADER_SIZE + i * 16;
+        bolt::LeafPageElement {
+            flags: item.flags,
+            pos: (data.len() - start) as u32,
+            ksize: item.key.len() as u32,
+            vsize: item.value.len() as u32,
+        }
+        .encode(&mut data[start..start + 16]);
+        data.extend_from_slice(&item.key);
+        data.extend_from_slice(&item.value);
+    }
+    Ok(data)
+}
+
+fn encode_branch(items: &[BranchItem]) -> Result<Vec<u8>, DatabaseError> {
+    let count = page_count(items.len())?;
+    let mut data = vec![0u8; PAGE_HEADER_SIZE + items.len() * 16];
+    bolt::Page {
+        id: Pgid(0),
+        flags: bolt::PageFlag::BranchPageFlag,
+        count,
+        overflow: 0,
+    }
+    .encode(&mut data);
+
+    for (i, item) in items.iter().enumerate() {
+        let start = PAGE_HEADER_SIZE + i * 16;
+        bolt::BranchPageElement {
+            pos: (data.len() - start) as u32,
+            ksize: item.key.len() as u32,
+            pgid: Pgid(item.pgid),
+        }
+        .encode(&mut data[start..start + 16]);
+        data.extend_from_slice(&item.key);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnclaOptions, Severity};
+    use proptest::collection::{btree_map, btree_set, vec};
+    use proptest::prelude::*;
+    use std::path::PathBuf;
+
+    // TempPath removes the database file when it's dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> TempPath {
+            let path =
+                std::env::temp_dir().join(format!("ancla-{}-{}.db", name, std::process::id()));
+            let _ = fs::remove_file(&path);
+            TempPath(path)
+        }
+
+        fn as_str(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+
+        fn open(&self) -> Rc<RefCell<DB>> {
+            DB::open_from_bytes(AnclaOptions::builder().build(), fs::read(&self.0).unwrap())
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn put_splits_pages() {
+        let path = TempPath::new("split");
+        let mut tx = WriteTx::create(path.as_str(), 4096).unwrap();
+        tx.create_bucket(&[b"b"]).unwrap();
+        tx.commit().unwrap();
+        for i in 0..400 {
+            let mut tx = WriteTx::begin(path.as_str()).unwrap();
+            tx.put(&[b"b"], &key(i), &[b'v'; 100]).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let db = path.open();
+        assert_eq!(DB::doctor(db.clone()).findings.len(), 0);
+        let bucket = DB::get_bucket(db.clone(), &[b"b"]).unwrap();
+        assert_eq!(bucket.iter_items(Some(0)).count(), 400);
+        assert!(bucket.info().depth > 1);
+        // The pages are split before they overflow.
+        assert!(DB::iter_pages(db).all(|page| page.overflow == 0));
+    }
+
+    #[test]
+    fn delete_removes_empty_pages() {
+        let path = TempPath::new("delete");
+        let mut tx = WriteTx::create(path.as_str(), 4096).unwrap();
+        tx.create_bucket(&[b"b"]).unwrap();
+        tx.commit().unwrap();
+        for i in 0..200 {
+            let mut tx = WriteTx::begin(path.as_str()).unwrap();
+            tx.put(&[b"b"], &key(i), &[b'v'; 100]).unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(DB::get_bucket(path.open(), &[b"b"]).unwrap().info().depth > 1);
+
+        for i in 0..200 {
+            let mut tx = WriteTx::begin(path.as_str()).unwrap();
+            tx.delete(&[b"b"], &key(i)).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let db = path.open();
+        assert_eq!(DB::doctor(db.clone()).findings.len(), 0);
+        let info = DB::get_bucket(db, &[b"b"]).unwrap().info();
+        assert_eq!((info.keys, info.depth, info.pages), (0, 1, 1));
+    }
+
+    #[test]
+    fn encode_too_many_elements() {
+        let items = vec![
+            BranchItem {
+                key: Vec::new(),
+                pgid: 3,
+            };
+            MAX_PAGE_ELEMENTS + 1
+        ];
+        assert!(matches!(
+            encode_branch(&items),
+            Err(DatabaseError::IncompatibleValue { .. })
+        ));
+        assert_eq!(
+            split_pages(items, usize::MAX, branch_item_size)
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>(),
+            vec![MAX_PAGE_ELEMENTS, 1]
+        );
+    }
+
+    #[test]
+    fn create_nested_bucket_before_commit() {
+        let path = TempPath::new("nested");
+        let mut tx = WriteTx::create(path.as_str(), 4096).unwrap();
+        tx.create_bucket(&[b"b"]).unwrap();
+        // The bucket b is moved out of the inline page into a new page, which is
+        // read again by the next update.
+        tx.create_bucket(&[b"b", b"c"]).unwrap();
+        tx.put(&[b"b", b"c"], b"key", b"value").unwrap();
+        tx.commit().unwrap();
+
+        let db = path.open();
+        assert_eq!(DB::doctor(db.clone()).findings.len(), 0);
+        let bucket = DB::get_bucket(db, &[b"b", b"c"]).unwrap();
+        assert_eq!(bucket.get(b"key").as_deref(), Some(&b"value"[..]));
+    }
+
+    #[test]
+    fn encode_max_elements() {
+        let items: Vec<BranchItem> = (0..MAX_PAGE_ELEMENTS)
+            .map(|i| BranchItem {
+                key: (i as u16).to_be_bytes().to_vec(),
+                pgid: i as u64,
+            })
+            .collect();
+        let decoded = decode_branch(&encode_branch(&items).unwrap()).unwrap();
+        assert_eq!(decoded.len(), MAX_PAGE_ELEMENTS);
+        assert_eq!(decoded[MAX_PAGE_ELEMENTS - 1].key, vec![0xff, 0xfe]);
+        assert_eq!(decoded[MAX_PAGE_ELEMENTS - 1].pgid, 0xfffe);
+    }
+
+    // value_size returns the value sizes around the page boundaries of 4K pages,
+    // the larger values are stored in the overflow pages.
+    fn value_size() -> impl Strategy<Value = usize> {
+        prop_oneof![0..64usize, 4000..4200usize, 8100..8300usize]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn leaf_round_trip(
+            items in vec((0u32..2, vec(any::<u8>(), 0..64), vec(any::<u8>(), 0..256)), 0..64)
+        ) {
+            let items: Vec<LeafItem> = items
+                .into_iter()
+                .map(|(flags, key, value)| LeafItem { flags, key, value })
+                .collect();
+            let fields = |items: Vec<LeafItem>| -> Vec<(u32, Vec<u8>, Vec<u8>)> {
+                items
+                    .into_iter()
+                    .map(|item| (item.flags, item.key, item.value))
+                    .collect()
+            };
+            let decoded = decode_leaf(&encode_leaf(&items)?)?;
+            prop_assert_eq!(fields(decoded), fields(items));
+        }
+
+        #[test]
+        fn branch_round_trip(items in vec((vec(any::<u8>(), 0..64), any::<u64>()), 0..64)) {
+            let items: Vec<BranchItem> = items
+                .into_iter()
+                .map(|(key, pgid)| BranchItem { key, pgid })
+                .collect();
+            let fields = |items: Vec<BranchItem>| -> Vec<(Vec<u8>, u64)> {
+                items.into_iter().map(|item| (item.key, item.pgid)).collect()
+            };
+            let decoded = decode_branch(&encode_branch(&items)?)?;
+            prop_assert_eq!(fields(decoded), fields(items));
+        }
+
+        #[test]
+        fn meta_round_trip(
+            shift in 9u32..17,
+            fields in (any::<u64>(), any::<u64>(), any::<u64>(), any::<u64>(), any::<u64>())
+        ) {
+            let page_size = 1u32 << shift;
+            let (root, sequence, freelist, max_pgid, txid) = fields;
+            let mut meta = bolt::Meta::new(page_size, txid);
+            meta.root_pgid = Pgid(root);
+            meta.root_sequence = sequence;
+            meta.freelist_pgid = Pgid(freelist);
+            meta.max_pgid = Pgid(max_pgid);
+
+            let data = meta.encode(page_size as usize);
+            prop_assert_eq!(data.len(), page_size as usize);
+            let page = bolt::Page::try_from(&data[..])?;
+            prop_assert_eq!(page.id.0, txid % 2);
+            prop_assert_eq!(page.flags, bolt::PageFlag::MetaPageFlag);
+            let decoded = bolt::Meta::try_from(&data[..])?;
+            prop_assert_eq!(
+                (decoded.magic, decoded.version, decoded.page_size),
+                (bolt::MAGIC_NUMBER, bolt::DATAFILE_VERSION, page_size)
+            );
+            prop_assert_eq!(
+                (decoded.root_pgid, decoded.root_sequence, decoded.freelist_pgid),
+                (Pgid(root), sequence, Pgid(freelist))
+            );
+            prop_assert_eq!((decoded.max_pgid, decoded.txid), (Pgid(max_pgid), txid));
+            prop_assert_eq!(decoded.checksum, utils::fnv64(&data[16..72]));
+        }
+
+        #[test]
+        fn bucket_header_round_trip(root in any::<u64>(), sequence in any::<u64>()) {
+            let header = bolt::Bucket { root: Pgid(root), sequence }.encode();
+            let decoded = bolt::Bucket::try_from(&header[..])?;
+            prop_assert_eq!((decoded.root, decoded.sequence), (Pgid(root), sequence));
+        }
+    }
+
+    proptest! {
+        // Each case writes a database file, so there are fewer cases.
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        // The freelist pages are decoded to the free pages, the count is stored in
+        // the first element if it doesn't fit in the page header.
+        #[test]
+        fn freelist_round_trip(
+            free in btree_set(4u64..1 << 40, 0..256),
+            overflow in any::<bool>(),
+        ) {
+            let path = TempPath::new("freelist");
+            let mut tx = WriteTx::create(path.as_str(), 4096)?;
+            // The free pages are above the pages of the empty database.
+            tx.free = if overflow {
+                (4..4 + 0xFFFF).chain(free).collect()
+            } else {
+                free
+            };
+            // The freelist page is allocated from the free pages, and the page of the
+            // previous freelist is freed.
+            let page_id = tx.write_freelist();
+            let free: Vec<u64> = tx.free.union(&tx.freed).copied().collect();
+            prop_assert_eq!(decode_freelist(&tx.dirty[&page_id])?, free);
+        }
+
+        // The pairs written by WriteTx are read back by the reader, including the
+        // values in the overflow pages and the pairs of the inline bucket.
+        #[test]
+        fn put_round_trip(
+            pairs in btree_map(
+                vec(any::<u8>(), 1..32),
+                value_size().prop_flat_map(|size| vec(any::<u8>(), size)),
+                0..40,
+            ),
+            inline_pairs in btree_map(vec(any::<u8>(), 1..8), vec(any::<u8>(), 0..32), 0..8),
+        ) {
+            let path = TempPath::new("round-trip");
+            let mut tx = WriteTx::create(path.as_str(), 4096)?;
+            tx.create_bucket(&[b"b"])?;
+            tx.create_bucket(&[b"b", b"inline"])?;
+            for (key, value) in &pairs {
+                tx.put(&[b"b"], key, value)?;
+            }
+            for (key, value) in &inline_pairs {
+                tx.put(&[b"b", b"inline"], key, value)?;
+            }
+            tx.commit()?;
+
+            // The overflow pages are reported as the info findings.
+            let db = path.open();
+            let diagnosis = DB::doctor(db.clone());
+            prop_assert!(
+                diagnosis.findings.iter().all(|finding| finding.severity == Severity::Info),
+                "{:?}",
+                diagnosis.findings
+            );
+            let bucket = DB::get_bucket(db.clone(), &[b"b", b"inline"]).unwrap();
+            prop_assert!(bucket.is_inline);
+
+            let mut items = BTreeMap::new();
+            for item in DB::iter_items(db, None).try_iter() {
+                if let DbItem::KeyValue { bucket, key, value } = item? {
+                    items.insert((bucket, key), value.to_vec());
+                }
+            }
+            let expected: BTreeMap<_, _> = pairs
+                .into_iter()
+                .map(|(key, value)| ((vec![b"b".to_vec()], key), value))
+                .chain(inline_pairs.into_iter().map(|(key, value)| {
+                    ((vec![b"b".to_vec(), b"inline".to_vec()], key), value)
+                }))
+                .collect();
+            prop_assert_eq!(items, expected);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+// The golden tests of the anclalet output, each command is run against the fixture
+// database and it's exit code, stdout and stderr are compared with the snapshot in
+// tests/snapshots. Run `cargo insta review` to accept the changed output.
+use ancla::WriteTx;
+use assert_cmd::Command;
+use std::fs;
+use std::path::PathBuf;
+
+// Workspace is the temporary directory which the commands are run in, so the paths
+// in the output are relative to it. It's removed when it's dropped.
+struct Workspace(PathBuf);
+
+impl Workspace {
+    // new creates the workspace with the fixture database `data.db`.
+    fn new(name: &str) -> Workspace {
+        let dir = std::env::temp_dir().join(format!("ancla-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let workspace = Workspace(dir);
+        workspace.create("data.db");
+        workspace
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().to_string()
+    }
+
+    // create writes the fixture database, which has a bucket with many keys, an
+    // inline sub bucket and a value in the overflow pages. The second transaction
+    // frees some pages, so the freelist isn't empty.
+    fn create(&self, name: &str) {
+        let path = self.path(name);
+        let mut tx = WriteTx::create(&path, 4096).unwrap();
+        tx.create_bucket(&[b"apps"]).unwrap();
+        tx.create_bucket(&[b"apps", b"config"]).unwrap();
+        tx.create_bucket(&[b"blobs"]).unwrap();
+        for i in 0..100 {
+            let key = format!("app/{:03}", i);
+            let value = format!(r#"{{"id":{},"name":"app{}"}}"#, i, i);
+            tx.put(&[b"apps"], key.as_bytes(), value.as_bytes())
+                .unwrap();
+        }
+        tx.put(&[b"apps", b"config"], b"replicas", b"3").unwrap();
+        tx.put(&[b"apps", b"config"], b"timeout", b"30s").unwrap();
+        tx.put(&[b"blobs"], b"large", &[b'x'; 10000]).unwrap();
+        tx.put(&[b"blobs"], b"small", b"\x00\x01\x02").unwrap();
+        tx.commit().unwrap();
+
+        let mut tx = WriteTx::begin(&path).unwrap();
+        for i in 0..10 {
+            let key = format!("app/{:03}", i);
+            tx.delete(&[b"apps"], key.as_bytes()).unwrap();
+        }
+        tx.put(&[b"apps"], b"app/100", br#"{"id":100,"name":"app100"}"#)
+            .unwrap();
+        tx.commit().unwrap();
+    }
+
+    // run runs anclalet in the workspace, and returns the exit code, stdout and stderr
+    // of it.
+    fn run(&self, args: &[&str]) -> String {
+        let output = Command::new(env!("CARGO_BIN_EXE_anclalet"))
+            .current_dir(&self.0)
+            .env("NO_COLOR", "1")
+            .args(args)
+            .output()
+            .unwrap();
+        format!(
+            "exit: {}\n--- stdout\n{}--- stderr\n{}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// snapshot runs the commands in the workspace, each of them is a snapshot named by
+// the test and the command.
+macro_rules! snapshot {
+    ($workspace:expr, $($args:expr),+ $(,)?) => {
+        $(
+            let args: &[&str] = &$args;
+            insta::with_settings!({ description => args.join(" "), omit_expression => true }, {
+                insta::assert_snapshot!($workspace.run(args));
+            });
+        )+
+    };
+}
+
+#[test]
+fn info() {
+    let workspace = Workspace::new("info");
+    snapshot!(
+        workspace,
+        ["data.db", "info"],
+        ["data.db", "info", "--page-types"],
+        ["--output", "json", "data.db", "info"],
+        ["missing.db", "info"],
+        ["--output", "json", "missing.db", "info"],
+    );
+}
+
+#[test]
+fn buckets() {
+    let workspace = Workspace::new("buckets");
+    snapshot!(
+        workspace,
+        ["data.db", "buckets"],
+        ["--output", "csv", "data.db", "buckets"],
+        ["data.db", "buckets", "--depth", "1"],
+        ["data.db", "buckets", "show", "apps"],
+        ["data.db", "buckets", "show", "apps/config"],
+        ["data.db", "buckets", "show", "missing"],
+    );
+}
+
+#[test]
+fn buckets_modify() {
+    let workspace = Workspace::new("buckets-modify");
+    snapshot!(
+        workspace,
+        ["data.db", "buckets", "create", "logs"],
+        ["data.db", "buckets", "create", "logs", "--yes"],
+        ["data.db", "buckets", "delete", "blobs", "--yes"],
+        ["data.db", "buckets", "export", "--bucket", "apps", "--to", "apps.db"],
+        ["data.db", "buckets"],
+        ["apps.db", "buckets"],
+    );
+}
+
+#[test]
+fn pages() {
+    let workspace = Workspace::new("pages");
+    snapshot!(
+        workspace,
+        ["data.db", "pages"],
+        ["--output", "csv", "data.db", "pages", "--type", "leaf"],
+        ["data.db", "pages", "--start-id", "4", "--limit", "3"],
+        ["data.db", "pages", "elements"],
+        ["data.db", "pages", "items", "--page-id", "4"],
+        ["data.db", "pages", "path", "--page-id", "4"],
+        ["data.db", "pages", "freelist"],
+        ["data.db", "pages", "unreachable"],
+        ["--output", "json", "data.db", "pages", "unreachable"],
+        ["data.db", "pages", "hash"],
+    );
+}
+
+#[test]
+fn analyze() {
+    let workspace = Workspace::new("analyze");
+    snapshot!(
+        workspace,
+        ["data.db", "analyze", "tx"],
+        ["data.db", "analyze", "inline"],
+        ["data.db", "analyze", "prefixes", "--bucket", "apps"],
+        ["data.db", "analyze", "tree"],
+        ["data.db", "analyze", "values", "--bucket", "apps"],
+    );
+}
+
+#[test]
+fn kv() {
+    let workspace = Workspace::new("kv");
+    snapshot!(
+        workspace,
+        ["data.db", "kv", "list", "apps", "--limit", "3"],
+        ["data.db", "kv", "list", "apps", "--reverse", "--limit", "3"],
+        [
+            "data.db",
+            "kv",
+            "list",
+            "apps",
+            "--after-key",
+            "app/050",
+            "--limit",
+            "2"
+        ],
+        ["data.db", "kv", "list", "*", "--max-value-bytes", "8"],
+        ["--output", "csv", "data.db", "kv", "list", "apps/config"],
+        ["data.db", "kv", "get", "apps/config", "timeout"],
+        ["data.db", "kv", "get", "apps", "app/000"],
+        ["data.db", "kv", "find", "--key", "replicas"],
+        ["data.db", "kv", "locate", "--bucket", "apps", "--key", "app/050"],
+        ["data.db", "kv", "first", "apps"],
+        ["data.db", "kv", "last", "apps"],
+        ["data.db", "kv", "count"],
+        ["data.db", "kv", "count", "--recursive"],
+        ["data.db", "kv", "count", "--bucket", "apps", "--estimate"],
+        ["data.db", "kv", "etcd-list"],
+    );
+}
+
+#[test]
+fn kv_modify() {
+    let workspace = Workspace::new("kv-modify");
+    snapshot!(
+        workspace,
+        ["data.db", "kv", "put", "apps/config", "replicas", "5"],
+        [
+            "data.db",
+            "kv",
+            "put",
+            "apps/config",
+            "replicas",
+            "5",
+            "--yes"
+        ],
+        ["data.db", "kv", "delete", "apps/config", "timeout", "--yes"],
+        ["data.db", "kv", "list", "apps/config"],
+    );
+}
+
+#[test]
+fn export() {
+    let workspace = Workspace::new("export");
+    snapshot!(
+        workspace,
+        ["data.db", "export", "--buckets", "apps/config,blobs"],
+        [
+            "data.db",
+            "export",
+            "--buckets",
+            "blobs",
+            "--scrub",
+            "keys=hash,values"
+        ],
+        ["data.db", "sample", "--bucket", "apps", "--n", "3", "--seed", "7"],
+        ["data.db", "sample", "--bucket", "blobs", "--anonymize"],
+    );
+}
+
+#[test]
+fn inspect() {
+    let workspace = Workspace::new("inspect");
+    snapshot!(
+        workspace,
+        ["data.db", "dump", "--length", "96"],
+        ["data.db", "dump", "--offset", "16384", "--length", "64"],
+        ["data.db", "locate", "16400"],
+        ["data.db", "etcd", "verify-snapshot"],
+    );
+}
+
+#[test]
+fn doctor() {
+    let workspace = Workspace::new("doctor");
+    snapshot!(
+        workspace,
+        ["data.db", "doctor"],
+        ["data.db", "doctor", "--summary"],
+        ["--output", "json", "data.db", "doctor"],
+        ["--output", "yaml", "data.db", "doctor"],
+    );
+}
+
+#[test]
+fn compact() {
+    let workspace = Workspace::new("compact");
+    snapshot!(
+        workspace,
+        ["data.db", "compact", "--bucket", "apps", "--dry-run"],
+        ["data.db", "compact", "--bucket", "apps", "--yes"],
+        ["data.db", "kv", "count", "--recursive"],
+    );
+}
+
+#[test]
+fn diff_and_merge() {
+    let workspace = Workspace::new("diff-and-merge");
+    workspace.create("old.db");
+    let mut tx = WriteTx::begin(&workspace.path("data.db")).unwrap();
+    tx.put(&[b"blobs"], b"small", b"changed").unwrap();
+    tx.put(&[b"blobs"], b"new", b"value").unwrap();
+    tx.commit().unwrap();
+    snapshot!(
+        workspace,
+        ["data.db", "diff", "--with", "old.db", "--pages"],
+        ["data.db", "merge", "--with", "old.db", "--to", "merged.db"],
+        [
+            "data.db",
+            "merge",
+            "--with",
+            "old.db",
+            "--to",
+            "merged.db",
+            "--conflict",
+            "ours"
+        ],
+        ["merged.db", "kv", "list", "blobs", "--max-value-bytes", "8"],
+    );
+}
+
+#[test]
+fn record_and_replay() {
+    let workspace = Workspace::new("record-and-replay");
+    snapshot!(
+        workspace,
+        ["--record", "session.json", "data.db", "kv", "count"],
+        ["data.db", "replay", "session.json"],
+    );
+}